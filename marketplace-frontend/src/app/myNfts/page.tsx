@@ -4,8 +4,9 @@ import { useEffect, useState } from "react";
 import { useAppKitAccount, useAppKitProvider } from "@reown/appkit/react";
 import { createUmi } from "@metaplex-foundation/umi-bundle-defaults";
 import { fetchAllDigitalAssetByOwner, TokenStandard } from "@metaplex-foundation/mpl-token-metadata";
-import { publicKey, Umi } from "@metaplex-foundation/umi";
+import { publicKey, Umi, unwrapOption } from "@metaplex-foundation/umi";
 import { NFTCard } from "@/src/components/NFTCard";
+import { useBackendAuth } from "@/src/hooks/useBackendAuth";
 import { DigitalAsset } from '@metaplex-foundation/mpl-token-metadata';
 import { useAppKitConnection, WalletAdapter, type Provider } from '@reown/appkit-adapter-solana/react';
 import { burnV1, transferV1 } from '@metaplex-foundation/mpl-token-metadata';
@@ -31,6 +32,7 @@ const SkeletonCard = () => (
 export default function MarketplacePage() {
     const { walletProvider } = useAppKitProvider<Provider>('solana');
     const walletPublicKey = walletProvider?.publicKey;
+    const { authFetch } = useBackendAuth();
     const [myNFTs, setMyNFTs] = useState<DigitalAsset[]>([]);
     const [loading, setLoading] = useState(true);
     const [transferring, setTransferring] = useState(false);
@@ -92,6 +94,16 @@ export default function MarketplacePage() {
         const sellerTokenAccount = await getAssociatedTokenAddress(mint, walletPublicKey);
         const escrowTokenAccount = await getAssociatedTokenAddress(mint, pda, true);
 
+        // Mints of a verified collection list against that collection's stats,
+        // which the first listing creates
+        const collection = unwrapOption(nft.metadata.collection);
+        const collectionStats = collection?.verified
+            ? PublicKey.findProgramAddressSync(
+                [Buffer.from("collection_stats"), new PublicKey(collection.key).toBuffer()],
+                program.programId
+            )[0]
+            : null;
+
         console.log("Listing NFT with price:", priceSol, "SOL");
 
         const tx = await program.methods
@@ -103,6 +115,10 @@ export default function MarketplacePage() {
                 sellerTokenAccount,
                 escrowTokenAccount,
                 listing: listingPDA,
+                metadataAccount: new PublicKey(nft.metadata.publicKey.toString()),
+                collectionStats,
+                collectionAllowlist: PublicKey.findProgramAddressSync([Buffer.from("collection_allowlist")], program.programId)[0],
+                admins: PublicKey.findProgramAddressSync([Buffer.from("admins")], program.programId)[0],
                 tokenProgram: TOKEN_PROGRAM_ID,
                 associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
                 systemProgram: SystemProgram.programId,
@@ -157,9 +173,9 @@ export default function MarketplacePage() {
                 );
             }
 
-            // Update backend with listing information
-            const response = await fetch("http://127.0.0.1:8000/update_nft_listing_status", {
-                method: "PUT",
+            // The backend stores the listing once it finds it on-chain
+            const response = await authFetch("/list_nft", {
+                method: "POST",
                 headers: {
                     "Content-Type": "application/json",
                 },
@@ -167,12 +183,10 @@ export default function MarketplacePage() {
                     mint_address: nft.mint.publicKey.toString(),
                     name: nft.metadata.name,
                     symbol: nft.metadata.symbol,
-                    price: price,
+                    price: Math.round(price * web3.LAMPORTS_PER_SOL),
                     image_uri: imageUrl || "",
                     metadata_uri: metadataUri,
-                    is_listed: true,
                     owner: walletPublicKey?.toString(),
-                    token_standard: nft.metadata.tokenStandard || "NonFungible",
                 }),
             });
 
@@ -270,14 +284,11 @@ export default function MarketplacePage() {
             ));
 
             try {
-                await fetch("http://127.0.0.1:8000/update_nft_listing_status", {
+                // The backend reads the new owner from chain
+                await authFetch("/update_nft_listing_status", {
                     method: "PUT",
                     headers: { "Content-Type": "application/json" },
-                    body: JSON.stringify({
-                        mint_address: nft.mint.publicKey.toString(),
-                        is_listed: false,
-                        new_owner: recipientPublicKey.toString(),
-                    }),
+                    body: JSON.stringify({ mint_address: nft.mint.publicKey.toString() }),
                 });
             } catch (err) {
                 console.error("Failed to update backend owner after transfer:", err);
@@ -338,7 +349,7 @@ export default function MarketplacePage() {
             console.log("Burning NFT:", nft.mint.publicKey.toString());
 
             try {
-                await fetch(`http://127.0.0.1:8000/delete_nft/${nft.mint.publicKey.toString()}`, {
+                await authFetch(`/delete_nft/${nft.mint.publicKey.toString()}`, {
                     method: "DELETE",
                 });
             } catch (err) {
@@ -4,11 +4,12 @@ import Layout from '../components/Layout';
 import { ConnectButton } from "../components/ConnectButton";
 import { useEffect, useState } from 'react';
 import { createUmi } from '@metaplex-foundation/umi-bundle-defaults';
-import { DigitalAsset, transferV1, TokenStandard ,fetchAllDigitalAssetByUpdateAuthority, mplTokenMetadata } from '@metaplex-foundation/mpl-token-metadata';
-import { publicKey } from '@metaplex-foundation/umi';
+import { DigitalAsset, transferV1, TokenStandard ,fetchAllDigitalAssetByUpdateAuthority, fetchMetadataFromSeeds, mplTokenMetadata } from '@metaplex-foundation/mpl-token-metadata';
+import { publicKey, unwrapOption } from '@metaplex-foundation/umi';
 import { useAppKitAccount, useAppKitProvider, useWalletInfo } from '@reown/appkit/react';
 import { PublicKey, Transaction, SystemProgram, LAMPORTS_PER_SOL, Connection } from '@solana/web3.js';
 import { ListedNFTCard } from "@/src/components/ListedNFTCard";
+import { useBackendAuth } from "@/src/hooks/useBackendAuth";
 import type { Provider } from "@reown/appkit-adapter-solana/react";
 import { walletAdapterIdentity } from '@metaplex-foundation/umi-signer-wallet-adapters';
 import {
@@ -30,7 +31,9 @@ interface ListedNFT {
   mint_address: string;
   name: string;
   symbol: string;
-  price: number;
+  price: number; // lamports
+  display_price: number; // SOL
+  payment_mint: string;
   image_uri: string;
   metadata_uri: string;
   is_listed: boolean;
@@ -46,9 +49,11 @@ const SkeletonCard: React.FC = () => (
   </div>
 );
 const PROGRAM_ID = new PublicKey(process.env.program_id || "8kU8YRPEr9SYYfr37iEb7PDLTARq2yuWr2kL7emyzYAk");
+const TOKEN_METADATA_PROGRAM_ID = new PublicKey("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
 
 export default function Home() {
 const { walletProvider } = useAppKitProvider<Provider>("solana");
+  const { authFetch } = useBackendAuth();
     
   const [isClient, setIsClient] = useState(false);
   const { isConnected, address } = useAppKitAccount();
@@ -72,7 +77,7 @@ const { walletProvider } = useAppKitProvider<Provider>("solana");
       if (!res.ok) throw new Error(`HTTP error! status: ${res.status}`);
       const data = await res.json();
       console.log("MongoDB Listed NFTs:", data);
-      return data;
+      return data.nfts;
     } catch (error) {
       console.error('Error fetching MongoDB NFTs:', error);
       return [];
@@ -127,7 +132,7 @@ async function handleBuy(nft: ListedNFT) {
         const buyer = new PublicKey(address);
         const seller = new PublicKey(nft.owner);
         const mint = new PublicKey(nft.mint_address);
-        const priceLamports = nft.price * web3.LAMPORTS_PER_SOL;
+        const priceLamports = nft.price;
 
         // Get PDA for escrow authority
         const [pda, bump] = PublicKey.findProgramAddressSync(
@@ -163,12 +168,33 @@ async function handleBuy(nft: ListedNFT) {
 
         const program = new Program(idl as any, provider);
 
+        // The marketplace fee goes to the treasury configured on-chain
+        const [marketplace] = PublicKey.findProgramAddressSync(
+            [Buffer.from("marketplace")],
+            PROGRAM_ID
+        );
+        const { treasury } = await (program.account as any).marketplace.fetch(marketplace);
+
+        // Mints of a verified collection also update that collection's stats
+        const metadata = await fetchMetadataFromSeeds(umi, { mint: publicKey(nft.mint_address) });
+        const collection = unwrapOption(metadata.collection);
+        const collectionStats = collection?.verified
+            ? PublicKey.findProgramAddressSync(
+                [Buffer.from("collection_stats"), new PublicKey(collection.key).toBuffer()],
+                PROGRAM_ID
+            )[0]
+            : null;
+
         // Build buy_nft instruction
         const buyInstruction = await program.methods
             .buyNft(bump)
             .accounts({
                 buyer,
                 seller,
+                proceedsVault: null,
+                marketplace,
+                treasury,
+                referrer: null,
                 mint,
                 buyerTokenAccount,
                 escrowTokenAccount,
@@ -177,6 +203,15 @@ async function handleBuy(nft: ListedNFT) {
                     [Buffer.from("listing"), mint.toBuffer()],
                     PROGRAM_ID
                 )[0],
+                metadataAccount: PublicKey.findProgramAddressSync(
+                    [Buffer.from("metadata"), TOKEN_METADATA_PROGRAM_ID.toBuffer(), mint.toBuffer()],
+                    TOKEN_METADATA_PROGRAM_ID
+                )[0],
+                collectionStats,
+                admins: PublicKey.findProgramAddressSync(
+                    [Buffer.from("admins")],
+                    PROGRAM_ID
+                )[0],
                 tokenProgram: TOKEN_PROGRAM_ID,
                 associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
                 systemProgram: SystemProgram.programId,
@@ -190,15 +225,13 @@ async function handleBuy(nft: ListedNFT) {
         const txSig = await walletProvider.sendTransaction(transaction, connection);
         console.log("✅ buy_nft tx confirmed:", txSig);
 
-        // Update backend
-        const res = await fetch("http://127.0.0.1:8000/update_nft_listing_status", {
+        await connection.confirmTransaction(txSig, "confirmed");
+
+        // The backend reads the new owner from chain
+        const res = await authFetch("/update_nft_listing_status", {
             method: "PUT",
             headers: { "Content-Type": "application/json" },
-            body: JSON.stringify({
-                mint_address: nft.mint_address,
-                is_listed: false,
-                new_owner: address,
-            }),
+            body: JSON.stringify({ mint_address: nft.mint_address }),
         });
 
         if (!res.ok) throw new Error("Failed to update NFT listing status");
@@ -208,7 +241,7 @@ async function handleBuy(nft: ListedNFT) {
             prev.filter((listedNft) => listedNft.mint_address !== nft.mint_address)
         );
 
-        alert(`✅ Successfully bought "${nft.name}" for ${nft.price} SOL`);
+        alert(`✅ Successfully bought "${nft.name}" for ${nft.display_price} SOL`);
     } catch (error: any) {
         console.error("Buy error:", error);
         alert(`❌ Failed to buy NFT: ${error.message}`);
@@ -244,7 +277,7 @@ async function handleBuy(nft: ListedNFT) {
               {listedNFTs.map((nft, idx) => (
                 <ListedNFTCard
                   key={idx}
-                  nft={nft}
+                  nft={{ ...nft, price: nft.display_price }}
                   onBuy={() => handleBuy(nft)}
                 />
               ))}
@@ -23,6 +23,7 @@ import {
 } from "@reown/appkit/react";
 import { useAppKitConnection } from "@reown/appkit-adapter-solana/react";
 import type { Provider } from "@reown/appkit-adapter-solana/react";
+import { useBackendAuth } from "../../hooks/useBackendAuth";
 
 const PROGRAM_ID = new PublicKey(process.env.program_id || "8kU8YRPEr9SYYfr37iEb7PDLTARq2yuWr2kL7emyzYAk");
 const TOKEN_METADATA_PROGRAM_ID = new PublicKey("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
@@ -38,6 +39,7 @@ export default function UploadNFTPage() {
     // Wallet connections
     const { walletProvider } = useAppKitProvider<Provider>("solana");
     const { connection } = useAppKitConnection();
+    const { authFetch } = useBackendAuth();
     const { isConnected, address } = useAppKitAccount();
 
     // Form state
@@ -225,6 +227,17 @@ export default function UploadNFTPage() {
                         sellerTokenAccount: sellerTokenAccount,
                         escrowTokenAccount: escrowTokenAccount,
                         listing: listingPda,
+                        metadataAccount: metadataPda,
+                        // Minted without a collection, so there are no collection stats
+                        collectionStats: null,
+                        collectionAllowlist: PublicKey.findProgramAddressSync(
+                            [Buffer.from("collection_allowlist")],
+                            PROGRAM_ID
+                        )[0],
+                        admins: PublicKey.findProgramAddressSync(
+                            [Buffer.from("admins")],
+                            PROGRAM_ID
+                        )[0],
                         tokenProgram: TOKEN_PROGRAM_ID,
                         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
                         systemProgram: SystemProgram.programId,
@@ -233,20 +246,22 @@ export default function UploadNFTPage() {
                     .instruction();
 
                 listingTransaction.add(listingInstruction);
-                await walletProvider.sendTransaction(listingTransaction, connection);
+                const listingSignature = await walletProvider.sendTransaction(listingTransaction, connection);
+                // The backend checks the listing on-chain before storing it
+                await connection.confirmTransaction(listingSignature, "confirmed");
 
                 // Update backend database
                 const nftData = {
                     mint_address: mintKeypair.publicKey.toBase58(),
                     name,
                     symbol,
-                    price,
+                    price: priceInLamports,
                     image_uri: uploadResult.imageUri,
                     metadata_uri: uploadResult.metadataUri,
                     owner: address,
                 };
 
-                await fetch("http://127.0.0.1:8000/list_nft", {
+                await authFetch("/list_nft", {
                     method: "POST",
                     headers: {
                         "Content-Type": "application/json",
@@ -0,0 +1,73 @@
+'use client'
+import { useCallback } from "react";
+import { utils } from "@coral-xyz/anchor";
+import { useAppKitAccount, useAppKitProvider } from "@reown/appkit/react";
+import type { Provider } from "@reown/appkit-adapter-solana/react";
+
+export const BACKEND_URL = "http://127.0.0.1:8000";
+
+interface BackendSession {
+    token: string;
+    expires_at: number; // unix seconds
+}
+
+const sessionKey = (wallet: string) => `backend-session:${wallet}`;
+
+// Sign in again a minute before the session runs out
+const EXPIRY_MARGIN_SECS = 60;
+
+function storedSession(wallet: string): BackendSession | null {
+    const stored = sessionStorage.getItem(sessionKey(wallet));
+    if (!stored) return null;
+
+    const session: BackendSession = JSON.parse(stored);
+    return session.expires_at > Date.now() / 1000 + EXPIRY_MARGIN_SECS ? session : null;
+}
+
+// Signs in to the backend with the connected wallet and sends its session
+// token with each request. The wallet is asked to sign once per session.
+export function useBackendAuth() {
+    const { walletProvider } = useAppKitProvider<Provider>("solana");
+    const { address } = useAppKitAccount();
+
+    const signIn = useCallback(async (): Promise<string> => {
+        if (!walletProvider || !address) throw new Error("Wallet not connected");
+
+        const cached = storedSession(address);
+        if (cached) return cached.token;
+
+        const nonceRes = await fetch(`${BACKEND_URL}/auth/nonce/${address}`);
+        if (!nonceRes.ok) throw new Error(`Failed to get sign-in nonce: ${nonceRes.status}`);
+        const { nonce, message } = await nonceRes.json();
+
+        const signature = await walletProvider.signMessage(new TextEncoder().encode(message));
+
+        const verifyRes = await fetch(`${BACKEND_URL}/auth/verify`, {
+            method: "POST",
+            headers: { "Content-Type": "application/json" },
+            body: JSON.stringify({
+                wallet: address,
+                nonce,
+                signature: utils.bytes.bs58.encode(signature),
+            }),
+        });
+        if (!verifyRes.ok) throw new Error(`Sign-in failed: ${verifyRes.status}`);
+        const { token, expires_at } = await verifyRes.json();
+
+        sessionStorage.setItem(sessionKey(address), JSON.stringify({ token, expires_at }));
+        return token;
+    }, [walletProvider, address]);
+
+    const authFetch = useCallback(async (path: string, init: RequestInit = {}) => {
+        const token = await signIn();
+        const headers = new Headers(init.headers);
+        headers.set("Authorization", `Bearer ${token}`);
+
+        const res = await fetch(`${BACKEND_URL}${path}`, { ...init, headers });
+        // Sessions can be revoked or expire server side, sign in again next time
+        if (res.status === 401 && address) sessionStorage.removeItem(sessionKey(address));
+        return res;
+    }, [signIn, address]);
+
+    return { authFetch };
+}
@@ -0,0 +1,268 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Parser, Subcommand};
+use mpl_token_metadata::accounts::Metadata;
+use nft_marketplace_client::{
+    accounts::{decode_listing, decode_marketplace},
+    instructions::{BuyNft, DelistNft, ListNft, MintNft, Payout, UpdatePrice},
+    pda, Listing,
+};
+use serde_json::{json, Value};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair, Signer},
+    transaction::Transaction,
+};
+
+// Same cluster and wallet as the [provider] section of Anchor.toml
+const DEFAULT_RPC_URL: &str = "https://api.devnet.solana.com";
+const DEFAULT_KEYPAIR: &str = "~/.config/solana/id.json";
+
+#[derive(Parser)]
+#[command(name = "nft-marketplace-cli", version, about = "Operate the nft_marketplace program")]
+struct Cli {
+    /// RPC URL of the cluster to talk to
+    #[arg(long, short = 'u', global = true, default_value = DEFAULT_RPC_URL)]
+    url: String,
+
+    /// Keypair that signs and pays for transactions
+    #[arg(long, short = 'k', global = true, default_value = DEFAULT_KEYPAIR)]
+    keypair: String,
+
+    /// Print results as a single line of JSON
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Mint a new NFT to the keypair's wallet
+    Mint {
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        symbol: String,
+        #[arg(long)]
+        uri: String,
+    },
+    /// List an NFT held by the keypair's wallet
+    List {
+        #[arg(long)]
+        mint: Pubkey,
+        /// Price in lamports
+        #[arg(long)]
+        price: u64,
+    },
+    /// Change the price of an active listing
+    UpdatePrice {
+        #[arg(long)]
+        mint: Pubkey,
+        /// New price in lamports
+        #[arg(long)]
+        price: u64,
+    },
+    /// Buy a listed NFT
+    Buy {
+        #[arg(long)]
+        mint: Pubkey,
+        /// Storefront that referred the purchase
+        #[arg(long)]
+        referrer: Option<Pubkey>,
+        /// Pay the seller's proceeds vault instead of their wallet
+        #[arg(long)]
+        to_vault: bool,
+    },
+    /// Delist an NFT and return it to the keypair's wallet
+    Delist {
+        #[arg(long)]
+        mint: Pubkey,
+    },
+    /// Show the listing for a mint
+    Listing {
+        #[arg(long)]
+        mint: Pubkey,
+    },
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let json = cli.json;
+
+    match run(cli) {
+        Ok(output) => print_output(&output, json),
+        Err(e) => {
+            print_output(&json!({ "status": "error", "message": format!("{:#}", e) }), json);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<Value> {
+    let rpc = RpcClient::new_with_commitment(cli.url, CommitmentConfig::confirmed());
+
+    if let Command::Listing { mint } = cli.command {
+        return listing_json(&mint, &fetch_listing(&rpc, &mint)?);
+    }
+
+    let payer = read_keypair_file(expand_home(&cli.keypair))
+        .map_err(|e| anyhow!("failed to read keypair {}: {}", cli.keypair, e))?;
+    let wallet = payer.pubkey();
+
+    match cli.command {
+        Command::Mint { name, symbol, uri } => {
+            let mint = Keypair::new();
+            let ix = MintNft {
+                signer: wallet,
+                mint: mint.pubkey(),
+                name,
+                symbol,
+                uri,
+            }
+            .instruction();
+            let signature = send(&rpc, ix, &[&payer, &mint])?;
+            Ok(json!({ "status": "success", "signature": signature, "mint": mint.pubkey().to_string() }))
+        }
+        Command::List { mint, price } => {
+            let ix = ListNft {
+                seller: wallet,
+                mint,
+                price,
+                collection: verified_collection(&rpc, &mint)?,
+            }
+            .instruction();
+            let signature = send(&rpc, ix, &[&payer])?;
+            Ok(json!({ "status": "success", "signature": signature, "listing": pda::listing(&mint).0.to_string() }))
+        }
+        Command::UpdatePrice { mint, price } => {
+            let ix = UpdatePrice {
+                seller: wallet,
+                mint,
+                new_price: price,
+                collection: verified_collection(&rpc, &mint)?,
+            }
+            .instruction();
+            let signature = send(&rpc, ix, &[&payer])?;
+            Ok(json!({ "status": "success", "signature": signature, "price": price }))
+        }
+        Command::Buy {
+            mint,
+            referrer,
+            to_vault,
+        } => {
+            let listing = fetch_listing(&rpc, &mint)?;
+            let marketplace_data = rpc
+                .get_account_data(&pda::marketplace().0)
+                .context("marketplace has not been initialized")?;
+            let marketplace = decode_marketplace(&marketplace_data)?;
+
+            let ix = BuyNft {
+                buyer: wallet,
+                seller: listing.seller,
+                mint,
+                treasury: marketplace.treasury,
+                payout: if to_vault {
+                    Payout::ProceedsVault
+                } else {
+                    Payout::Wallet
+                },
+                referrer,
+                collection: verified_collection(&rpc, &mint)?,
+            }
+            .instruction();
+            let signature = send(&rpc, ix, &[&payer])?;
+            Ok(json!({ "status": "success", "signature": signature, "price": listing.price }))
+        }
+        Command::Delist { mint } => {
+            let ix = DelistNft {
+                seller: wallet,
+                mint,
+                collection: verified_collection(&rpc, &mint)?,
+            }
+            .instruction();
+            let signature = send(&rpc, ix, &[&payer])?;
+            Ok(json!({ "status": "success", "signature": signature }))
+        }
+        Command::Listing { .. } => unreachable!("handled before loading the keypair"),
+    }
+}
+
+fn send(rpc: &RpcClient, ix: Instruction, signers: &[&Keypair]) -> Result<String> {
+    let blockhash = rpc.get_latest_blockhash()?;
+    let tx = Transaction::new_signed_with_payer(&[ix], Some(&signers[0].pubkey()), signers, blockhash);
+    let signature = rpc
+        .send_and_confirm_transaction(&tx)
+        .context("transaction failed")?;
+    Ok(signature.to_string())
+}
+
+fn fetch_listing(rpc: &RpcClient, mint: &Pubkey) -> Result<Listing> {
+    let address = pda::listing(mint).0;
+    let data = rpc
+        .get_account_data(&address)
+        .with_context(|| format!("no listing found for mint {}", mint))?;
+    decode_listing(&data).map_err(|e| anyhow!("failed to decode listing {}: {}", address, e))
+}
+
+/// Verified Metaplex collection of `mint`, needed to pass the right
+/// collection stats account.
+fn verified_collection(rpc: &RpcClient, mint: &Pubkey) -> Result<Option<Pubkey>> {
+    let address = pda::metadata(mint);
+    let Some(account) = rpc
+        .get_account_with_commitment(&address, rpc.commitment())
+        .with_context(|| format!("failed to fetch metadata {}", address))?
+        .value
+    else {
+        return Ok(None);
+    };
+    let metadata = Metadata::safe_deserialize(&account.data)
+        .map_err(|e| anyhow!("failed to decode metadata for {}: {}", mint, e))?;
+    Ok(metadata
+        .collection
+        .filter(|collection| collection.verified)
+        .map(|collection| collection.key))
+}
+
+fn listing_json(mint: &Pubkey, listing: &Listing) -> Result<Value> {
+    if listing.mint != *mint {
+        bail!("listing account does not belong to mint {}", mint);
+    }
+    Ok(json!({
+        "address": pda::listing(mint).0.to_string(),
+        "seller": listing.seller.to_string(),
+        "mint": listing.mint.to_string(),
+        "price": listing.price,
+        "is_active": listing.is_active,
+        "version": listing.version,
+    }))
+}
+
+fn print_output(output: &Value, json: bool) {
+    if json {
+        println!("{}", output);
+        return;
+    }
+
+    match output.as_object() {
+        Some(fields) => {
+            for (key, value) in fields {
+                match value {
+                    Value::String(s) => println!("{}: {}", key, s),
+                    other => println!("{}: {}", key, other),
+                }
+            }
+        }
+        None => println!("{}", output),
+    }
+}
+
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home, rest),
+        _ => path.to_string(),
+    }
+}
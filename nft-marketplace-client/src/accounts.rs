@@ -0,0 +1,40 @@
+use anchor_lang::{AccountDeserialize, Result};
+use nft_marketplace::{
+    Admins, CollectionAllowlist, CollectionStats, Listing, Marketplace, ProceedsVault, Proposal,
+    Rental,
+};
+
+/// Decodes raw account data, including the 8 byte discriminator, as a
+/// `Listing`. Listings that predate versioning fail to decode until
+/// `migrate_listing` has been run on them.
+pub fn decode_listing(data: &[u8]) -> Result<Listing> {
+    Listing::try_deserialize(&mut &data[..])
+}
+
+pub fn decode_marketplace(data: &[u8]) -> Result<Marketplace> {
+    Marketplace::try_deserialize(&mut &data[..])
+}
+
+pub fn decode_collection_stats(data: &[u8]) -> Result<CollectionStats> {
+    CollectionStats::try_deserialize(&mut &data[..])
+}
+
+pub fn decode_collection_allowlist(data: &[u8]) -> Result<CollectionAllowlist> {
+    CollectionAllowlist::try_deserialize(&mut &data[..])
+}
+
+pub fn decode_admins(data: &[u8]) -> Result<Admins> {
+    Admins::try_deserialize(&mut &data[..])
+}
+
+pub fn decode_proposal(data: &[u8]) -> Result<Proposal> {
+    Proposal::try_deserialize(&mut &data[..])
+}
+
+pub fn decode_proceeds_vault(data: &[u8]) -> Result<ProceedsVault> {
+    ProceedsVault::try_deserialize(&mut &data[..])
+}
+
+pub fn decode_rental(data: &[u8]) -> Result<Rental> {
+    Rental::try_deserialize(&mut &data[..])
+}
@@ -0,0 +1,368 @@
+use anchor_lang::{
+    prelude::Pubkey,
+    solana_program::{
+        instruction::{AccountMeta, Instruction},
+        system_program, sysvar,
+    },
+    InstructionData, ToAccountMetas,
+};
+use anchor_spl::{associated_token::get_associated_token_address, metadata::ID as METADATA_ID};
+use nft_marketplace::{accounts, instruction};
+
+use crate::pda;
+
+/// Where a sale's proceeds are paid.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Payout {
+    /// Directly to the seller's wallet.
+    #[default]
+    Wallet,
+    /// Into the seller's `ProceedsVault`, which must already exist.
+    ProceedsVault,
+}
+
+fn build(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: nft_marketplace::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+/// Mints a new NFT to `signer`. `mint` must also sign the transaction.
+#[derive(Clone, Debug)]
+pub struct MintNft {
+    pub signer: Pubkey,
+    pub mint: Pubkey,
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+}
+
+impl MintNft {
+    pub fn instruction(&self) -> Instruction {
+        let (authority, bump) = pda::authority();
+        build(
+            accounts::MintNFT {
+                signer: self.signer,
+                mint: self.mint,
+                pda: authority,
+                associated_token_account: get_associated_token_address(&self.signer, &self.mint),
+                metadata_account: pda::metadata(&self.mint),
+                master_edition_account: pda::master_edition(&self.mint),
+                token_program: anchor_spl::token::ID,
+                associated_token_program: anchor_spl::associated_token::ID,
+                token_metadata_program: METADATA_ID,
+                system_program: system_program::ID,
+                rent: sysvar::rent::ID,
+            },
+            instruction::MintNft {
+                name: self.name.clone(),
+                symbol: self.symbol.clone(),
+                uri: self.uri.clone(),
+                bump,
+            },
+        )
+    }
+}
+
+/// Moves `mint` from the seller's associated token account into escrow and
+/// creates its listing. `collection` is the mint's verified collection, if any.
+#[derive(Clone, Debug)]
+pub struct ListNft {
+    pub seller: Pubkey,
+    pub mint: Pubkey,
+    pub price: u64,
+    pub collection: Option<Pubkey>,
+}
+
+impl ListNft {
+    pub fn instruction(&self) -> Instruction {
+        let (authority, bump) = pda::authority();
+        build(
+            accounts::ListNFT {
+                seller: self.seller,
+                mint: self.mint,
+                pda: authority,
+                seller_token_account: get_associated_token_address(&self.seller, &self.mint),
+                escrow_token_account: pda::escrow_token_account(&self.mint),
+                listing: pda::listing(&self.mint).0,
+                metadata_account: pda::metadata(&self.mint),
+                collection_stats: collection_stats(self.collection),
+                collection_allowlist: pda::collection_allowlist().0,
+                admins: pda::admins().0,
+                token_program: anchor_spl::token::ID,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: system_program::ID,
+                rent: sysvar::rent::ID,
+            },
+            instruction::ListNft {
+                price: self.price,
+                _bump: bump,
+            },
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UpdatePrice {
+    pub seller: Pubkey,
+    pub mint: Pubkey,
+    pub new_price: u64,
+    pub collection: Option<Pubkey>,
+}
+
+impl UpdatePrice {
+    pub fn instruction(&self) -> Instruction {
+        build(
+            accounts::UpdatePrice {
+                seller: self.seller,
+                mint: self.mint,
+                listing: pda::listing(&self.mint).0,
+                metadata_account: pda::metadata(&self.mint),
+                collection_stats: collection_stats(self.collection),
+            },
+            instruction::UpdatePrice {
+                new_price: self.new_price,
+            },
+        )
+    }
+}
+
+/// Buys the active listing for `mint`. `treasury` must match the one stored
+/// in the `Marketplace` account.
+#[derive(Clone, Debug)]
+pub struct BuyNft {
+    pub buyer: Pubkey,
+    pub seller: Pubkey,
+    pub mint: Pubkey,
+    pub treasury: Pubkey,
+    pub payout: Payout,
+    pub referrer: Option<Pubkey>,
+    pub collection: Option<Pubkey>,
+}
+
+impl BuyNft {
+    pub fn instruction(&self) -> Instruction {
+        let (authority, bump) = pda::authority();
+        let (seller, proceeds_vault) = match self.payout {
+            Payout::Wallet => (Some(self.seller), None),
+            Payout::ProceedsVault => (None, Some(pda::proceeds_vault(&self.seller).0)),
+        };
+        build(
+            accounts::BuyNFT {
+                buyer: self.buyer,
+                seller,
+                proceeds_vault,
+                marketplace: pda::marketplace().0,
+                treasury: self.treasury,
+                referrer: self.referrer,
+                mint: self.mint,
+                pda: authority,
+                escrow_token_account: pda::escrow_token_account(&self.mint),
+                buyer_token_account: get_associated_token_address(&self.buyer, &self.mint),
+                listing: pda::listing(&self.mint).0,
+                metadata_account: pda::metadata(&self.mint),
+                collection_stats: collection_stats(self.collection),
+                admins: pda::admins().0,
+                token_program: anchor_spl::token::ID,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: system_program::ID,
+                rent: sysvar::rent::ID,
+            },
+            instruction::BuyNft { bump },
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DelistNft {
+    pub seller: Pubkey,
+    pub mint: Pubkey,
+    pub collection: Option<Pubkey>,
+}
+
+impl DelistNft {
+    pub fn instruction(&self) -> Instruction {
+        let (authority, bump) = pda::authority();
+        build(
+            accounts::DelistNFT {
+                seller: self.seller,
+                mint: self.mint,
+                pda: authority,
+                escrow_token_account: pda::escrow_token_account(&self.mint),
+                seller_token_account: get_associated_token_address(&self.seller, &self.mint),
+                listing: pda::listing(&self.mint).0,
+                metadata_account: pda::metadata(&self.mint),
+                collection_stats: collection_stats(self.collection),
+                token_program: anchor_spl::token::ID,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: system_program::ID,
+            },
+            instruction::DelistNft { bump },
+        )
+    }
+}
+
+/// One listing swept by `BuyMany`.
+#[derive(Clone, Debug)]
+pub struct SweepListing {
+    pub mint: Pubkey,
+    pub seller: Pubkey,
+    /// Price the buyer saw, the sweep fails if the listing was repriced
+    pub expected_price: u64,
+    pub payout: Payout,
+    pub collection: Option<Pubkey>,
+}
+
+/// Buys several listings in one instruction, spending at most `max_total`
+/// lamports on them. With `skip_sold`, listings that are gone or were relisted
+/// with another seller or price by the time the transaction lands are skipped
+/// instead of failing it.
+#[derive(Clone, Debug)]
+pub struct BuyMany {
+    pub buyer: Pubkey,
+    pub treasury: Pubkey,
+    pub referrer: Option<Pubkey>,
+    pub listings: Vec<SweepListing>,
+    pub max_total: u64,
+    pub skip_sold: bool,
+}
+
+impl BuyMany {
+    pub fn instruction(&self) -> Instruction {
+        let mut ix = build(
+            accounts::BuyMany {
+                buyer: self.buyer,
+                marketplace: pda::marketplace().0,
+                treasury: self.treasury,
+                referrer: self.referrer,
+                pda: pda::authority().0,
+                admins: pda::admins().0,
+                token_program: anchor_spl::token::ID,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: system_program::ID,
+            },
+            instruction::BuyMany {
+                expected_prices: self
+                    .listings
+                    .iter()
+                    .map(|listing| listing.expected_price)
+                    .collect(),
+                max_total: self.max_total,
+                skip_sold: self.skip_sold,
+            },
+        );
+        for listing in &self.listings {
+            let payout = match listing.payout {
+                Payout::Wallet => listing.seller,
+                Payout::ProceedsVault => pda::proceeds_vault(&listing.seller).0,
+            };
+            ix.accounts.extend([
+                AccountMeta::new_readonly(listing.mint, false),
+                AccountMeta::new(pda::escrow_token_account(&listing.mint), false),
+                AccountMeta::new(
+                    get_associated_token_address(&self.buyer, &listing.mint),
+                    false,
+                ),
+                AccountMeta::new(pda::listing(&listing.mint).0, false),
+                AccountMeta::new(payout, false),
+                AccountMeta::new_readonly(pda::metadata(&listing.mint), false),
+                collection_stats_meta(listing.collection),
+            ]);
+        }
+        ix
+    }
+}
+
+/// One NFT in a `BatchListNft`.
+#[derive(Clone, Debug)]
+pub struct BatchListing {
+    pub mint: Pubkey,
+    pub price: u64,
+    pub collection: Option<Pubkey>,
+}
+
+/// Lists several of the seller's NFTs in one instruction.
+#[derive(Clone, Debug)]
+pub struct BatchListNft {
+    pub seller: Pubkey,
+    pub listings: Vec<BatchListing>,
+}
+
+impl BatchListNft {
+    pub fn instruction(&self) -> Instruction {
+        let mut ix = build(
+            accounts::BatchListNFT {
+                seller: self.seller,
+                pda: pda::authority().0,
+                collection_allowlist: pda::collection_allowlist().0,
+                admins: pda::admins().0,
+                token_program: anchor_spl::token::ID,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: system_program::ID,
+            },
+            instruction::BatchList {
+                prices: self.listings.iter().map(|listing| listing.price).collect(),
+            },
+        );
+        for listing in &self.listings {
+            ix.accounts
+                .extend(batch_group(&self.seller, &listing.mint, listing.collection));
+        }
+        ix
+    }
+}
+
+/// Delists several of the seller's NFTs in one instruction. `listings` holds
+/// each mint with its verified collection, if any.
+#[derive(Clone, Debug)]
+pub struct BatchDelistNft {
+    pub seller: Pubkey,
+    pub listings: Vec<(Pubkey, Option<Pubkey>)>,
+}
+
+impl BatchDelistNft {
+    pub fn instruction(&self) -> Instruction {
+        let mut ix = build(
+            accounts::BatchDelistNFT {
+                seller: self.seller,
+                pda: pda::authority().0,
+                token_program: anchor_spl::token::ID,
+            },
+            instruction::BatchDelist {
+                count: self.listings.len() as u8,
+            },
+        );
+        for (mint, collection) in &self.listings {
+            ix.accounts
+                .extend(batch_group(&self.seller, mint, *collection));
+        }
+        ix
+    }
+}
+
+/// Remaining accounts for one NFT, in the order the program expects them.
+fn batch_group(seller: &Pubkey, mint: &Pubkey, collection: Option<Pubkey>) -> [AccountMeta; 6] {
+    [
+        AccountMeta::new_readonly(*mint, false),
+        AccountMeta::new(get_associated_token_address(seller, mint), false),
+        AccountMeta::new(pda::escrow_token_account(mint), false),
+        AccountMeta::new(pda::listing(mint).0, false),
+        AccountMeta::new_readonly(pda::metadata(mint), false),
+        collection_stats_meta(collection),
+    ]
+}
+
+/// Collection stats passed as a remaining account, where the program ID
+/// stands for none.
+fn collection_stats_meta(collection: Option<Pubkey>) -> AccountMeta {
+    match collection_stats(collection) {
+        Some(stats) => AccountMeta::new(stats, false),
+        None => AccountMeta::new_readonly(nft_marketplace::ID, false),
+    }
+}
+
+fn collection_stats(collection: Option<Pubkey>) -> Option<Pubkey> {
+    collection.map(|collection| pda::collection_stats(&collection).0)
+}
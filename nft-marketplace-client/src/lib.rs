@@ -0,0 +1,18 @@
+//! Client helpers for the `nft_marketplace` program: PDA derivation, typed
+//! instruction builders and account decoding.
+//!
+//! ```ignore
+//! use nft_marketplace_client::{instructions::ListNft, pda};
+//!
+//! let ix = ListNft { seller, mint, price: 1_000_000_000, collection: None }.instruction();
+//! let (listing, _) = pda::listing(&mint);
+//! ```
+
+pub mod accounts;
+pub mod instructions;
+pub mod pda;
+
+pub use nft_marketplace::{
+    AdminAction, AdminMember, Admins, CollectionAllowlist, CollectionStats, Listing, Marketplace,
+    ProceedsVault, Proposal, Rental, ID as PROGRAM_ID,
+};
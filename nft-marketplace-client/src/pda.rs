@@ -0,0 +1,80 @@
+use anchor_lang::prelude::Pubkey;
+use anchor_spl::associated_token::get_associated_token_address;
+use mpl_token_metadata::accounts::{MasterEdition, Metadata};
+use nft_marketplace::{
+    ADMINS_SEED, ALLOWLIST_SEED, AUTHORITY_SEED, COLLECTION_STATS_SEED, LISTING_SEED,
+    MARKETPLACE_SEED, ORDER_NONCE_SEED, PROCEEDS_SEED, PROPOSAL_SEED, RENTAL_SEED,
+};
+
+/// Marketplace authority PDA: mint/freeze authority of minted NFTs and owner
+/// of every escrow token account.
+pub fn authority() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[AUTHORITY_SEED], &nft_marketplace::ID)
+}
+
+pub fn listing(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[LISTING_SEED, mint.as_ref()], &nft_marketplace::ID)
+}
+
+/// Program data account of the deployed program, which holds its upgrade
+/// authority.
+pub fn program_data() -> Pubkey {
+    Pubkey::find_program_address(
+        &[nft_marketplace::ID.as_ref()],
+        &anchor_lang::solana_program::bpf_loader_upgradeable::ID,
+    )
+    .0
+}
+
+pub fn marketplace() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[MARKETPLACE_SEED], &nft_marketplace::ID)
+}
+
+pub fn proceeds_vault(seller: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[PROCEEDS_SEED, seller.as_ref()], &nft_marketplace::ID)
+}
+
+pub fn collection_stats(collection_mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[COLLECTION_STATS_SEED, collection_mint.as_ref()],
+        &nft_marketplace::ID,
+    )
+}
+
+pub fn collection_allowlist() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ALLOWLIST_SEED], &nft_marketplace::ID)
+}
+
+pub fn admins() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ADMINS_SEED], &nft_marketplace::ID)
+}
+
+pub fn proposal(id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[PROPOSAL_SEED, &id.to_le_bytes()], &nft_marketplace::ID)
+}
+
+pub fn rental(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[RENTAL_SEED, mint.as_ref()], &nft_marketplace::ID)
+}
+
+pub fn order_nonce(seller: &Pubkey, nonce: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[ORDER_NONCE_SEED, seller.as_ref(), &nonce.to_le_bytes()],
+        &nft_marketplace::ID,
+    )
+}
+
+/// Escrow token account holding a listed NFT.
+pub fn escrow_token_account(mint: &Pubkey) -> Pubkey {
+    get_associated_token_address(&authority().0, mint)
+}
+
+/// Metaplex metadata PDA of `mint`.
+pub fn metadata(mint: &Pubkey) -> Pubkey {
+    Metadata::find_pda(mint).0
+}
+
+/// Metaplex master edition PDA of `mint`.
+pub fn master_edition(mint: &Pubkey) -> Pubkey {
+    MasterEdition::find_pda(mint).0
+}
@@ -1,25 +1,396 @@
 use anchor_lang::prelude::*;
-use anchor_lang::solana_program::{program::invoke, system_instruction};
+use anchor_lang::solana_program::{
+    bpf_loader_upgradeable, ed25519_program,
+    program::invoke,
+    program_option::COption,
+    system_instruction,
+    sysvar::instructions::{
+        self as sysvar_instructions, load_current_index_checked, load_instruction_at_checked,
+    },
+};
+use anchor_lang::system_program::{allocate, assign, create_account, Allocate, Assign, CreateAccount};
 use anchor_spl::{
-    associated_token::AssociatedToken,
+    associated_token::{self, get_associated_token_address, AssociatedToken},
     metadata::{
         create_master_edition_v3, create_metadata_accounts_v3, CreateMasterEditionV3,
         CreateMetadataAccountsV3, Metadata,
     },
-    token::{mint_to, Mint, MintTo, Token, TokenAccount, transfer_checked, TransferChecked},
+    token::{
+        approve, freeze_account, mint_to, thaw_account, transfer_checked, Approve, FreezeAccount,
+        Mint, MintTo, ThawAccount, Token, TokenAccount, TransferChecked,
+    },
 };
 use mpl_token_metadata::types::DataV2;
-use mpl_token_metadata::accounts::{MasterEdition, Metadata as MetadataAccount};
+use mpl_token_metadata::accounts::Metadata as MetadataAccount;
 
 declare_id!("8kU8YRPEr9SYYfr37iEb7PDLTARq2yuWr2kL7emyzYAk");
 
 pub const AUTHORITY_SEED: &[u8] = b"authority";
 pub const LISTING_SEED: &[u8] = b"listing";
+pub const RENTAL_SEED: &[u8] = b"rental";
+pub const MARKETPLACE_SEED: &[u8] = b"marketplace";
+pub const PROCEEDS_SEED: &[u8] = b"proceeds";
+pub const COLLECTION_STATS_SEED: &[u8] = b"collection_stats";
+pub const ORDER_NONCE_SEED: &[u8] = b"order_nonce";
+pub const ALLOWLIST_SEED: &[u8] = b"collection_allowlist";
+pub const ADMINS_SEED: &[u8] = b"admins";
+pub const PROPOSAL_SEED: &[u8] = b"proposal";
+
+pub const ORDER_MESSAGE_PREFIX: &[u8] = b"nft_marketplace:signed_order";
+/// Size of the ed25519 instruction header for a single signature.
+const ED25519_HEADER_LEN: usize = 2 + 14;
+
+pub const MAX_BASIS_POINTS: u16 = 10_000;
+
+/// Capacity of the `CollectionAllowlist`.
+pub const MAX_ALLOWED_COLLECTIONS: usize = 100;
+
+/// Admin roles, combined as bit flags in `AdminMember::roles`.
+pub const ROLE_FEE_MANAGER: u8 = 1 << 0;
+pub const ROLE_PAUSER: u8 = 1 << 1;
+pub const ROLE_ALLOWLIST_CURATOR: u8 = 1 << 2;
+pub const ALL_ROLES: u8 = ROLE_FEE_MANAGER | ROLE_PAUSER | ROLE_ALLOWLIST_CURATOR;
+pub const MAX_ADMINS: usize = 10;
+/// Delay between proposing and executing a marketplace fee increase.
+pub const FEE_INCREASE_TIMELOCK: i64 = 2 * SECONDS_PER_DAY;
+/// Time after which a proposal can no longer be approved or executed.
+pub const PROPOSAL_LIFETIME: i64 = 7 * SECONDS_PER_DAY;
+
+/// Current `Listing` layout version. Unversioned listings are migrated with
+/// `migrate_listing`.
+pub const LISTING_VERSION: u8 = 1;
+/// Bytes kept free at the end of `Listing` for future fields.
+pub const LISTING_RESERVED_BYTES: usize = 64;
+/// Size of listings created before versioning was introduced.
+pub const LEGACY_LISTING_SPACE: usize = 8 + 32 + 32 + 8 + 1 + 1; // discriminator + seller + mint + price + is_active + bump
+
+pub const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Remaining accounts per NFT in `batch_list` and `batch_delist`: mint, seller
+/// token account, escrow token account, listing, metadata and collection stats.
+pub const BATCH_GROUP_LEN: usize = 6;
+/// Remaining accounts per listing in `buy_many`: mint, escrow token account,
+/// buyer token account, listing, payout (seller or proceeds vault), metadata
+/// and collection stats.
+pub const SWEEP_GROUP_LEN: usize = 7;
 
 #[program]
 pub mod nft_marketplace {
     use super::*;
 
+    pub fn initialize_marketplace(
+        ctx: Context<InitializeMarketplace>,
+        fee_bps: u16,
+        referral_bps: u16,
+    ) -> Result<()> {
+        require!(fee_bps <= MAX_BASIS_POINTS, ErrorCode::InvalidBasisPoints);
+        require!(referral_bps <= MAX_BASIS_POINTS, ErrorCode::InvalidBasisPoints);
+
+        let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.authority = ctx.accounts.authority.key();
+        marketplace.treasury = ctx.accounts.treasury.key();
+        marketplace.fee_bps = fee_bps;
+        marketplace.referral_bps = referral_bps;
+        marketplace.bump = ctx.bumps.marketplace;
+
+        Ok(())
+    }
+
+    pub fn update_marketplace(
+        ctx: Context<UpdateMarketplace>,
+        fee_bps: u16,
+        referral_bps: u16,
+    ) -> Result<()> {
+        require!(fee_bps <= MAX_BASIS_POINTS, ErrorCode::InvalidBasisPoints);
+        require!(referral_bps <= MAX_BASIS_POINTS, ErrorCode::InvalidBasisPoints);
+
+        let marketplace = &mut ctx.accounts.marketplace;
+        marketplace.treasury = ctx.accounts.treasury.key();
+        marketplace.fee_bps = fee_bps;
+        marketplace.referral_bps = referral_bps;
+
+        msg!(
+            "Marketplace fee set to {} bps, referral share {} bps",
+            fee_bps,
+            referral_bps
+        );
+
+        Ok(())
+    }
+
+    pub fn init_collection_stats(ctx: Context<InitCollectionStats>) -> Result<()> {
+        let stats = &mut ctx.accounts.collection_stats;
+        stats.collection = ctx.accounts.collection_mint.key();
+        stats.bump = ctx.bumps.collection_stats;
+
+        Ok(())
+    }
+
+    pub fn init_collection_allowlist(
+        ctx: Context<InitCollectionAllowlist>,
+        strict: bool,
+    ) -> Result<()> {
+        ctx.accounts.check_role()?;
+
+        let allowlist = &mut ctx.accounts.collection_allowlist;
+        allowlist.strict = strict;
+        allowlist.bump = ctx.bumps.collection_allowlist;
+
+        Ok(())
+    }
+
+    pub fn set_allowlist_strict(
+        ctx: Context<UpdateCollectionAllowlist>,
+        strict: bool,
+    ) -> Result<()> {
+        ctx.accounts.check_role()?;
+        ctx.accounts.collection_allowlist.strict = strict;
+
+        msg!(
+            "Strict listing {}",
+            if strict { "enabled" } else { "disabled" }
+        );
+
+        Ok(())
+    }
+
+    pub fn add_allowed_collection(
+        ctx: Context<UpdateCollectionAllowlist>,
+        collection: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.check_role()?;
+
+        let allowlist = &mut ctx.accounts.collection_allowlist;
+
+        require!(
+            !allowlist.collections.contains(&collection),
+            ErrorCode::CollectionAlreadyAllowed
+        );
+        require!(
+            allowlist.collections.len() < MAX_ALLOWED_COLLECTIONS,
+            ErrorCode::AllowlistFull
+        );
+        allowlist.collections.push(collection);
+
+        msg!("Collection {} added to the allowlist", collection);
+
+        Ok(())
+    }
+
+    pub fn remove_allowed_collection(
+        ctx: Context<UpdateCollectionAllowlist>,
+        collection: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.check_role()?;
+
+        let allowlist = &mut ctx.accounts.collection_allowlist;
+
+        let index = allowlist
+            .collections
+            .iter()
+            .position(|allowed| *allowed == collection)
+            .ok_or(ErrorCode::CollectionNotOnAllowlist)?;
+        allowlist.collections.swap_remove(index);
+
+        msg!("Collection {} removed from the allowlist", collection);
+
+        Ok(())
+    }
+
+    /// Hands control of the marketplace from its single authority key to an
+    /// `Admins` account. From then on fee and treasury changes go through
+    /// proposals, and pausing and allowlist curation need the matching role.
+    pub fn init_admins(
+        ctx: Context<InitAdmins>,
+        members: Vec<AdminMember>,
+        threshold: u8,
+    ) -> Result<()> {
+        validate_admins(&members, threshold)?;
+
+        let admins = &mut ctx.accounts.admins;
+        admins.members = members;
+        admins.threshold = threshold;
+        admins.paused = false;
+        admins.proposal_count = 0;
+        admins.members_version = 0;
+        admins.bump = ctx.bumps.admins;
+
+        // The PDA cannot sign, which retires `update_marketplace`
+        ctx.accounts.marketplace.authority = admins.key();
+
+        msg!(
+            "Marketplace handed to {} admins, threshold {}",
+            admins.members.len(),
+            threshold
+        );
+
+        Ok(())
+    }
+
+    pub fn propose_admin_action(
+        ctx: Context<ProposeAdminAction>,
+        action: AdminAction,
+    ) -> Result<()> {
+        let admins = &mut ctx.accounts.admins;
+        let proposer = ctx.accounts.proposer.key();
+
+        let role = match &action {
+            AdminAction::UpdateFees {
+                fee_bps,
+                referral_bps,
+            } => {
+                require!(*fee_bps <= MAX_BASIS_POINTS, ErrorCode::InvalidBasisPoints);
+                require!(
+                    *referral_bps <= MAX_BASIS_POINTS,
+                    ErrorCode::InvalidBasisPoints
+                );
+                ROLE_FEE_MANAGER
+            }
+            AdminAction::SetTreasury { .. } => ROLE_FEE_MANAGER,
+            AdminAction::SetMembers { members, threshold } => {
+                validate_admins(members, *threshold)?;
+                0
+            }
+        };
+        require!(admins.is_member(&proposer), ErrorCode::NotAdmin);
+        require!(admins.has_role(&proposer, role), ErrorCode::MissingRole);
+
+        let proposal = &mut ctx.accounts.proposal;
+        proposal.id = admins.proposal_count;
+        proposal.proposer = proposer;
+        proposal.action = action;
+        proposal.approvals = vec![proposer];
+        proposal.members_version = admins.members_version;
+        proposal.created_at = Clock::get()?.unix_timestamp;
+        proposal.expires_at = proposal
+            .created_at
+            .checked_add(PROPOSAL_LIFETIME)
+            .ok_or(ErrorCode::MathOverflow)?;
+        proposal.bump = ctx.bumps.proposal;
+
+        admins.proposal_count += 1;
+
+        msg!("Proposal {} created", proposal.id);
+
+        Ok(())
+    }
+
+    pub fn approve_admin_action(ctx: Context<ApproveAdminAction>) -> Result<()> {
+        let member = ctx.accounts.member.key();
+        let admins = &ctx.accounts.admins;
+        let proposal = &mut ctx.accounts.proposal;
+
+        require!(admins.is_member(&member), ErrorCode::NotAdmin);
+        require!(
+            Clock::get()?.unix_timestamp < proposal.expires_at,
+            ErrorCode::ProposalExpired
+        );
+        proposal.prune_approvals(admins);
+        require!(
+            !proposal.approvals.contains(&member),
+            ErrorCode::AlreadyApproved
+        );
+        proposal.approvals.push(member);
+
+        msg!(
+            "Proposal {} has {} approvals",
+            proposal.id,
+            proposal.approvals.len()
+        );
+
+        Ok(())
+    }
+
+    /// Applies an approved proposal and closes it. Only approvals from current
+    /// members count towards the threshold.
+    pub fn execute_admin_action(ctx: Context<ExecuteAdminAction>) -> Result<()> {
+        let admins = &mut ctx.accounts.admins;
+        let marketplace = &mut ctx.accounts.marketplace;
+        let proposal = &mut ctx.accounts.proposal;
+
+        require!(
+            admins.is_member(&ctx.accounts.executor.key()),
+            ErrorCode::NotAdmin
+        );
+        require!(
+            Clock::get()?.unix_timestamp < proposal.expires_at,
+            ErrorCode::ProposalExpired
+        );
+        proposal.prune_approvals(admins);
+        require!(
+            proposal.approvals.len() >= admins.threshold as usize,
+            ErrorCode::ThresholdNotMet
+        );
+
+        match proposal.action.clone() {
+            AdminAction::UpdateFees {
+                fee_bps,
+                referral_bps,
+            } => {
+                require!(fee_bps <= MAX_BASIS_POINTS, ErrorCode::InvalidBasisPoints);
+                require!(
+                    referral_bps <= MAX_BASIS_POINTS,
+                    ErrorCode::InvalidBasisPoints
+                );
+                if fee_bps > marketplace.fee_bps {
+                    let unlocks_at = proposal
+                        .created_at
+                        .checked_add(FEE_INCREASE_TIMELOCK)
+                        .ok_or(ErrorCode::MathOverflow)?;
+                    require!(
+                        Clock::get()?.unix_timestamp >= unlocks_at,
+                        ErrorCode::TimelockActive
+                    );
+                }
+                marketplace.fee_bps = fee_bps;
+                marketplace.referral_bps = referral_bps;
+            }
+            AdminAction::SetTreasury { treasury } => marketplace.treasury = treasury,
+            AdminAction::SetMembers { members, threshold } => {
+                validate_admins(&members, threshold)?;
+                admins.members = members;
+                admins.threshold = threshold;
+                admins.members_version += 1;
+            }
+        }
+
+        msg!("Proposal {} executed", proposal.id);
+
+        Ok(())
+    }
+
+    /// Closes a proposal and refunds its rent to the proposer. The proposer
+    /// can withdraw it at any time, anyone can clean it up once it expired.
+    pub fn cancel_proposal(ctx: Context<CancelProposal>) -> Result<()> {
+        let proposal = &ctx.accounts.proposal;
+
+        require!(
+            ctx.accounts.signer.key() == proposal.proposer
+                || Clock::get()?.unix_timestamp >= proposal.expires_at,
+            ErrorCode::ProposalNotExpired
+        );
+
+        msg!("Proposal {} cancelled", proposal.id);
+
+        Ok(())
+    }
+
+    /// Stops new listings, rentals and sales. Delisting, ending rentals and
+    /// withdrawing proceeds keep working so users can always get out.
+    pub fn set_paused(ctx: Context<SetPaused>, paused: bool) -> Result<()> {
+        let admins = &mut ctx.accounts.admins;
+
+        require!(
+            admins.has_role(&ctx.accounts.pauser.key(), ROLE_PAUSER),
+            ErrorCode::MissingRole
+        );
+        admins.paused = paused;
+
+        msg!("Marketplace {}", if paused { "paused" } else { "unpaused" });
+
+        Ok(())
+    }
+
     pub fn mint_nft(
         ctx: Context<MintNFT>,
         name: String,
@@ -98,7 +469,11 @@ pub mod nft_marketplace {
         Ok(())
     }
 
-    pub fn list_nft(ctx: Context<ListNFT>, price: u64, bump: u8) -> Result<()> {
+    // `_bump` is kept so existing clients keep working; the listing stores its
+    // own canonical bump.
+    pub fn list_nft(ctx: Context<ListNFT>, price: u64, _bump: u8) -> Result<()> {
+        require_not_paused(&ctx.accounts.admins)?;
+
         // Transfer NFT from seller to marketplace escrow
         transfer_checked(
             CpiContext::new(
@@ -120,7 +495,81 @@ pub mod nft_marketplace {
         listing.mint = ctx.accounts.mint.key();
         listing.price = price;
         listing.is_active = true;
-        listing.bump = bump;
+        listing.bump = ctx.bumps.listing;
+        listing.version = LISTING_VERSION;
+
+        let collection = verified_collection(&ctx.accounts.metadata_account)?;
+        check_allowlist(&ctx.accounts.collection_allowlist, collection)?;
+
+        // The stats were just created if this is the collection's first listing
+        if let (Some(stats), Some(collection), Some(bump)) = (
+            ctx.accounts.collection_stats.as_mut(),
+            collection,
+            ctx.bumps.collection_stats,
+        ) {
+            if stats.collection == Pubkey::default() {
+                stats.collection = collection;
+                stats.bump = bump;
+            }
+        }
+
+        if let Some(stats) = stats_for_collection(collection, &mut ctx.accounts.collection_stats)? {
+            stats.listed_count = stats.listed_count.saturating_add(1);
+            stats.listed_value = stats.listed_value.saturating_add(price);
+        }
+
+        Ok(())
+    }
+
+    pub fn migrate_listing(ctx: Context<MigrateListing>) -> Result<()> {
+        let info = ctx.accounts.listing.to_account_info();
+
+        require!(
+            info.data_len() == LEGACY_LISTING_SPACE,
+            ErrorCode::ListingAlreadyMigrated
+        );
+
+        let legacy = {
+            let data = info.try_borrow_data()?;
+            require!(
+                data[..8] == *Listing::DISCRIMINATOR,
+                ErrorCode::InvalidListingAccount
+            );
+            LegacyListing::deserialize(&mut &data[8..])?
+        };
+        require!(
+            legacy.mint == ctx.accounts.mint.key(),
+            ErrorCode::InvalidListingAccount
+        );
+
+        // Top up rent for the larger layout
+        let new_space = 8 + Listing::INIT_SPACE;
+        let rent_due = Rent::get()?
+            .minimum_balance(new_space)
+            .saturating_sub(info.lamports());
+        transfer_lamports(
+            &ctx.accounts.payer.to_account_info(),
+            &info,
+            &ctx.accounts.system_program,
+            rent_due,
+        )?;
+
+        info.realloc(new_space, true)?;
+
+        // Legacy listings stored whatever bump the client passed, so store the
+        // canonical one while rewriting
+        let listing = Listing {
+            seller: legacy.seller,
+            mint: legacy.mint,
+            price: legacy.price,
+            is_active: legacy.is_active,
+            bump: ctx.bumps.listing,
+            version: LISTING_VERSION,
+            reserved: [0; LISTING_RESERVED_BYTES],
+        };
+        listing.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+
+        msg!("Listing for {} migrated to version {}", listing.mint, LISTING_VERSION);
 
         Ok(())
     }
@@ -138,6 +587,16 @@ pub mod nft_marketplace {
         // Validate price is greater than 0
         require!(new_price > 0, ErrorCode::InvalidPrice);
         
+        if let Some(stats) = collection_stats_for(
+            &ctx.accounts.metadata_account,
+            &mut ctx.accounts.collection_stats,
+        )? {
+            stats.listed_value = stats
+                .listed_value
+                .saturating_sub(listing.price)
+                .saturating_add(new_price);
+        }
+
         // Update the price
         listing.price = new_price;
         
@@ -147,43 +606,199 @@ pub mod nft_marketplace {
     }
 
     pub fn buy_nft(ctx: Context<BuyNFT>, bump: u8) -> Result<()> {
+        require_not_paused(&ctx.accounts.admins)?;
+
         let listing = &ctx.accounts.listing;
+        let marketplace = &ctx.accounts.marketplace;
         
         // Verify listing is active
         require!(listing.is_active, ErrorCode::ListingNotActive);
-        
-        // Transfer SOL from buyer to seller
-        invoke(
-            &system_instruction::transfer(
-                ctx.accounts.buyer.key,
-                &listing.seller,
-                listing.price,
-            ),
-            &[
-                ctx.accounts.buyer.to_account_info(),
-                ctx.accounts.seller.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
-        )?;
 
-        // Transfer NFT from escrow to buyer
-        let seeds = &[AUTHORITY_SEED, &[bump]];
-        let signer_seeds = &[&seeds[..]];
-        
-        transfer_checked(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                TransferChecked {
-                    from: ctx.accounts.escrow_token_account.to_account_info(),
-                    to: ctx.accounts.buyer_token_account.to_account_info(),
-                    mint: ctx.accounts.mint.to_account_info(),
-                    authority: ctx.accounts.pda.to_account_info(),
-                },
-                signer_seeds,
-            ),
-            1,
-            0,
+        // Pay the seller directly or into their proceeds vault
+        let payout = proceeds_destination(&ctx.accounts.seller, &ctx.accounts.proceeds_vault)?;
+
+        let purchase = Purchase {
+            buyer: ctx.accounts.buyer.to_account_info(),
+            marketplace,
+            treasury: ctx.accounts.treasury.clone(),
+            referrer: ctx.accounts.referrer.clone(),
+            pda: ctx.accounts.pda.to_account_info(),
+            authority_bump: bump,
+            token_program: ctx.accounts.token_program.to_account_info(),
+            system_program: &ctx.accounts.system_program,
+        };
+        purchase.settle(PurchasedListing {
+            listing,
+            mint: ctx.accounts.mint.to_account_info(),
+            escrow_token_account: ctx.accounts.escrow_token_account.to_account_info(),
+            buyer_token_account: ctx.accounts.buyer_token_account.to_account_info(),
+            payout,
+            metadata_account: &ctx.accounts.metadata_account,
+            collection_stats: &mut ctx.accounts.collection_stats,
+        })
+    }
+
+    /// Sweeps several listings in one instruction. Each group of
+    /// `SWEEP_GROUP_LEN` remaining accounts is bought at its entry in
+    /// `expected_prices`, and the instruction fails once the total would exceed
+    /// `max_total`. With `skip_sold`, listings that were sold, delisted or
+    /// relisted with another seller or price in the meantime are skipped
+    /// instead of failing the sweep.
+    pub fn buy_many<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BuyMany<'info>>,
+        expected_prices: Vec<u64>,
+        max_total: u64,
+        skip_sold: bool,
+    ) -> Result<()> {
+        require_not_paused(&ctx.accounts.admins)?;
+
+        let buyer = ctx.accounts.buyer.to_account_info();
+        let pda = ctx.accounts.pda.key();
+        let purchase = Purchase {
+            buyer: buyer.clone(),
+            marketplace: &ctx.accounts.marketplace,
+            treasury: ctx.accounts.treasury.clone(),
+            referrer: ctx.accounts.referrer.clone(),
+            pda: ctx.accounts.pda.to_account_info(),
+            authority_bump: ctx.bumps.pda,
+            token_program: ctx.accounts.token_program.to_account_info(),
+            system_program: &ctx.accounts.system_program,
+        };
+
+        let groups = batch_groups(
+            ctx.remaining_accounts,
+            expected_prices.len(),
+            SWEEP_GROUP_LEN,
         )?;
+        let mut total: u64 = 0;
+        let mut bought = 0;
+
+        for (group, &expected_price) in groups.zip(&expected_prices) {
+            let [mint_info, escrow_info, buyer_token_info, listing_info, payout_info, metadata_info, stats_info] =
+                group
+            else {
+                unreachable!()
+            };
+
+            // Sold and delisted listings are closed
+            if skip_sold && listing_info.owner != &crate::ID {
+                msg!("Skipping closed listing {}", listing_info.key());
+                continue;
+            }
+
+            // Same checks as the `BuyNFT` constraints
+            let mint = Account::<Mint>::try_from(mint_info)?;
+            let listing = Account::<Listing>::try_from(listing_info)?;
+            let listing_key = Pubkey::create_program_address(
+                &[LISTING_SEED, mint.key().as_ref(), &[listing.bump]],
+                &crate::ID,
+            )
+            .map_err(|_| ErrorCode::InvalidBatchAccounts)?;
+            require_keys_eq!(
+                listing_info.key(),
+                listing_key,
+                ErrorCode::InvalidBatchAccounts
+            );
+            require_keys_eq!(
+                escrow_info.key(),
+                get_associated_token_address(&pda, &mint.key()),
+                ErrorCode::InvalidBatchAccounts
+            );
+            require_keys_eq!(
+                buyer_token_info.key(),
+                get_associated_token_address(&buyer.key(), &mint.key()),
+                ErrorCode::InvalidBatchAccounts
+            );
+            require_keys_eq!(
+                metadata_info.key(),
+                MetadataAccount::find_pda(&mint.key()).0,
+                ErrorCode::InvalidBatchAccounts
+            );
+            // A listing that was sold and relisted since has another seller or
+            // price, which the buyer did not agree to. A payout account that
+            // belongs to no seller is a bad request and never skipped.
+            let payout_seller = sweep_payout_seller(payout_info)?;
+            if skip_sold
+                && (!listing.is_active
+                    || listing.price != expected_price
+                    || listing.seller != payout_seller)
+            {
+                msg!("Skipping changed listing {}", listing_info.key());
+                continue;
+            }
+
+            require_keys_eq!(
+                payout_seller,
+                listing.seller,
+                ErrorCode::InvalidProceedsDestination
+            );
+            require!(listing.is_active, ErrorCode::ListingNotActive);
+            require!(listing.price == expected_price, ErrorCode::PriceMismatch);
+            total = total
+                .checked_add(listing.price)
+                .ok_or(ErrorCode::MathOverflow)?;
+            require!(total <= max_total, ErrorCode::MaxSpendExceeded);
+
+            if buyer_token_info.data_is_empty() {
+                associated_token::create(CpiContext::new(
+                    ctx.accounts.associated_token_program.to_account_info(),
+                    associated_token::Create {
+                        payer: buyer.clone(),
+                        associated_token: buyer_token_info.clone(),
+                        authority: buyer.clone(),
+                        mint: mint_info.clone(),
+                        system_program: ctx.accounts.system_program.to_account_info(),
+                        token_program: ctx.accounts.token_program.to_account_info(),
+                    },
+                ))?;
+            }
+
+            let mut collection_stats = optional_collection_stats(stats_info)?;
+            purchase.settle(PurchasedListing {
+                listing: &listing,
+                mint: mint_info.clone(),
+                escrow_token_account: escrow_info.clone(),
+                buyer_token_account: buyer_token_info.clone(),
+                payout: payout_info.clone(),
+                metadata_account: metadata_info,
+                collection_stats: &mut collection_stats,
+            })?;
+            if let Some(stats) = &collection_stats {
+                stats.exit(&crate::ID)?;
+            }
+            bought += 1;
+        }
+
+        msg!(
+            "Bought {} of {} listings for {} lamports",
+            bought,
+            expected_prices.len(),
+            total
+        );
+
+        Ok(())
+    }
+
+    pub fn init_proceeds_vault(ctx: Context<InitProceedsVault>) -> Result<()> {
+        let vault = &mut ctx.accounts.proceeds_vault;
+        vault.seller = ctx.accounts.seller.key();
+        vault.bump = ctx.bumps.proceeds_vault;
+
+        Ok(())
+    }
+
+    pub fn withdraw_proceeds(ctx: Context<WithdrawProceeds>) -> Result<()> {
+        let vault = ctx.accounts.proceeds_vault.to_account_info();
+
+        // Everything above the rent-exempt minimum belongs to the seller
+        let rent_exempt = Rent::get()?.minimum_balance(vault.data_len());
+        let available = vault.lamports().saturating_sub(rent_exempt);
+        require!(available > 0, ErrorCode::NoProceeds);
+
+        **vault.try_borrow_mut_lamports()? -= available;
+        **ctx.accounts.seller.to_account_info().try_borrow_mut_lamports()? += available;
+
+        msg!("Withdrew {} lamports of proceeds", available);
 
         Ok(())
     }
@@ -219,129 +834,1361 @@ pub mod nft_marketplace {
         // Mark listing as inactive
         listing.is_active = false;
 
+        if let Some(stats) = collection_stats_for(
+            &ctx.accounts.metadata_account,
+            &mut ctx.accounts.collection_stats,
+        )? {
+            stats.listed_count = stats.listed_count.saturating_sub(1);
+            stats.listed_value = stats.listed_value.saturating_sub(listing.price);
+        }
+
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-#[instruction(bump: u8)]
-pub struct MintNFT<'info> {
-    #[account(mut, signer)]
-    pub signer: Signer<'info>,
+    /// Lists several NFTs in one instruction, one price per group of
+    /// `BATCH_GROUP_LEN` remaining accounts. Pass the program ID as collection
+    /// stats for mints without a verified collection, as with `list_nft`.
+    pub fn batch_list<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BatchListNFT<'info>>,
+        prices: Vec<u64>,
+    ) -> Result<()> {
+        require_not_paused(&ctx.accounts.admins)?;
 
-    #[account(
-        init,
-        payer = signer,
-        mint::decimals = 0,
-        mint::authority = pda.key(),
-        mint::freeze_authority = pda.key(),
-    )]
-    pub mint: Account<'info, Mint>,
+        let seller = ctx.accounts.seller.to_account_info();
+        let pda = ctx.accounts.pda.key();
 
-    #[account(
-        seeds = [AUTHORITY_SEED],
-        bump,
-    )]
-    pub pda: SystemAccount<'info>,
+        let groups = batch_groups(ctx.remaining_accounts, prices.len(), BATCH_GROUP_LEN)?;
+        for (group, &price) in groups.zip(&prices) {
+            let [mint_info, seller_token_info, escrow_info, listing_info, metadata_info, stats_info] =
+                group
+            else {
+                unreachable!()
+            };
 
-    #[account(
-        init_if_needed,
-        payer = signer,
-        associated_token::mint = mint,
-        associated_token::authority = signer
-    )]
-    pub associated_token_account: Account<'info, TokenAccount>,
+            // Same checks as the `ListNFT` constraints
+            let mint = Account::<Mint>::try_from(mint_info)?;
+            let seller_token_account = Account::<TokenAccount>::try_from(seller_token_info)?;
+            require!(
+                seller_token_account.amount == 1
+                    && seller_token_account.owner == seller.key()
+                    && seller_token_account.mint == mint.key(),
+                ErrorCode::InvalidSellerTokenAccount
+            );
+            require_keys_eq!(
+                escrow_info.key(),
+                get_associated_token_address(&pda, &mint.key()),
+                ErrorCode::InvalidBatchAccounts
+            );
+            let (listing_key, listing_bump) =
+                Pubkey::find_program_address(&[LISTING_SEED, mint.key().as_ref()], &crate::ID);
+            require_keys_eq!(
+                listing_info.key(),
+                listing_key,
+                ErrorCode::InvalidBatchAccounts
+            );
+            require_keys_eq!(
+                metadata_info.key(),
+                MetadataAccount::find_pda(&mint.key()).0,
+                ErrorCode::InvalidBatchAccounts
+            );
 
-    /// CHECK: Metaplex Metadata PDA
-    #[account(mut)]
-    pub metadata_account: AccountInfo<'info>,
+            if escrow_info.data_is_empty() {
+                associated_token::create(CpiContext::new(
+                    ctx.accounts.associated_token_program.to_account_info(),
+                    associated_token::Create {
+                        payer: seller.clone(),
+                        associated_token: escrow_info.clone(),
+                        authority: ctx.accounts.pda.to_account_info(),
+                        mint: mint_info.clone(),
+                        system_program: ctx.accounts.system_program.to_account_info(),
+                        token_program: ctx.accounts.token_program.to_account_info(),
+                    },
+                ))?;
+            }
 
-    /// CHECK: Metaplex Master Edition PDA
-    #[account(mut)]
-    pub master_edition_account: AccountInfo<'info>,
+            // Fails like `init` when the mint is already listed
+            create_pda_account(
+                &seller,
+                listing_info,
+                &ctx.accounts.system_program,
+                8 + Listing::INIT_SPACE,
+                &[LISTING_SEED, mint.key().as_ref(), &[listing_bump]],
+            )?;
 
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub token_metadata_program: Program<'info, Metadata>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
-}
+            transfer_checked(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: seller_token_info.clone(),
+                        to: escrow_info.clone(),
+                        mint: mint_info.clone(),
+                        authority: seller.clone(),
+                    },
+                ),
+                1,
+                0,
+            )?;
 
-#[derive(Accounts)]
-#[instruction(price: u64, bump: u8)]
-pub struct ListNFT<'info> {
-    #[account(mut)]
-    pub seller: Signer<'info>,
+            let listing = Listing {
+                seller: seller.key(),
+                mint: mint.key(),
+                price,
+                is_active: true,
+                bump: listing_bump,
+                version: LISTING_VERSION,
+                reserved: [0; LISTING_RESERVED_BYTES],
+            };
+            listing.try_serialize(&mut &mut listing_info.try_borrow_mut_data()?[..])?;
 
-    #[account(mut)]
-    pub mint: Account<'info, Mint>,
+            let collection = verified_collection(metadata_info)?;
+            check_allowlist(&ctx.accounts.collection_allowlist, collection)?;
 
-    #[account(
-        seeds = [AUTHORITY_SEED],
-        bump,
-    )]
-    pub pda: SystemAccount<'info>,
+            let mut collection_stats = optional_collection_stats(stats_info)?;
+            if let Some(stats) = stats_for_collection(collection, &mut collection_stats)? {
+                stats.listed_count = stats.listed_count.saturating_add(1);
+                stats.listed_value = stats.listed_value.saturating_add(price);
+                stats.exit(&crate::ID)?;
+            }
+        }
 
-    #[account(
-        mut,
-        constraint = seller_token_account.amount == 1 &&
-                     seller_token_account.owner == seller.key() &&
-                     seller_token_account.mint == mint.key()
-    )]
-    pub seller_token_account: Account<'info, TokenAccount>,
+        msg!("Listed {} NFTs", prices.len());
 
-    #[account(
-        init_if_needed,
-        payer = seller,
-        associated_token::mint = mint,
-        associated_token::authority = pda
-    )]
-    pub escrow_token_account: Account<'info, TokenAccount>,
+        Ok(())
+    }
 
-    #[account(
-        init,
-        payer = seller,
-        space = 8 + 32 + 32 + 8 + 1 + 1, // discriminator + seller + mint + price + is_active + bump
-        seeds = [LISTING_SEED, mint.key().as_ref()],
-        bump
-    )]
-    pub listing: Account<'info, Listing>,
+    /// Delists several NFTs in one instruction, `count` groups of
+    /// `BATCH_GROUP_LEN` remaining accounts laid out as for `batch_list`.
+    pub fn batch_delist<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BatchDelistNFT<'info>>,
+        count: u8,
+    ) -> Result<()> {
+        let seller = ctx.accounts.seller.to_account_info();
+        let pda = ctx.accounts.pda.key();
+        let seeds = &[AUTHORITY_SEED, &[ctx.bumps.pda]];
+        let signer_seeds = &[&seeds[..]];
 
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
-}
+        for group in batch_groups(ctx.remaining_accounts, count as usize, BATCH_GROUP_LEN)? {
+            let [mint_info, seller_token_info, escrow_info, listing_info, metadata_info, stats_info] =
+                group
+            else {
+                unreachable!()
+            };
 
-// NEW: Update price context
-#[derive(Accounts)]
-pub struct UpdatePrice<'info> {
-    #[account(mut)]
-    pub seller: Signer<'info>,
+            // Same checks as the `DelistNFT` constraints
+            let mint = Account::<Mint>::try_from(mint_info)?;
+            require_keys_eq!(
+                seller_token_info.key(),
+                get_associated_token_address(&seller.key(), &mint.key()),
+                ErrorCode::InvalidBatchAccounts
+            );
+            require_keys_eq!(
+                escrow_info.key(),
+                get_associated_token_address(&pda, &mint.key()),
+                ErrorCode::InvalidBatchAccounts
+            );
+            let listing = Account::<Listing>::try_from(listing_info)?;
+            let listing_key = Pubkey::create_program_address(
+                &[LISTING_SEED, mint.key().as_ref(), &[listing.bump]],
+                &crate::ID,
+            )
+            .map_err(|_| ErrorCode::InvalidBatchAccounts)?;
+            require_keys_eq!(
+                listing_info.key(),
+                listing_key,
+                ErrorCode::InvalidBatchAccounts
+            );
+            require_keys_eq!(
+                metadata_info.key(),
+                MetadataAccount::find_pda(&mint.key()).0,
+                ErrorCode::InvalidBatchAccounts
+            );
 
-    #[account(mut)]
-    pub mint: Account<'info, Mint>,
+            require!(listing.is_active, ErrorCode::ListingNotActive);
+            require!(
+                listing.seller == seller.key(),
+                ErrorCode::UnauthorizedSeller
+            );
 
-    #[account(
+            transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    TransferChecked {
+                        from: escrow_info.clone(),
+                        to: seller_token_info.clone(),
+                        mint: mint_info.clone(),
+                        authority: ctx.accounts.pda.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                1,
+                0,
+            )?;
+
+            let mut collection_stats = optional_collection_stats(stats_info)?;
+            if let Some(stats) = collection_stats_for(metadata_info, &mut collection_stats)? {
+                stats.listed_count = stats.listed_count.saturating_sub(1);
+                stats.listed_value = stats.listed_value.saturating_sub(listing.price);
+                stats.exit(&crate::ID)?;
+            }
+
+            listing.close(seller.clone())?;
+        }
+
+        msg!("Delisted {} NFTs", count);
+
+        Ok(())
+    }
+
+    pub fn list_for_rent(ctx: Context<ListForRent>, daily_rate: u64, max_days: u16) -> Result<()> {
+        require_not_paused(&ctx.accounts.admins)?;
+
+        require!(daily_rate > 0, ErrorCode::InvalidPrice);
+        require!(max_days > 0, ErrorCode::InvalidRentalDuration);
+
+        // Transfer NFT from owner to marketplace escrow
+        transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.owner_token_account.to_account_info(),
+                    to: ctx.accounts.escrow_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                },
+            ),
+            1,
+            0,
+        )?;
+
+        // Initialize rental account
+        let rental = &mut ctx.accounts.rental;
+        rental.owner = ctx.accounts.owner.key();
+        rental.mint = ctx.accounts.mint.key();
+        rental.renter = Pubkey::default();
+        rental.daily_rate = daily_rate;
+        rental.max_days = max_days;
+        rental.end_time = 0;
+        rental.bump = ctx.bumps.rental;
+
+        Ok(())
+    }
+
+    pub fn rent_nft(ctx: Context<RentNFT>, days: u16, bump: u8) -> Result<()> {
+        require_not_paused(&ctx.accounts.admins)?;
+
+        let rental = &mut ctx.accounts.rental;
+
+        // Only one renter at a time
+        require!(!rental.is_rented(), ErrorCode::RentalActive);
+        require!(
+            days > 0 && days <= rental.max_days,
+            ErrorCode::InvalidRentalDuration
+        );
+
+        let cost = rental
+            .daily_rate
+            .checked_mul(days as u64)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Renter pays the full rental upfront
+        transfer_lamports(
+            &ctx.accounts.renter.to_account_info(),
+            &ctx.accounts.owner,
+            &ctx.accounts.system_program,
+            cost,
+        )?;
+
+        let seeds = &[AUTHORITY_SEED, &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        // Transfer NFT from escrow to renter
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.escrow_token_account.to_account_info(),
+                    to: ctx.accounts.renter_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    authority: ctx.accounts.pda.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            1,
+            0,
+        )?;
+
+        // Let the marketplace PDA pull the NFT back when the rental ends
+        approve(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Approve {
+                    to: ctx.accounts.renter_token_account.to_account_info(),
+                    delegate: ctx.accounts.pda.to_account_info(),
+                    authority: ctx.accounts.renter.to_account_info(),
+                },
+            ),
+            1,
+        )?;
+
+        // Freeze the renter's account so the NFT can be used but not moved
+        freeze_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            FreezeAccount {
+                account: ctx.accounts.renter_token_account.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                authority: ctx.accounts.pda.to_account_info(),
+            },
+            signer_seeds,
+        ))?;
+
+        let now = Clock::get()?.unix_timestamp;
+        rental.renter = ctx.accounts.renter.key();
+        rental.end_time = now
+            .checked_add(days as i64 * SECONDS_PER_DAY)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        msg!(
+            "NFT {} rented by {} until {}",
+            rental.mint,
+            rental.renter,
+            rental.end_time
+        );
+
+        Ok(())
+    }
+
+    pub fn end_rental(ctx: Context<EndRental>, bump: u8) -> Result<()> {
+        let rental = &ctx.accounts.rental;
+
+        require!(rental.is_rented(), ErrorCode::RentalNotActive);
+        require!(
+            Clock::get()?.unix_timestamp >= rental.end_time,
+            ErrorCode::RentalNotExpired
+        );
+
+        let seeds = &[AUTHORITY_SEED, &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        // Unfreeze the renter's account
+        thaw_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            ThawAccount {
+                account: ctx.accounts.renter_token_account.to_account_info(),
+                mint: ctx.accounts.mint.to_account_info(),
+                authority: ctx.accounts.pda.to_account_info(),
+            },
+            signer_seeds,
+        ))?;
+
+        // Return NFT to owner using the delegation granted in rent_nft
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.renter_token_account.to_account_info(),
+                    to: ctx.accounts.owner_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    authority: ctx.accounts.pda.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            1,
+            0,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn delist_rental(ctx: Context<DelistRental>, bump: u8) -> Result<()> {
+        // A rented NFT can only come back through end_rental
+        require!(!ctx.accounts.rental.is_rented(), ErrorCode::RentalActive);
+
+        // Transfer NFT back to owner
+        let seeds = &[AUTHORITY_SEED, &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.escrow_token_account.to_account_info(),
+                    to: ctx.accounts.owner_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    authority: ctx.accounts.pda.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            1,
+            0,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn fill_signed_order(
+        ctx: Context<FillSignedOrder>,
+        order: SignedOrder,
+        bump: u8,
+    ) -> Result<()> {
+        require_not_paused(&ctx.accounts.admins)?;
+
+        require!(order.price > 0, ErrorCode::InvalidPrice);
+        require!(
+            Clock::get()?.unix_timestamp < order.expiry,
+            ErrorCode::OrderExpired
+        );
+
+        // The preceding instruction must be the seller's ed25519 signature over the order
+        verify_ed25519_signature(
+            &ctx.accounts.instructions_sysvar,
+            &order.seller,
+            &order.message()?,
+        )?;
+
+        // Recording the nonce makes the order unusable a second time
+        let order_nonce = &mut ctx.accounts.order_nonce;
+        order_nonce.seller = order.seller;
+        order_nonce.nonce = order.nonce;
+        order_nonce.bump = ctx.bumps.order_nonce;
+
+        let marketplace = &ctx.accounts.marketplace;
+        let marketplace_fee = marketplace.marketplace_fee(order.price)?;
+        let seller_proceeds = order.price - marketplace_fee;
+
+        // Pay the seller directly or into their proceeds vault
+        let payout = proceeds_destination(&ctx.accounts.seller, &ctx.accounts.proceeds_vault)?;
+
+        transfer_lamports(
+            &ctx.accounts.buyer.to_account_info(),
+            &payout,
+            &ctx.accounts.system_program,
+            seller_proceeds,
+        )?;
+
+        transfer_lamports(
+            &ctx.accounts.buyer.to_account_info(),
+            &ctx.accounts.treasury,
+            &ctx.accounts.system_program,
+            marketplace_fee,
+        )?;
+
+        // Transfer NFT from seller to buyer using the delegation the seller granted
+        let seeds = &[AUTHORITY_SEED, &[bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.seller_token_account.to_account_info(),
+                    to: ctx.accounts.buyer_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
+                    authority: ctx.accounts.pda.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            1,
+            0,
+        )?;
+
+        if let Some(stats) = collection_stats_for(
+            &ctx.accounts.metadata_account,
+            &mut ctx.accounts.collection_stats,
+        )? {
+            stats.sales_count = stats.sales_count.saturating_add(1);
+            stats.total_volume = stats.total_volume.saturating_add(order.price);
+            stats.last_sale_price = order.price;
+        }
+
+        emit!(NftSold {
+            mint: order.mint,
+            seller: order.seller,
+            buyer: ctx.accounts.buyer.key(),
+            price: order.price,
+            marketplace_fee,
+            referrer: None,
+            referral_fee: 0,
+        });
+
+        Ok(())
+    }
+
+    pub fn cancel_signed_order(ctx: Context<CancelSignedOrder>, nonce: u64) -> Result<()> {
+        let order_nonce = &mut ctx.accounts.order_nonce;
+        order_nonce.seller = ctx.accounts.seller.key();
+        order_nonce.nonce = nonce;
+        order_nonce.bump = ctx.bumps.order_nonce;
+
+        msg!("Signed order {} cancelled", nonce);
+
+        Ok(())
+    }
+}
+
+/// Only the program's upgrade authority may create the marketplace, so nobody
+/// can front-run deployment and pick the authority, treasury or fee.
+#[derive(Accounts)]
+pub struct InitializeMarketplace<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(authority.key())
+            @ ErrorCode::UnauthorizedAuthority
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    /// CHECK: receives marketplace fees
+    pub treasury: AccountInfo<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + 32 + 32 + 2 + 2 + 1, // discriminator + authority + treasury + fee_bps + referral_bps + bump
+        seeds = [MARKETPLACE_SEED],
+        bump
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMarketplace<'info> {
+    pub authority: Signer<'info>,
+
+    /// CHECK: receives marketplace fees
+    pub treasury: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [MARKETPLACE_SEED],
+        bump = marketplace.bump,
+        has_one = authority @ ErrorCode::UnauthorizedAuthority
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+}
+
+#[derive(Accounts)]
+pub struct InitCollectionStats<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub collection_mint: Account<'info, Mint>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + 32 + 8 + 8 + 8 + 8 + 8 + 1, // discriminator + collection + listed_count + listed_value + sales_count + total_volume + last_sale_price + bump
+        seeds = [COLLECTION_STATS_SEED, collection_mint.key().as_ref()],
+        bump
+    )]
+    pub collection_stats: Account<'info, CollectionStats>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitCollectionAllowlist<'info> {
+    /// Marketplace authority, or an allowlist curator once admins are set up
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [MARKETPLACE_SEED],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        seeds = [ADMINS_SEED],
+        bump = admins.bump,
+    )]
+    pub admins: Option<Account<'info, Admins>>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + 1 + 4 + 32 * MAX_ALLOWED_COLLECTIONS + 1, // discriminator + strict + collections + bump
+        seeds = [ALLOWLIST_SEED],
+        bump
+    )]
+    pub collection_allowlist: Account<'info, CollectionAllowlist>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateCollectionAllowlist<'info> {
+    /// Marketplace authority, or an allowlist curator once admins are set up
+    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [MARKETPLACE_SEED],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        seeds = [ADMINS_SEED],
+        bump = admins.bump,
+    )]
+    pub admins: Option<Account<'info, Admins>>,
+
+    #[account(
+        mut,
+        seeds = [ALLOWLIST_SEED],
+        bump = collection_allowlist.bump,
+    )]
+    pub collection_allowlist: Account<'info, CollectionAllowlist>,
+}
+
+impl InitCollectionAllowlist<'_> {
+    fn check_role(&self) -> Result<()> {
+        require_role(
+            &self.marketplace,
+            &self.admins,
+            &self.authority.key(),
+            ROLE_ALLOWLIST_CURATOR,
+        )
+    }
+}
+
+impl UpdateCollectionAllowlist<'_> {
+    fn check_role(&self) -> Result<()> {
+        require_role(
+            &self.marketplace,
+            &self.admins,
+            &self.authority.key(),
+            ROLE_ALLOWLIST_CURATOR,
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitAdmins<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [MARKETPLACE_SEED],
+        bump = marketplace.bump,
+        has_one = authority @ ErrorCode::UnauthorizedAuthority
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Admins::INIT_SPACE,
+        seeds = [ADMINS_SEED],
+        bump
+    )]
+    pub admins: Account<'info, Admins>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeAdminAction<'info> {
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [ADMINS_SEED],
+        bump = admins.bump,
+    )]
+    pub admins: Account<'info, Admins>,
+
+    #[account(
+        init,
+        payer = proposer,
+        space = 8 + Proposal::INIT_SPACE,
+        seeds = [PROPOSAL_SEED, &admins.proposal_count.to_le_bytes()],
+        bump
+    )]
+    pub proposal: Account<'info, Proposal>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveAdminAction<'info> {
+    pub member: Signer<'info>,
+
+    #[account(
+        seeds = [ADMINS_SEED],
+        bump = admins.bump,
+    )]
+    pub admins: Account<'info, Admins>,
+
+    #[account(
+        mut,
+        seeds = [PROPOSAL_SEED, &proposal.id.to_le_bytes()],
+        bump = proposal.bump,
+    )]
+    pub proposal: Account<'info, Proposal>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteAdminAction<'info> {
+    pub executor: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [ADMINS_SEED],
+        bump = admins.bump,
+    )]
+    pub admins: Account<'info, Admins>,
+
+    #[account(
+        mut,
+        seeds = [MARKETPLACE_SEED],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    #[account(
+        mut,
+        seeds = [PROPOSAL_SEED, &proposal.id.to_le_bytes()],
+        bump = proposal.bump,
+        close = proposer
+    )]
+    pub proposal: Account<'info, Proposal>,
+
+    /// CHECK: receives the proposal's rent back
+    #[account(mut, address = proposal.proposer)]
+    pub proposer: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelProposal<'info> {
+    pub signer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [PROPOSAL_SEED, &proposal.id.to_le_bytes()],
+        bump = proposal.bump,
+        close = proposer
+    )]
+    pub proposal: Account<'info, Proposal>,
+
+    /// CHECK: receives the proposal's rent back
+    #[account(mut, address = proposal.proposer)]
+    pub proposer: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPaused<'info> {
+    pub pauser: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [ADMINS_SEED],
+        bump = admins.bump,
+    )]
+    pub admins: Account<'info, Admins>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct MintNFT<'info> {
+    #[account(mut, signer)]
+    pub signer: Signer<'info>,
+
+    #[account(
+        init,
+        payer = signer,
+        mint::decimals = 0,
+        mint::authority = pda.key(),
+        mint::freeze_authority = pda.key(),
+    )]
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [AUTHORITY_SEED],
+        bump,
+    )]
+    pub pda: SystemAccount<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = mint,
+        associated_token::authority = signer
+    )]
+    pub associated_token_account: Account<'info, TokenAccount>,
+
+    /// CHECK: Metaplex Metadata PDA
+    #[account(mut)]
+    pub metadata_account: AccountInfo<'info>,
+
+    /// CHECK: Metaplex Master Edition PDA
+    #[account(mut)]
+    pub master_edition_account: AccountInfo<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_metadata_program: Program<'info, Metadata>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(price: u64, bump: u8)]
+pub struct ListNFT<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(mut)]
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [AUTHORITY_SEED],
+        bump,
+    )]
+    pub pda: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = seller_token_account.amount == 1 &&
+                     seller_token_account.owner == seller.key() &&
+                     seller_token_account.mint == mint.key()
+    )]
+    pub seller_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = seller,
+        associated_token::mint = mint,
+        associated_token::authority = pda
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = seller,
+        space = 8 + Listing::INIT_SPACE,
+        seeds = [LISTING_SEED, mint.key().as_ref()],
+        bump
+    )]
+    pub listing: Account<'info, Listing>,
+
+    /// CHECK: Metaplex metadata PDA of the mint, read for its verified collection
+    #[account(
+        seeds = [MetadataAccount::PREFIX, mpl_token_metadata::ID.as_ref(), mint.key().as_ref()],
+        seeds::program = mpl_token_metadata::ID,
+        bump
+    )]
+    pub metadata_account: UncheckedAccount<'info>,
+
+    /// Required when the mint belongs to a verified collection, created by the
+    /// seller on the collection's first listing
+    #[account(
+        init_if_needed,
+        payer = seller,
+        space = 8 + 32 + 8 + 8 + 8 + 8 + 8 + 1, // discriminator + collection + listed_count + listed_value + sales_count + total_volume + last_sale_price + bump
+        seeds = [COLLECTION_STATS_SEED, &stats_collection(&metadata_account)?],
+        bump
+    )]
+    pub collection_stats: Option<Account<'info, CollectionStats>>,
+
+    /// CHECK: `CollectionAllowlist`, read in the handler since it may not exist yet
+    #[account(
+        seeds = [ALLOWLIST_SEED],
+        bump
+    )]
+    pub collection_allowlist: UncheckedAccount<'info>,
+
+    /// CHECK: `Admins`, read for the pause flag since it may not exist yet
+    #[account(
+        seeds = [ADMINS_SEED],
+        bump
+    )]
+    pub admins: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateListing<'info> {
+    /// Pays for the extra rent, anyone may migrate a listing
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub mint: Account<'info, Mint>,
+
+    /// CHECK: legacy listing that cannot be deserialized as `Listing`, validated in the handler
+    #[account(
+        mut,
+        seeds = [LISTING_SEED, mint.key().as_ref()],
+        bump,
+        owner = crate::ID,
+    )]
+    pub listing: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// NEW: Update price context
+#[derive(Accounts)]
+pub struct UpdatePrice<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(mut)]
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [LISTING_SEED, mint.key().as_ref()],
+        bump = listing.bump,
+        constraint = listing.seller == seller.key() @ ErrorCode::UnauthorizedSeller
+    )]
+    pub listing: Account<'info, Listing>,
+
+    /// CHECK: Metaplex metadata PDA of the mint, read for its verified collection
+    #[account(
+        seeds = [MetadataAccount::PREFIX, mpl_token_metadata::ID.as_ref(), mint.key().as_ref()],
+        seeds::program = mpl_token_metadata::ID,
+        bump
+    )]
+    pub metadata_account: UncheckedAccount<'info>,
+
+    /// Required when the mint belongs to a verified collection
+    #[account(mut)]
+    pub collection_stats: Option<Account<'info, CollectionStats>>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct BuyNFT<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    /// CHECK: seller receives SOL, omitted when paying into the seller's proceeds vault
+    #[account(mut, address = listing.seller @ ErrorCode::UnauthorizedSeller)]
+    pub seller: Option<AccountInfo<'info>>,
+
+    #[account(
+        mut,
+        seeds = [PROCEEDS_SEED, listing.seller.as_ref()],
+        bump = proceeds_vault.bump,
+    )]
+    pub proceeds_vault: Option<Account<'info, ProceedsVault>>,
+
+    #[account(
+        seeds = [MARKETPLACE_SEED],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// CHECK: receives the marketplace fee
+    #[account(mut, address = marketplace.treasury)]
+    pub treasury: AccountInfo<'info>,
+
+    /// CHECK: optional storefront that referred the buyer, receives a share of the fee
+    #[account(
+        mut,
+        constraint = referrer.key() != buyer.key() @ ErrorCode::SelfReferral
+    )]
+    pub referrer: Option<AccountInfo<'info>>,
+
+    #[account(mut)]
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [AUTHORITY_SEED],
+        bump,
+    )]
+    pub pda: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = pda
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        associated_token::mint = mint,
+        associated_token::authority = buyer
+    )]
+    pub buyer_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [LISTING_SEED, mint.key().as_ref()],
+        bump = listing.bump,
+    )]
+    pub listing: Account<'info, Listing>,
+
+    /// CHECK: Metaplex metadata PDA of the mint, read for its verified collection
+    #[account(
+        seeds = [MetadataAccount::PREFIX, mpl_token_metadata::ID.as_ref(), mint.key().as_ref()],
+        seeds::program = mpl_token_metadata::ID,
+        bump
+    )]
+    pub metadata_account: UncheckedAccount<'info>,
+
+    /// Required when the mint belongs to a verified collection
+    #[account(mut)]
+    pub collection_stats: Option<Account<'info, CollectionStats>>,
+
+    /// CHECK: `Admins`, read for the pause flag since it may not exist yet
+    #[account(
+        seeds = [ADMINS_SEED],
+        bump
+    )]
+    pub admins: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct BuyMany<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    #[account(
+        seeds = [MARKETPLACE_SEED],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// CHECK: receives the marketplace fee
+    #[account(mut, address = marketplace.treasury)]
+    pub treasury: AccountInfo<'info>,
+
+    /// CHECK: optional storefront that referred the buyer, receives a share of every fee
+    #[account(
+        mut,
+        constraint = referrer.key() != buyer.key() @ ErrorCode::SelfReferral
+    )]
+    pub referrer: Option<AccountInfo<'info>>,
+
+    #[account(
+        seeds = [AUTHORITY_SEED],
+        bump,
+    )]
+    pub pda: SystemAccount<'info>,
+
+    /// CHECK: `Admins`, read for the pause flag since it may not exist yet
+    #[account(
+        seeds = [ADMINS_SEED],
+        bump
+    )]
+    pub admins: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitProceedsVault<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        init,
+        payer = seller,
+        space = 8 + 32 + 1, // discriminator + seller + bump
+        seeds = [PROCEEDS_SEED, seller.key().as_ref()],
+        bump
+    )]
+    pub proceeds_vault: Account<'info, ProceedsVault>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawProceeds<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [PROCEEDS_SEED, seller.key().as_ref()],
+        bump = proceeds_vault.bump,
+        has_one = seller @ ErrorCode::UnauthorizedSeller
+    )]
+    pub proceeds_vault: Account<'info, ProceedsVault>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct DelistNFT<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(mut)]
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [AUTHORITY_SEED],
+        bump,
+    )]
+    pub pda: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = pda
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = seller
+    )]
+    pub seller_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [LISTING_SEED, mint.key().as_ref()],
+        bump = listing.bump,
+        close = seller
+    )]
+    pub listing: Account<'info, Listing>,
+
+    /// CHECK: Metaplex metadata PDA of the mint, read for its verified collection
+    #[account(
+        seeds = [MetadataAccount::PREFIX, mpl_token_metadata::ID.as_ref(), mint.key().as_ref()],
+        seeds::program = mpl_token_metadata::ID,
+        bump
+    )]
+    pub metadata_account: UncheckedAccount<'info>,
+
+    /// Required when the mint belongs to a verified collection
+    #[account(mut)]
+    pub collection_stats: Option<Account<'info, CollectionStats>>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BatchListNFT<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        seeds = [AUTHORITY_SEED],
+        bump,
+    )]
+    pub pda: SystemAccount<'info>,
+
+    /// CHECK: `CollectionAllowlist`, read in the handler since it may not exist yet
+    #[account(
+        seeds = [ALLOWLIST_SEED],
+        bump
+    )]
+    pub collection_allowlist: UncheckedAccount<'info>,
+
+    /// CHECK: `Admins`, read for the pause flag since it may not exist yet
+    #[account(
+        seeds = [ADMINS_SEED],
+        bump
+    )]
+    pub admins: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BatchDelistNFT<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        seeds = [AUTHORITY_SEED],
+        bump,
+    )]
+    pub pda: SystemAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ListForRent<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        constraint = mint.freeze_authority == COption::Some(pda.key()) @ ErrorCode::FreezeAuthorityMismatch
+    )]
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [AUTHORITY_SEED],
+        bump,
+    )]
+    pub pda: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        constraint = owner_token_account.amount == 1 &&
+                     owner_token_account.owner == owner.key() &&
+                     owner_token_account.mint == mint.key()
+    )]
+    pub owner_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        associated_token::mint = mint,
+        associated_token::authority = pda
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + 32 + 32 + 32 + 8 + 2 + 8 + 1, // discriminator + owner + mint + renter + daily_rate + max_days + end_time + bump
+        seeds = [RENTAL_SEED, mint.key().as_ref()],
+        bump
+    )]
+    pub rental: Account<'info, Rental>,
+
+    /// CHECK: `Admins`, read for the pause flag since it may not exist yet
+    #[account(
+        seeds = [ADMINS_SEED],
+        bump
+    )]
+    pub admins: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(days: u16, bump: u8)]
+pub struct RentNFT<'info> {
+    #[account(mut)]
+    pub renter: Signer<'info>,
+
+    /// CHECK: owner receives SOL
+    #[account(mut, address = rental.owner @ ErrorCode::UnauthorizedSeller)]
+    pub owner: AccountInfo<'info>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [AUTHORITY_SEED],
+        bump,
+    )]
+    pub pda: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = pda
+    )]
+    pub escrow_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = renter,
+        associated_token::mint = mint,
+        associated_token::authority = renter
+    )]
+    pub renter_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [RENTAL_SEED, mint.key().as_ref()],
+        bump = rental.bump,
+    )]
+    pub rental: Account<'info, Rental>,
+
+    /// CHECK: `Admins`, read for the pause flag since it may not exist yet
+    #[account(
+        seeds = [ADMINS_SEED],
+        bump
+    )]
+    pub admins: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(bump: u8)]
+pub struct EndRental<'info> {
+    /// Anyone may end an expired rental
+    #[account(mut)]
+    pub caller: Signer<'info>,
+
+    /// CHECK: receives the rental account's rent
+    #[account(mut, address = rental.owner @ ErrorCode::UnauthorizedSeller)]
+    pub owner: AccountInfo<'info>,
+
+    /// CHECK: only used to derive the renter's token account
+    #[account(address = rental.renter)]
+    pub renter: AccountInfo<'info>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [AUTHORITY_SEED],
+        bump,
+    )]
+    pub pda: SystemAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = renter
+    )]
+    pub renter_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = caller,
+        associated_token::mint = mint,
+        associated_token::authority = owner
+    )]
+    pub owner_token_account: Account<'info, TokenAccount>,
+
+    #[account(
         mut,
-        seeds = [LISTING_SEED, mint.key().as_ref()],
-        bump = listing.bump,
-        constraint = listing.seller == seller.key() @ ErrorCode::UnauthorizedSeller
+        seeds = [RENTAL_SEED, mint.key().as_ref()],
+        bump = rental.bump,
+        close = owner
     )]
-    pub listing: Account<'info, Listing>,
+    pub rental: Account<'info, Rental>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
 #[instruction(bump: u8)]
-pub struct BuyNFT<'info> {
-    #[account(mut)]
-    pub buyer: Signer<'info>,
-
-    /// CHECK: seller receives SOL
+pub struct DelistRental<'info> {
     #[account(mut)]
-    pub seller: AccountInfo<'info>,
+    pub owner: Signer<'info>,
 
-    #[account(mut)]
     pub mint: Account<'info, Mint>,
 
     #[account(
@@ -359,33 +2206,54 @@ pub struct BuyNFT<'info> {
 
     #[account(
         init_if_needed,
-        payer = buyer,
+        payer = owner,
         associated_token::mint = mint,
-        associated_token::authority = buyer
+        associated_token::authority = owner
     )]
-    pub buyer_token_account: Account<'info, TokenAccount>,
+    pub owner_token_account: Account<'info, TokenAccount>,
 
     #[account(
         mut,
-        seeds = [LISTING_SEED, mint.key().as_ref()],
-        bump = listing.bump,
-        close = seller
+        seeds = [RENTAL_SEED, mint.key().as_ref()],
+        bump = rental.bump,
+        constraint = rental.owner == owner.key() @ ErrorCode::UnauthorizedSeller,
+        close = owner
     )]
-    pub listing: Account<'info, Listing>,
+    pub rental: Account<'info, Rental>,
 
     pub token_program: Program<'info, Token>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
 }
 
 #[derive(Accounts)]
-#[instruction(bump: u8)]
-pub struct DelistNFT<'info> {
+#[instruction(order: SignedOrder, bump: u8)]
+pub struct FillSignedOrder<'info> {
     #[account(mut)]
-    pub seller: Signer<'info>,
+    pub buyer: Signer<'info>,
 
-    #[account(mut)]
+    /// CHECK: seller receives SOL, omitted when paying into the seller's proceeds vault
+    #[account(mut, address = order.seller @ ErrorCode::UnauthorizedSeller)]
+    pub seller: Option<AccountInfo<'info>>,
+
+    #[account(
+        mut,
+        seeds = [PROCEEDS_SEED, order.seller.as_ref()],
+        bump = proceeds_vault.bump,
+    )]
+    pub proceeds_vault: Option<Account<'info, ProceedsVault>>,
+
+    #[account(
+        seeds = [MARKETPLACE_SEED],
+        bump = marketplace.bump,
+    )]
+    pub marketplace: Account<'info, Marketplace>,
+
+    /// CHECK: receives the marketplace fee
+    #[account(mut, address = marketplace.treasury)]
+    pub treasury: AccountInfo<'info>,
+
+    #[account(address = order.mint)]
     pub mint: Account<'info, Mint>,
 
     #[account(
@@ -396,38 +2264,709 @@ pub struct DelistNFT<'info> {
 
     #[account(
         mut,
-        associated_token::mint = mint,
-        associated_token::authority = pda
+        constraint = seller_token_account.amount == 1 &&
+                     seller_token_account.owner == order.seller &&
+                     seller_token_account.mint == mint.key() @ ErrorCode::InvalidSellerTokenAccount,
+        constraint = seller_token_account.delegate == COption::Some(pda.key()) &&
+                     seller_token_account.delegated_amount >= 1 @ ErrorCode::MissingDelegation
     )]
-    pub escrow_token_account: Account<'info, TokenAccount>,
+    pub seller_token_account: Account<'info, TokenAccount>,
 
     #[account(
-        mut,
+        init_if_needed,
+        payer = buyer,
         associated_token::mint = mint,
-        associated_token::authority = seller
+        associated_token::authority = buyer
     )]
-    pub seller_token_account: Account<'info, TokenAccount>,
+    pub buyer_token_account: Account<'info, TokenAccount>,
 
     #[account(
-        mut,
-        seeds = [LISTING_SEED, mint.key().as_ref()],
-        bump = listing.bump,
-        close = seller
+        init,
+        payer = buyer,
+        space = 8 + 32 + 8 + 1, // discriminator + seller + nonce + bump
+        seeds = [ORDER_NONCE_SEED, order.seller.as_ref(), &order.nonce.to_le_bytes()],
+        bump
     )]
-    pub listing: Account<'info, Listing>,
+    pub order_nonce: Account<'info, OrderNonce>,
+
+    /// CHECK: Metaplex metadata PDA of the mint, read for its verified collection
+    #[account(
+        seeds = [MetadataAccount::PREFIX, mpl_token_metadata::ID.as_ref(), mint.key().as_ref()],
+        seeds::program = mpl_token_metadata::ID,
+        bump
+    )]
+    pub metadata_account: UncheckedAccount<'info>,
+
+    /// Required when the mint belongs to a verified collection
+    #[account(mut)]
+    pub collection_stats: Option<Account<'info, CollectionStats>>,
+
+    /// CHECK: instructions sysvar, used to find the ed25519 signature check
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions_sysvar: AccountInfo<'info>,
+
+    /// CHECK: `Admins`, read for the pause flag since it may not exist yet
+    #[account(
+        seeds = [ADMINS_SEED],
+        bump
+    )]
+    pub admins: UncheckedAccount<'info>,
 
     pub token_program: Program<'info, Token>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+#[instruction(nonce: u64)]
+pub struct CancelSignedOrder<'info> {
+    #[account(mut)]
+    pub seller: Signer<'info>,
+
+    #[account(
+        init,
+        payer = seller,
+        space = 8 + 32 + 8 + 1, // discriminator + seller + nonce + bump
+        seeds = [ORDER_NONCE_SEED, seller.key().as_ref(), &nonce.to_le_bytes()],
+        bump
+    )]
+    pub order_nonce: Account<'info, OrderNonce>,
+
+    pub system_program: Program<'info, System>,
+}
+
 #[account]
+#[derive(InitSpace)]
 pub struct Listing {
     pub seller: Pubkey,
     pub mint: Pubkey,
     pub price: u64,
     pub is_active: bool,
     pub bump: u8,
+    pub version: u8,
+    pub reserved: [u8; LISTING_RESERVED_BYTES],
+}
+
+/// `Listing` layout before versioning, only read by `migrate_listing`.
+#[derive(AnchorDeserialize)]
+pub struct LegacyListing {
+    pub seller: Pubkey,
+    pub mint: Pubkey,
+    pub price: u64,
+    pub is_active: bool,
+    pub bump: u8,
+}
+
+#[account]
+pub struct Marketplace {
+    pub authority: Pubkey,
+    pub treasury: Pubkey,
+    pub fee_bps: u16,
+    pub referral_bps: u16,
+    pub bump: u8,
+}
+
+impl Marketplace {
+    /// Marketplace's cut of a sale at `price`.
+    pub fn marketplace_fee(&self, price: u64) -> Result<u64> {
+        apply_basis_points(price, self.fee_bps)
+    }
+
+    /// Referrer's share of the marketplace fee.
+    pub fn referral_fee(&self, marketplace_fee: u64) -> Result<u64> {
+        apply_basis_points(marketplace_fee, self.referral_bps)
+    }
+}
+
+/// Trading activity for a verified Metaplex collection. The floor price is
+/// not kept here since maintaining it exactly would need every active listing
+/// of the collection; clients read it from the indexer instead.
+#[account]
+pub struct CollectionStats {
+    pub collection: Pubkey,
+    pub listed_count: u64,
+    /// Sum of the prices of all active listings
+    pub listed_value: u64,
+    pub sales_count: u64,
+    pub total_volume: u64,
+    pub last_sale_price: u64,
+    pub bump: u8,
+}
+
+/// Collections the marketplace authority allows to be listed. In strict mode
+/// `list_nft` and `batch_list` only accept mints of a verified collection on
+/// the list.
+#[account]
+pub struct CollectionAllowlist {
+    pub strict: bool,
+    pub collections: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+impl CollectionAllowlist {
+    pub fn allows(&self, collection: Option<Pubkey>) -> bool {
+        !self.strict || collection.is_some_and(|collection| self.collections.contains(&collection))
+    }
+}
+
+/// Members that control the marketplace once `init_admins` has run, in place
+/// of the single `Marketplace::authority` key.
+#[account]
+#[derive(InitSpace)]
+pub struct Admins {
+    #[max_len(MAX_ADMINS)]
+    pub members: Vec<AdminMember>,
+    /// Approvals needed to execute a proposal
+    pub threshold: u8,
+    pub paused: bool,
+    /// ID of the next proposal
+    pub proposal_count: u64,
+    /// Bumped whenever `SetMembers` replaces the members
+    pub members_version: u64,
+    pub bump: u8,
+}
+
+impl Admins {
+    pub fn is_member(&self, key: &Pubkey) -> bool {
+        self.members.iter().any(|member| member.key == *key)
+    }
+
+    pub fn has_role(&self, key: &Pubkey, role: u8) -> bool {
+        self.members
+            .iter()
+            .any(|member| member.key == *key && member.roles & role == role)
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq, InitSpace)]
+pub struct AdminMember {
+    pub key: Pubkey,
+    /// `ROLE_*` flags
+    pub roles: u8,
+}
+
+/// Privileged change that needs `Admins::threshold` approvals.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq, InitSpace)]
+pub enum AdminAction {
+    /// Fee increases wait `FEE_INCREASE_TIMELOCK` after being proposed
+    UpdateFees {
+        fee_bps: u16,
+        referral_bps: u16,
+    },
+    SetTreasury {
+        treasury: Pubkey,
+    },
+    SetMembers {
+        #[max_len(MAX_ADMINS)]
+        members: Vec<AdminMember>,
+        threshold: u8,
+    },
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Proposal {
+    pub id: u64,
+    pub proposer: Pubkey,
+    pub action: AdminAction,
+    #[max_len(MAX_ADMINS)]
+    pub approvals: Vec<Pubkey>,
+    /// `Admins::members_version` the approvals were given under
+    pub members_version: u64,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub bump: u8,
+}
+
+impl Proposal {
+    /// Drops the approvals of members removed since the approvals were given,
+    /// so they neither count towards the threshold nor take up space.
+    pub fn prune_approvals(&mut self, admins: &Admins) {
+        if self.members_version != admins.members_version {
+            self.approvals.retain(|approver| admins.is_member(approver));
+            self.members_version = admins.members_version;
+        }
+    }
+}
+
+/// Accumulates sale proceeds for a seller until they call `withdraw_proceeds`.
+#[account]
+pub struct ProceedsVault {
+    pub seller: Pubkey,
+    pub bump: u8,
+}
+
+#[account]
+pub struct Rental {
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub renter: Pubkey,
+    pub daily_rate: u64,
+    pub max_days: u16,
+    pub end_time: i64,
+    pub bump: u8,
+}
+
+impl Rental {
+    pub fn is_rented(&self) -> bool {
+        self.renter != Pubkey::default()
+    }
+}
+
+/// Marks a signed order nonce as used, either filled or cancelled.
+#[account]
+pub struct OrderNonce {
+    pub seller: Pubkey,
+    pub nonce: u64,
+    pub bump: u8,
+}
+
+/// Order a seller signs off-chain instead of creating a `Listing`. The seller
+/// must have approved the authority PDA as delegate of their token account.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct SignedOrder {
+    pub seller: Pubkey,
+    pub mint: Pubkey,
+    pub price: u64,
+    pub expiry: i64,
+    pub nonce: u64,
+}
+
+impl SignedOrder {
+    /// Bytes the seller signs: a domain prefix, this program's ID and the
+    /// borsh-encoded order.
+    pub fn message(&self) -> Result<Vec<u8>> {
+        let mut message = ORDER_MESSAGE_PREFIX.to_vec();
+        message.extend_from_slice(crate::ID.as_ref());
+        self.serialize(&mut message)?;
+        Ok(message)
+    }
+}
+
+#[event]
+pub struct NftSold {
+    pub mint: Pubkey,
+    pub seller: Pubkey,
+    pub buyer: Pubkey,
+    pub price: u64,
+    pub marketplace_fee: u64,
+    pub referrer: Option<Pubkey>,
+    pub referral_fee: u64,
+}
+
+/// Accounts shared by every listing bought in one `buy_nft` or `buy_many`.
+struct Purchase<'a, 'info> {
+    buyer: AccountInfo<'info>,
+    marketplace: &'a Marketplace,
+    treasury: AccountInfo<'info>,
+    referrer: Option<AccountInfo<'info>>,
+    pda: AccountInfo<'info>,
+    authority_bump: u8,
+    token_program: AccountInfo<'info>,
+    system_program: &'a Program<'info, System>,
+}
+
+/// Accounts of a single listing being bought, already validated.
+struct PurchasedListing<'a, 'info> {
+    listing: &'a Account<'info, Listing>,
+    mint: AccountInfo<'info>,
+    escrow_token_account: AccountInfo<'info>,
+    buyer_token_account: AccountInfo<'info>,
+    /// Seller wallet or proceeds vault
+    payout: AccountInfo<'info>,
+    metadata_account: &'a AccountInfo<'info>,
+    collection_stats: &'a mut Option<Account<'info, CollectionStats>>,
+}
+
+impl<'info> Purchase<'_, 'info> {
+    /// Splits the price between seller, marketplace and referrer, moves the
+    /// NFT to the buyer and closes the listing.
+    fn settle(&self, item: PurchasedListing<'_, 'info>) -> Result<()> {
+        let listing = item.listing;
+
+        // Split the price between seller, marketplace and referrer
+        let marketplace_fee = self.marketplace.marketplace_fee(listing.price)?;
+        let referral_fee = match self.referrer {
+            Some(_) => self.marketplace.referral_fee(marketplace_fee)?,
+            None => 0,
+        };
+        let seller_proceeds = listing.price - marketplace_fee;
+
+        // Transfer SOL from buyer to seller
+        transfer_lamports(
+            &self.buyer,
+            &item.payout,
+            self.system_program,
+            seller_proceeds,
+        )?;
+
+        // Transfer marketplace fee, minus the referral share, to the treasury
+        transfer_lamports(
+            &self.buyer,
+            &self.treasury,
+            self.system_program,
+            marketplace_fee - referral_fee,
+        )?;
+
+        if let Some(referrer) = &self.referrer {
+            transfer_lamports(&self.buyer, referrer, self.system_program, referral_fee)?;
+        }
+
+        // Transfer NFT from escrow to buyer
+        let seeds = &[AUTHORITY_SEED, &[self.authority_bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        transfer_checked(
+            CpiContext::new_with_signer(
+                self.token_program.clone(),
+                TransferChecked {
+                    from: item.escrow_token_account,
+                    to: item.buyer_token_account,
+                    mint: item.mint,
+                    authority: self.pda.clone(),
+                },
+                signer_seeds,
+            ),
+            1,
+            0,
+        )?;
+
+        emit!(NftSold {
+            mint: listing.mint,
+            seller: listing.seller,
+            buyer: self.buyer.key(),
+            price: listing.price,
+            marketplace_fee,
+            referrer: self.referrer.as_ref().map(|r| r.key()),
+            referral_fee,
+        });
+
+        if let Some(stats) = collection_stats_for(item.metadata_account, item.collection_stats)? {
+            stats.listed_count = stats.listed_count.saturating_sub(1);
+            stats.listed_value = stats.listed_value.saturating_sub(listing.price);
+            stats.sales_count = stats.sales_count.saturating_add(1);
+            stats.total_volume = stats.total_volume.saturating_add(listing.price);
+            stats.last_sale_price = listing.price;
+        }
+
+        // Close the listing, returning its rent alongside the proceeds
+        listing.close(item.payout)
+    }
+}
+
+fn apply_basis_points(amount: u64, bps: u16) -> Result<u64> {
+    let value = (amount as u128)
+        .checked_mul(bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / MAX_BASIS_POINTS as u128;
+    u64::try_from(value).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+/// Returns the verified Metaplex collection of the mint behind
+/// `metadata_account`, if it has one.
+fn verified_collection(metadata_account: &AccountInfo) -> Result<Option<Pubkey>> {
+    if metadata_account.data_is_empty() {
+        return Ok(None);
+    }
+    let metadata = MetadataAccount::safe_deserialize(&metadata_account.try_borrow_data()?)
+        .map_err(|_| ErrorCode::InvalidMetadata)?;
+    Ok(metadata
+        .collection
+        .filter(|collection| collection.verified)
+        .map(|collection| collection.key))
+}
+
+/// Seed of the `CollectionStats` that `list_nft` creates for the mint behind
+/// `metadata_account`, which must belong to a verified collection.
+fn stats_collection(metadata_account: &AccountInfo) -> Result<[u8; 32]> {
+    let collection =
+        verified_collection(metadata_account)?.ok_or(ErrorCode::CollectionStatsMismatch)?;
+    Ok(collection.to_bytes())
+}
+
+/// Returns the stats account to update for the mint behind `metadata_account`.
+/// Mints in a verified collection must pass that collection's stats account,
+/// all other mints must pass none.
+fn collection_stats_for<'a, 'info>(
+    metadata_account: &AccountInfo<'info>,
+    collection_stats: &'a mut Option<Account<'info, CollectionStats>>,
+) -> Result<Option<&'a mut Account<'info, CollectionStats>>> {
+    stats_for_collection(verified_collection(metadata_account)?, collection_stats)
+}
+
+/// `collection_stats_for` with the mint's verified collection already read.
+fn stats_for_collection<'a, 'info>(
+    collection: Option<Pubkey>,
+    collection_stats: &'a mut Option<Account<'info, CollectionStats>>,
+) -> Result<Option<&'a mut Account<'info, CollectionStats>>> {
+    match (collection, collection_stats.as_mut()) {
+        (None, None) => Ok(None),
+        (Some(collection), Some(stats)) if stats.collection == collection => Ok(Some(stats)),
+        (Some(_), None) => err!(ErrorCode::CollectionStatsRequired),
+        _ => err!(ErrorCode::CollectionStatsMismatch),
+    }
+}
+
+/// Fails when the marketplace's collection allowlist exists, is strict and
+/// does not contain `collection`. Listing is open until the allowlist is
+/// initialized.
+fn check_allowlist(allowlist: &AccountInfo, collection: Option<Pubkey>) -> Result<()> {
+    if allowlist.data_is_empty() {
+        return Ok(());
+    }
+    let allowlist = CollectionAllowlist::try_deserialize(&mut &allowlist.try_borrow_data()?[..])?;
+    require!(
+        allowlist.allows(collection),
+        ErrorCode::CollectionNotAllowed
+    );
+
+    Ok(())
+}
+
+/// Checks a new admin set: 1 to `MAX_ADMINS` distinct members with known
+/// roles, and a threshold they can reach.
+fn validate_admins(members: &[AdminMember], threshold: u8) -> Result<()> {
+    require!(
+        !members.is_empty() && members.len() <= MAX_ADMINS,
+        ErrorCode::InvalidAdminConfig
+    );
+    require!(
+        threshold > 0 && threshold as usize <= members.len(),
+        ErrorCode::InvalidAdminConfig
+    );
+    for (i, member) in members.iter().enumerate() {
+        require!(
+            member.roles & !ALL_ROLES == 0,
+            ErrorCode::InvalidAdminConfig
+        );
+        require!(
+            members[..i].iter().all(|other| other.key != member.key),
+            ErrorCode::InvalidAdminConfig
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks that `signer` may act with `role`. Before `init_admins` the
+/// marketplace authority holds every role; afterwards the authority is the
+/// `Admins` PDA and the signer needs the role there.
+fn require_role(
+    marketplace: &Marketplace,
+    admins: &Option<Account<Admins>>,
+    signer: &Pubkey,
+    role: u8,
+) -> Result<()> {
+    match admins {
+        Some(admins) if marketplace.authority == admins.key() => {
+            require!(admins.has_role(signer, role), ErrorCode::MissingRole);
+        }
+        _ => require_keys_eq!(
+            marketplace.authority,
+            *signer,
+            ErrorCode::UnauthorizedAuthority
+        ),
+    }
+
+    Ok(())
+}
+
+/// Fails when admins exist and have paused the marketplace.
+fn require_not_paused(admins: &AccountInfo) -> Result<()> {
+    if admins.data_is_empty() {
+        return Ok(());
+    }
+    let admins = Admins::try_deserialize(&mut &admins.try_borrow_data()?[..])?;
+    require!(!admins.paused, ErrorCode::MarketplacePaused);
+
+    Ok(())
+}
+
+/// Splits `remaining_accounts` into `count` groups of `group_len`.
+fn batch_groups<'a, 'info>(
+    remaining_accounts: &'a [AccountInfo<'info>],
+    count: usize,
+    group_len: usize,
+) -> Result<std::slice::ChunksExact<'a, AccountInfo<'info>>> {
+    require!(
+        count > 0 && remaining_accounts.len() == count * group_len,
+        ErrorCode::InvalidBatchAccounts
+    );
+    Ok(remaining_accounts.chunks_exact(group_len))
+}
+
+/// Seller the payout account of a swept listing belongs to: the wallet
+/// itself, or the owner of a proceeds vault.
+fn sweep_payout_seller<'info>(payout: &'info AccountInfo<'info>) -> Result<Pubkey> {
+    if payout.owner != &crate::ID {
+        return Ok(payout.key());
+    }
+    let vault = Account::<ProceedsVault>::try_from(payout)
+        .map_err(|_| ErrorCode::InvalidProceedsDestination)?;
+    let vault_key = Pubkey::create_program_address(
+        &[PROCEEDS_SEED, vault.seller.as_ref(), &[vault.bump]],
+        &crate::ID,
+    )
+    .map_err(|_| ErrorCode::InvalidProceedsDestination)?;
+    require_keys_eq!(
+        payout.key(),
+        vault_key,
+        ErrorCode::InvalidProceedsDestination
+    );
+    Ok(vault.seller)
+}
+
+/// Loads collection stats passed as a remaining account, where the program ID
+/// stands for none like it does for Anchor's optional accounts.
+fn optional_collection_stats<'info>(
+    info: &'info AccountInfo<'info>,
+) -> Result<Option<Account<'info, CollectionStats>>> {
+    if info.key() == crate::ID {
+        return Ok(None);
+    }
+    Ok(Some(Account::try_from(info)?))
+}
+
+/// Resolves where a seller's proceeds go: their wallet or their proceeds
+/// vault. Exactly one of the two must be supplied.
+fn proceeds_destination<'info>(
+    seller: &Option<AccountInfo<'info>>,
+    proceeds_vault: &Option<Account<'info, ProceedsVault>>,
+) -> Result<AccountInfo<'info>> {
+    match (seller, proceeds_vault) {
+        (Some(seller), None) => Ok(seller.clone()),
+        (None, Some(vault)) => Ok(vault.to_account_info()),
+        _ => err!(ErrorCode::InvalidProceedsDestination),
+    }
+}
+
+/// Checks that the instruction before the current one is an ed25519 program
+/// instruction verifying `signer`'s signature over `message`.
+fn verify_ed25519_signature(
+    instructions_sysvar: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> Result<()> {
+    let current_index = load_current_index_checked(instructions_sysvar)?;
+    require!(current_index > 0, ErrorCode::MissingSignatureVerification);
+
+    let ix = load_instruction_at_checked(current_index as usize - 1, instructions_sysvar)?;
+    require!(
+        ix.program_id == ed25519_program::ID && ix.accounts.is_empty(),
+        ErrorCode::MissingSignatureVerification
+    );
+
+    // Layout: num_signatures (u8), padding (u8), then one offsets struct of
+    // seven u16s, all referring to data inside this same instruction
+    let data = &ix.data;
+    require!(
+        data.len() >= ED25519_HEADER_LEN && data[0] == 1,
+        ErrorCode::InvalidSignatureVerification
+    );
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let signature_ix_index = read_u16(4);
+    let public_key_offset = read_u16(6) as usize;
+    let public_key_ix_index = read_u16(8);
+    let message_offset = read_u16(10) as usize;
+    let message_size = read_u16(12) as usize;
+    let message_ix_index = read_u16(14);
+
+    require!(
+        signature_ix_index == u16::MAX
+            && public_key_ix_index == u16::MAX
+            && message_ix_index == u16::MAX,
+        ErrorCode::InvalidSignatureVerification
+    );
+
+    let public_key = data
+        .get(public_key_offset..public_key_offset + 32)
+        .ok_or(ErrorCode::InvalidSignatureVerification)?;
+    let signed_message = data
+        .get(message_offset..message_offset + message_size)
+        .ok_or(ErrorCode::InvalidSignatureVerification)?;
+
+    require!(
+        public_key == signer.as_ref() && signed_message == message,
+        ErrorCode::InvalidSignatureVerification
+    );
+
+    Ok(())
+}
+
+/// Creates the program-owned PDA `account` like Anchor's `init`. A PDA that
+/// already holds lamports, e.g. dust sent to block the listing, cannot be
+/// created with `create_account`, so it is topped up to rent exemption,
+/// allocated and assigned instead.
+fn create_pda_account<'info>(
+    payer: &AccountInfo<'info>,
+    account: &AccountInfo<'info>,
+    system_program: &Program<'info, System>,
+    space: usize,
+    seeds: &[&[u8]],
+) -> Result<()> {
+    let rent = Rent::get()?.minimum_balance(space);
+    let system_program_info = system_program.to_account_info();
+
+    if account.lamports() == 0 {
+        return create_account(
+            CpiContext::new_with_signer(
+                system_program_info,
+                CreateAccount {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+                &[seeds],
+            ),
+            rent,
+            space as u64,
+            &crate::ID,
+        );
+    }
+
+    transfer_lamports(
+        payer,
+        account,
+        system_program,
+        rent.saturating_sub(account.lamports()),
+    )?;
+    allocate(
+        CpiContext::new_with_signer(
+            system_program_info.clone(),
+            Allocate {
+                account_to_allocate: account.clone(),
+            },
+            &[seeds],
+        ),
+        space as u64,
+    )?;
+    assign(
+        CpiContext::new_with_signer(
+            system_program_info,
+            Assign {
+                account_to_assign: account.clone(),
+            },
+            &[seeds],
+        ),
+        &crate::ID,
+    )
+}
+
+fn transfer_lamports<'info>(
+    from: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    system_program: &Program<'info, System>,
+    amount: u64,
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+
+    invoke(
+        &system_instruction::transfer(from.key, to.key, amount),
+        &[from.clone(), to.clone(), system_program.to_account_info()],
+    )?;
+
+    Ok(())
 }
 
 #[error_code]
@@ -438,4 +2977,78 @@ pub enum ErrorCode {
     UnauthorizedSeller,
     #[msg("Invalid price - must be greater than 0")]
     InvalidPrice,
+    #[msg("Rental duration must be between 1 and the listing's max days")]
+    InvalidRentalDuration,
+    #[msg("NFT is currently rented")]
+    RentalActive,
+    #[msg("NFT is not currently rented")]
+    RentalNotActive,
+    #[msg("Rental period has not ended yet")]
+    RentalNotExpired,
+    #[msg("Marketplace PDA is not the mint's freeze authority")]
+    FreezeAuthorityMismatch,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Basis points must not exceed 10000")]
+    InvalidBasisPoints,
+    #[msg("Signer is not the marketplace authority")]
+    UnauthorizedAuthority,
+    #[msg("Buyer cannot refer their own purchase")]
+    SelfReferral,
+    #[msg("Pass either the seller or the seller's proceeds vault, not both")]
+    InvalidProceedsDestination,
+    #[msg("No proceeds available to withdraw")]
+    NoProceeds,
+    #[msg("Metadata account could not be deserialized")]
+    InvalidMetadata,
+    #[msg("Collection stats account is required for mints in a verified collection")]
+    CollectionStatsRequired,
+    #[msg("Collection stats account does not match the mint's verified collection")]
+    CollectionStatsMismatch,
+    #[msg("Listing has already been migrated")]
+    ListingAlreadyMigrated,
+    #[msg("Account is not a listing for this mint")]
+    InvalidListingAccount,
+    #[msg("Signed order has expired")]
+    OrderExpired,
+    #[msg("Seller token account does not hold this NFT")]
+    InvalidSellerTokenAccount,
+    #[msg("Seller has not approved the marketplace as delegate")]
+    MissingDelegation,
+    #[msg("Missing ed25519 signature verification instruction")]
+    MissingSignatureVerification,
+    #[msg("Signature verification does not match the order")]
+    InvalidSignatureVerification,
+    #[msg("Batch accounts must be one complete, correctly derived group per NFT")]
+    InvalidBatchAccounts,
+    #[msg("Listing price does not match the expected price")]
+    PriceMismatch,
+    #[msg("Sweep would exceed the maximum total spend")]
+    MaxSpendExceeded,
+    #[msg("Collection is already on the allowlist")]
+    CollectionAlreadyAllowed,
+    #[msg("Collection allowlist is full")]
+    AllowlistFull,
+    #[msg("Collection is not on the allowlist")]
+    CollectionNotOnAllowlist,
+    #[msg("Only mints of an allowlisted, verified collection may be listed")]
+    CollectionNotAllowed,
+    #[msg("Admins need 1 to 10 distinct members with known roles and a reachable threshold")]
+    InvalidAdminConfig,
+    #[msg("Signer is not an admin")]
+    NotAdmin,
+    #[msg("Admin lacks the role for this action")]
+    MissingRole,
+    #[msg("Admin already approved this proposal")]
+    AlreadyApproved,
+    #[msg("Proposal does not have enough approvals")]
+    ThresholdNotMet,
+    #[msg("Fee increase is still timelocked")]
+    TimelockActive,
+    #[msg("Marketplace is paused")]
+    MarketplacePaused,
+    #[msg("Proposal has expired")]
+    ProposalExpired,
+    #[msg("Only the proposer can cancel a proposal before it expires")]
+    ProposalNotExpired,
 }
\ No newline at end of file
@@ -0,0 +1,571 @@
+mod common;
+
+use anchor_lang::{prelude::Pubkey, solana_program::instruction::Instruction};
+use common::{assert_error, ix, TestContext, FEE_BPS, REFERRAL_BPS, SOL};
+use nft_marketplace::{
+    accounts, instruction, AdminAction, AdminMember, Admins, CollectionAllowlist, ErrorCode,
+    Marketplace, Proposal, ALL_ROLES, FEE_INCREASE_TIMELOCK, MAX_ADMINS, PROPOSAL_LIFETIME,
+    ROLE_ALLOWLIST_CURATOR, ROLE_FEE_MANAGER, ROLE_PAUSER,
+};
+use nft_marketplace_client::{
+    instructions::{BuyNft, DelistNft, ListNft, Payout},
+    pda,
+};
+use solana_sdk::signature::{Keypair, Signer};
+
+/// Three admins needing two approvals: `owner` holds every role, `fees` can
+/// manage fees and `ops` can pause and curate the allowlist.
+struct Board {
+    owner: Keypair,
+    fees: Keypair,
+    ops: Keypair,
+}
+
+fn member(keypair: &Keypair, roles: u8) -> AdminMember {
+    AdminMember {
+        key: keypair.pubkey(),
+        roles,
+    }
+}
+
+fn init_admins(authority: &Keypair, members: Vec<AdminMember>, threshold: u8) -> Instruction {
+    ix(
+        accounts::InitAdmins {
+            authority: authority.pubkey(),
+            marketplace: pda::marketplace().0,
+            admins: pda::admins().0,
+            system_program: anchor_lang::system_program::ID,
+        },
+        instruction::InitAdmins { members, threshold },
+    )
+}
+
+fn board(ctx: &mut TestContext) -> Board {
+    let authority = ctx.authority.insecure_clone();
+    let board = Board {
+        owner: ctx.funded_keypair(),
+        fees: ctx.funded_keypair(),
+        ops: ctx.funded_keypair(),
+    };
+    let members = vec![
+        member(&board.owner, ALL_ROLES),
+        member(&board.fees, ROLE_FEE_MANAGER),
+        member(&board.ops, ROLE_PAUSER | ROLE_ALLOWLIST_CURATOR),
+    ];
+    ctx.send(&[init_admins(&authority, members, 2)], &[&authority])
+        .unwrap();
+    board
+}
+
+fn next_proposal(ctx: &TestContext) -> u64 {
+    ctx.account::<Admins>(&pda::admins().0)
+        .unwrap()
+        .proposal_count
+}
+
+/// Proposes `action` and returns the new proposal's ID.
+fn propose(ctx: &mut TestContext, proposer: &Keypair, action: AdminAction) -> u64 {
+    let id = next_proposal(ctx);
+    ctx.send(&[propose_ix(proposer, id, action)], &[proposer])
+        .unwrap();
+    id
+}
+
+fn propose_ix(proposer: &Keypair, id: u64, action: AdminAction) -> Instruction {
+    ix(
+        accounts::ProposeAdminAction {
+            proposer: proposer.pubkey(),
+            admins: pda::admins().0,
+            proposal: pda::proposal(id).0,
+            system_program: anchor_lang::system_program::ID,
+        },
+        instruction::ProposeAdminAction { action },
+    )
+}
+
+fn approve(member: &Keypair, id: u64) -> Instruction {
+    ix(
+        accounts::ApproveAdminAction {
+            member: member.pubkey(),
+            admins: pda::admins().0,
+            proposal: pda::proposal(id).0,
+        },
+        instruction::ApproveAdminAction {},
+    )
+}
+
+fn execute(executor: &Keypair, proposer: &Pubkey, id: u64) -> Instruction {
+    ix(
+        accounts::ExecuteAdminAction {
+            executor: executor.pubkey(),
+            admins: pda::admins().0,
+            marketplace: pda::marketplace().0,
+            proposal: pda::proposal(id).0,
+            proposer: *proposer,
+        },
+        instruction::ExecuteAdminAction {},
+    )
+}
+
+fn cancel(signer: &Keypair, proposer: &Pubkey, id: u64) -> Instruction {
+    ix(
+        accounts::CancelProposal {
+            signer: signer.pubkey(),
+            proposal: pda::proposal(id).0,
+            proposer: *proposer,
+        },
+        instruction::CancelProposal {},
+    )
+}
+
+fn set_paused(pauser: &Keypair, paused: bool) -> Instruction {
+    ix(
+        accounts::SetPaused {
+            pauser: pauser.pubkey(),
+            admins: pda::admins().0,
+        },
+        instruction::SetPaused { paused },
+    )
+}
+
+fn update_fees(fee_bps: u16) -> AdminAction {
+    AdminAction::UpdateFees {
+        fee_bps,
+        referral_bps: REFERRAL_BPS,
+    }
+}
+
+fn marketplace(ctx: &TestContext) -> Marketplace {
+    ctx.account(&pda::marketplace().0).unwrap()
+}
+
+#[test]
+fn hands_marketplace_to_admins() {
+    let mut ctx = TestContext::new();
+    let authority = ctx.authority.insecure_clone();
+    board(&mut ctx);
+
+    assert_eq!(marketplace(&ctx).authority, pda::admins().0);
+    let admins: Admins = ctx.account(&pda::admins().0).unwrap();
+    assert_eq!(admins.members.len(), 3);
+    assert_eq!(admins.threshold, 2);
+    assert!(!admins.paused);
+
+    let result = ctx.send(
+        &[ix(
+            accounts::UpdateMarketplace {
+                authority: authority.pubkey(),
+                treasury: ctx.treasury,
+                marketplace: pda::marketplace().0,
+            },
+            instruction::UpdateMarketplace {
+                fee_bps: 0,
+                referral_bps: 0,
+            },
+        )],
+        &[&authority],
+    );
+    assert_error(result, ErrorCode::UnauthorizedAuthority);
+}
+
+#[test]
+fn rejects_invalid_admin_sets() {
+    let mut ctx = TestContext::new();
+    let authority = ctx.authority.insecure_clone();
+    let admin = ctx.funded_keypair();
+
+    let invalid = [
+        (vec![], 1),
+        (vec![member(&admin, ALL_ROLES)], 0),
+        (vec![member(&admin, ALL_ROLES)], 2),
+        (
+            vec![member(&admin, ALL_ROLES), member(&admin, ROLE_PAUSER)],
+            1,
+        ),
+        (vec![member(&admin, 1 << 7)], 1),
+    ];
+    for (members, threshold) in invalid {
+        let result = ctx.send(
+            &[init_admins(&authority, members, threshold)],
+            &[&authority],
+        );
+        assert_error(result, ErrorCode::InvalidAdminConfig);
+    }
+}
+
+#[test]
+fn executes_fee_decrease_once_threshold_is_met() {
+    let mut ctx = TestContext::new();
+    let board = board(&mut ctx);
+    let id = propose(&mut ctx, &board.fees, update_fees(100));
+
+    let result = ctx.send(
+        &[execute(&board.fees, &board.fees.pubkey(), id)],
+        &[&board.fees],
+    );
+    assert_error(result, ErrorCode::ThresholdNotMet);
+
+    ctx.send(&[approve(&board.owner, id)], &[&board.owner])
+        .unwrap();
+    let proposer_before = ctx.balance(&board.fees.pubkey());
+    ctx.send(
+        &[execute(&board.owner, &board.fees.pubkey(), id)],
+        &[&board.owner],
+    )
+    .unwrap();
+
+    assert_eq!(marketplace(&ctx).fee_bps, 100);
+    // The proposal is closed and its rent goes back to the proposer
+    assert!(ctx.svm.get_account(&pda::proposal(id).0).is_none());
+    assert!(ctx.balance(&board.fees.pubkey()) > proposer_before);
+}
+
+#[test]
+fn holds_fee_increase_until_timelock_passes() {
+    let mut ctx = TestContext::new();
+    let board = board(&mut ctx);
+    let id = propose(&mut ctx, &board.fees, update_fees(FEE_BPS + 100));
+    ctx.send(&[approve(&board.ops, id)], &[&board.ops]).unwrap();
+
+    let result = ctx.send(
+        &[execute(&board.ops, &board.fees.pubkey(), id)],
+        &[&board.ops],
+    );
+    assert_error(result, ErrorCode::TimelockActive);
+    assert_eq!(marketplace(&ctx).fee_bps, FEE_BPS);
+
+    ctx.warp_to(ctx.now() + FEE_INCREASE_TIMELOCK);
+    ctx.send(
+        &[execute(&board.ops, &board.fees.pubkey(), id)],
+        &[&board.ops],
+    )
+    .unwrap();
+
+    assert_eq!(marketplace(&ctx).fee_bps, FEE_BPS + 100);
+}
+
+#[test]
+fn sets_treasury_through_proposal() {
+    let mut ctx = TestContext::new();
+    let board = board(&mut ctx);
+    let new_treasury = Pubkey::new_unique();
+    let id = propose(
+        &mut ctx,
+        &board.owner,
+        AdminAction::SetTreasury {
+            treasury: new_treasury,
+        },
+    );
+    ctx.send(&[approve(&board.fees, id)], &[&board.fees])
+        .unwrap();
+    ctx.send(
+        &[execute(&board.fees, &board.owner.pubkey(), id)],
+        &[&board.fees],
+    )
+    .unwrap();
+
+    assert_eq!(marketplace(&ctx).treasury, new_treasury);
+}
+
+#[test]
+fn rejects_proposals_without_role_or_membership() {
+    let mut ctx = TestContext::new();
+    let board = board(&mut ctx);
+    let outsider = ctx.funded_keypair();
+    let id = next_proposal(&ctx);
+
+    let result = ctx.send(&[propose_ix(&board.ops, id, update_fees(0))], &[&board.ops]);
+    assert_error(result, ErrorCode::MissingRole);
+
+    let result = ctx.send(&[propose_ix(&outsider, id, update_fees(0))], &[&outsider]);
+    assert_error(result, ErrorCode::NotAdmin);
+
+    let id = propose(&mut ctx, &board.fees, update_fees(0));
+    let result = ctx.send(&[approve(&outsider, id)], &[&outsider]);
+    assert_error(result, ErrorCode::NotAdmin);
+
+    let result = ctx.send(&[approve(&board.fees, id)], &[&board.fees]);
+    assert_error(result, ErrorCode::AlreadyApproved);
+}
+
+#[test]
+fn replaces_members_and_ignores_stale_approvals() {
+    let mut ctx = TestContext::new();
+    let board = board(&mut ctx);
+    let newcomer = ctx.funded_keypair();
+
+    // Approved by `fees`, who is about to be removed
+    let stale = propose(&mut ctx, &board.fees, update_fees(0));
+
+    let id = propose(
+        &mut ctx,
+        &board.ops,
+        AdminAction::SetMembers {
+            members: vec![
+                member(&board.owner, ALL_ROLES),
+                member(&board.ops, ROLE_PAUSER),
+                member(&newcomer, ROLE_FEE_MANAGER),
+            ],
+            threshold: 2,
+        },
+    );
+    ctx.send(&[approve(&board.owner, id)], &[&board.owner])
+        .unwrap();
+    ctx.send(
+        &[execute(&board.owner, &board.ops.pubkey(), id)],
+        &[&board.owner],
+    )
+    .unwrap();
+
+    let admins: Admins = ctx.account(&pda::admins().0).unwrap();
+    assert!(admins.is_member(&newcomer.pubkey()));
+    assert!(!admins.is_member(&board.fees.pubkey()));
+
+    ctx.send(&[approve(&newcomer, stale)], &[&newcomer])
+        .unwrap();
+    let proposal: Proposal = ctx.account(&pda::proposal(stale).0).unwrap();
+    assert_eq!(proposal.approvals, vec![newcomer.pubkey()]);
+    assert_eq!(proposal.members_version, admins.members_version);
+    let result = ctx.send(
+        &[execute(&newcomer, &board.fees.pubkey(), stale)],
+        &[&newcomer],
+    );
+    assert_error(result, ErrorCode::ThresholdNotMet);
+
+    // Current members can still carry the proposal
+    ctx.send(&[approve(&board.owner, stale)], &[&board.owner])
+        .unwrap();
+    ctx.send(
+        &[execute(&newcomer, &board.fees.pubkey(), stale)],
+        &[&newcomer],
+    )
+    .unwrap();
+    assert_eq!(marketplace(&ctx).fee_bps, 0);
+}
+
+#[test]
+fn rotates_full_admin_set_during_open_proposal() {
+    let mut ctx = TestContext::new();
+    let authority = ctx.authority.insecure_clone();
+    let old_board: Vec<Keypair> = (0..MAX_ADMINS).map(|_| ctx.funded_keypair()).collect();
+    let new_board: Vec<Keypair> = (0..MAX_ADMINS).map(|_| ctx.funded_keypair()).collect();
+    let members = |board: &[Keypair]| -> Vec<AdminMember> {
+        board.iter().map(|keypair| member(keypair, ALL_ROLES)).collect()
+    };
+    let threshold = MAX_ADMINS as u8;
+    ctx.send(
+        &[init_admins(&authority, members(&old_board), threshold)],
+        &[&authority],
+    )
+    .unwrap();
+
+    // Every old member approves a proposal that stays open across the rotation
+    let open = propose(&mut ctx, &old_board[0], update_fees(0));
+    for approver in &old_board[1..] {
+        ctx.send(&[approve(approver, open)], &[approver]).unwrap();
+    }
+    let rotation = propose(
+        &mut ctx,
+        &old_board[0],
+        AdminAction::SetMembers {
+            members: members(&new_board),
+            threshold,
+        },
+    );
+    for approver in &old_board[1..] {
+        ctx.send(&[approve(approver, rotation)], &[approver])
+            .unwrap();
+    }
+    ctx.send(
+        &[execute(&old_board[0], &old_board[0].pubkey(), rotation)],
+        &[&old_board[0]],
+    )
+    .unwrap();
+
+    // The old approvals are pruned, so the new board fits and must approve anew
+    let result = ctx.send(
+        &[execute(&new_board[0], &old_board[0].pubkey(), open)],
+        &[&new_board[0]],
+    );
+    assert_error(result, ErrorCode::ThresholdNotMet);
+    for approver in &new_board {
+        ctx.send(&[approve(approver, open)], &[approver]).unwrap();
+    }
+    let proposal: Proposal = ctx.account(&pda::proposal(open).0).unwrap();
+    assert_eq!(proposal.approvals.len(), MAX_ADMINS);
+    ctx.send(
+        &[execute(&new_board[0], &old_board[0].pubkey(), open)],
+        &[&new_board[0]],
+    )
+    .unwrap();
+
+    assert_eq!(marketplace(&ctx).fee_bps, 0);
+}
+
+#[test]
+fn rejects_expired_proposal() {
+    let mut ctx = TestContext::new();
+    let board = board(&mut ctx);
+    let id = propose(&mut ctx, &board.fees, update_fees(0));
+    ctx.warp_to(ctx.now() + PROPOSAL_LIFETIME);
+
+    let result = ctx.send(&[approve(&board.owner, id)], &[&board.owner]);
+    assert_error(result, ErrorCode::ProposalExpired);
+
+    let result = ctx.send(
+        &[execute(&board.fees, &board.fees.pubkey(), id)],
+        &[&board.fees],
+    );
+    assert_error(result, ErrorCode::ProposalExpired);
+    assert_eq!(marketplace(&ctx).fee_bps, FEE_BPS);
+}
+
+#[test]
+fn proposer_cancels_open_proposal() {
+    let mut ctx = TestContext::new();
+    let board = board(&mut ctx);
+    let id = propose(&mut ctx, &board.fees, update_fees(0));
+    let proposer_before = ctx.balance(&board.fees.pubkey());
+    let rent = ctx.balance(&pda::proposal(id).0);
+
+    let result = ctx.send(
+        &[cancel(&board.owner, &board.fees.pubkey(), id)],
+        &[&board.owner],
+    );
+    assert_error(result, ErrorCode::ProposalNotExpired);
+
+    let relayer = ctx.funded_keypair();
+    ctx.send(
+        &[cancel(&board.fees, &board.fees.pubkey(), id)],
+        &[&relayer, &board.fees],
+    )
+    .unwrap();
+
+    assert!(ctx.account::<Proposal>(&pda::proposal(id).0).is_none());
+    assert_eq!(ctx.balance(&board.fees.pubkey()) - proposer_before, rent);
+}
+
+#[test]
+fn anyone_closes_expired_proposal() {
+    let mut ctx = TestContext::new();
+    let board = board(&mut ctx);
+    let id = propose(&mut ctx, &board.fees, update_fees(0));
+    let proposer_before = ctx.balance(&board.fees.pubkey());
+    let rent = ctx.balance(&pda::proposal(id).0);
+    ctx.warp_to(ctx.now() + PROPOSAL_LIFETIME);
+
+    let outsider = ctx.funded_keypair();
+    ctx.send(&[cancel(&outsider, &board.fees.pubkey(), id)], &[&outsider])
+        .unwrap();
+
+    assert!(ctx.account::<Proposal>(&pda::proposal(id).0).is_none());
+    assert_eq!(ctx.balance(&board.fees.pubkey()) - proposer_before, rent);
+}
+
+#[test]
+fn pauses_listing_and_buying() {
+    let mut ctx = TestContext::new();
+    let board = board(&mut ctx);
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let listed = ctx.listed_nft(&seller, SOL);
+    let unlisted = ctx.mint_nft(&seller);
+
+    let result = ctx.send(&[set_paused(&board.fees, true)], &[&board.fees]);
+    assert_error(result, ErrorCode::MissingRole);
+
+    ctx.send(&[set_paused(&board.ops, true)], &[&board.ops])
+        .unwrap();
+
+    let buy = BuyNft {
+        buyer: buyer.pubkey(),
+        seller: seller.pubkey(),
+        mint: listed,
+        treasury: ctx.treasury,
+        payout: Payout::Wallet,
+        referrer: None,
+        collection: None,
+    }
+    .instruction();
+    let result = ctx.send(std::slice::from_ref(&buy), &[&buyer]);
+    assert_error(result, ErrorCode::MarketplacePaused);
+
+    let list = ListNft {
+        seller: seller.pubkey(),
+        mint: unlisted,
+        price: SOL,
+        collection: None,
+    }
+    .instruction();
+    let result = ctx.send(&[list], &[&seller]);
+    assert_error(result, ErrorCode::MarketplacePaused);
+
+    ctx.send(&[set_paused(&board.ops, false)], &[&board.ops])
+        .unwrap();
+    ctx.send(&[buy], &[&buyer]).unwrap();
+
+    assert_eq!(ctx.token_amount(&buyer.pubkey(), &listed), 1);
+}
+
+#[test]
+fn delists_while_paused() {
+    let mut ctx = TestContext::new();
+    let board = board(&mut ctx);
+    let seller = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, SOL);
+    ctx.send(&[set_paused(&board.owner, true)], &[&board.owner])
+        .unwrap();
+
+    let delist = DelistNft {
+        seller: seller.pubkey(),
+        mint,
+        collection: None,
+    }
+    .instruction();
+    ctx.send(&[delist], &[&seller]).unwrap();
+
+    assert_eq!(ctx.token_amount(&seller.pubkey(), &mint), 1);
+}
+
+#[test]
+fn curator_manages_allowlist_after_handover() {
+    let mut ctx = TestContext::new();
+    let authority = ctx.authority.insecure_clone();
+    let board = board(&mut ctx);
+    let collection = Pubkey::new_unique();
+
+    let init = |authority: &Keypair| {
+        ix(
+            accounts::InitCollectionAllowlist {
+                authority: authority.pubkey(),
+                marketplace: pda::marketplace().0,
+                admins: Some(pda::admins().0),
+                collection_allowlist: pda::collection_allowlist().0,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::InitCollectionAllowlist { strict: true },
+        )
+    };
+    let add = |authority: &Keypair| {
+        ix(
+            accounts::UpdateCollectionAllowlist {
+                authority: authority.pubkey(),
+                marketplace: pda::marketplace().0,
+                admins: Some(pda::admins().0),
+                collection_allowlist: pda::collection_allowlist().0,
+            },
+            instruction::AddAllowedCollection { collection },
+        )
+    };
+
+    let result = ctx.send(&[init(&authority)], &[&authority]);
+    assert_error(result, ErrorCode::MissingRole);
+    ctx.send(&[init(&board.ops)], &[&board.ops]).unwrap();
+
+    let result = ctx.send(&[add(&board.fees)], &[&board.fees]);
+    assert_error(result, ErrorCode::MissingRole);
+    ctx.send(&[add(&board.ops)], &[&board.ops]).unwrap();
+
+    let allowlist: CollectionAllowlist = ctx.account(&pda::collection_allowlist().0).unwrap();
+    assert_eq!(allowlist.collections, vec![collection]);
+}
@@ -0,0 +1,390 @@
+//! Shared LiteSVM harness for the nft_marketplace integration tests.
+//!
+//! The tests run the SBF build of the program, so `anchor build` (or
+//! `cargo build-sbf`) must have produced `target/deploy/nft_marketplace.so`.
+//! The Token Metadata program is loaded from the committed
+//! `tests/fixtures/mpl_token_metadata.so`, which `scripts/fetch-fixtures.sh`
+//! refreshes from mainnet.
+
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use anchor_lang::{
+    prelude::Pubkey,
+    solana_program::{
+        bpf_loader_upgradeable, instruction::Instruction, program_pack::Pack, system_instruction,
+    },
+    AccountDeserialize, AccountSerialize, Discriminator, InstructionData, ToAccountMetas,
+};
+use anchor_spl::{
+    associated_token::{
+        get_associated_token_address, spl_associated_token_account::instruction as ata_instruction,
+    },
+    token::{spl_token, TokenAccount},
+};
+use borsh::BorshSerialize;
+use litesvm::{types::TransactionResult, LiteSVM};
+use mpl_token_metadata::{accounts::Metadata, types::Collection};
+use nft_marketplace::{accounts, instruction, Listing, LEGACY_LISTING_SPACE};
+use nft_marketplace_client::{
+    instructions::{ListNft, MintNft},
+    pda,
+};
+use solana_sdk::{
+    account::Account,
+    clock::Clock,
+    instruction::InstructionError,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+pub const SOL: u64 = 1_000_000_000;
+pub const FEE_BPS: u16 = 250;
+pub const REFERRAL_BPS: u16 = 2_000;
+
+pub struct TestContext {
+    pub svm: LiteSVM,
+    /// Marketplace authority, also used as a generic fee payer
+    pub authority: Keypair,
+    pub treasury: Pubkey,
+}
+
+impl TestContext {
+    /// Loads both programs and initializes the marketplace with `FEE_BPS` and
+    /// `REFERRAL_BPS`.
+    pub fn new() -> Self {
+        let mut ctx = Self::without_marketplace();
+        let authority = ctx.authority.insecure_clone();
+        let treasury = ctx.treasury;
+
+        ctx.send(
+            &[ix(
+                accounts::InitializeMarketplace {
+                    authority: authority.pubkey(),
+                    program_data: pda::program_data(),
+                    treasury,
+                    marketplace: pda::marketplace().0,
+                    system_program: anchor_lang::system_program::ID,
+                },
+                instruction::InitializeMarketplace {
+                    fee_bps: FEE_BPS,
+                    referral_bps: REFERRAL_BPS,
+                },
+            )],
+            &[&authority],
+        )
+        .expect("initialize_marketplace failed");
+
+        ctx
+    }
+
+    pub fn without_marketplace() -> Self {
+        let mut svm = LiteSVM::new();
+        let authority = Keypair::new();
+        deploy_upgradeable(
+            &mut svm,
+            &authority.pubkey(),
+            program_path("target/deploy/nft_marketplace.so"),
+        );
+        svm.add_program_from_file(mpl_token_metadata::ID, fixture_path("mpl_token_metadata.so"))
+            .expect("invalid Token Metadata fixture");
+
+        svm.airdrop(&authority.pubkey(), 100 * SOL).unwrap();
+
+        // Fund the treasury so small fees never fall below rent exemption
+        let treasury = Pubkey::new_unique();
+        svm.airdrop(&treasury, SOL).unwrap();
+
+        Self {
+            svm,
+            authority,
+            treasury,
+        }
+    }
+
+    pub fn funded_keypair(&mut self) -> Keypair {
+        let keypair = Keypair::new();
+        self.svm.airdrop(&keypair.pubkey(), 100 * SOL).unwrap();
+        keypair
+    }
+
+    /// Sends `ixs` in one transaction paid for by the first signer.
+    pub fn send(&mut self, ixs: &[Instruction], signers: &[&Keypair]) -> TransactionResult {
+        let tx = Transaction::new_signed_with_payer(
+            ixs,
+            Some(&signers[0].pubkey()),
+            signers,
+            self.svm.latest_blockhash(),
+        );
+        let result = self.svm.send_transaction(tx);
+        // Identical transactions later in a test must not be deduplicated
+        self.svm.expire_blockhash();
+        result
+    }
+
+    pub fn mint_nft(&mut self, owner: &Keypair) -> Pubkey {
+        let mint = Keypair::new();
+        let ix = MintNft {
+            signer: owner.pubkey(),
+            mint: mint.pubkey(),
+            name: "Test NFT".to_string(),
+            symbol: "TNFT".to_string(),
+            uri: "https://example.com/nft.json".to_string(),
+        }
+        .instruction();
+        self.send(&[ix], &[owner, &mint]).expect("mint_nft failed");
+        mint.pubkey()
+    }
+
+    pub fn list_nft(&mut self, seller: &Keypair, mint: &Pubkey, price: u64) {
+        let ix = ListNft {
+            seller: seller.pubkey(),
+            mint: *mint,
+            price,
+            collection: None,
+        }
+        .instruction();
+        self.send(&[ix], &[seller]).expect("list_nft failed");
+    }
+
+    /// Mints an NFT to `seller` and lists it at `price`.
+    pub fn listed_nft(&mut self, seller: &Keypair, price: u64) -> Pubkey {
+        let mint = self.mint_nft(seller);
+        self.list_nft(seller, &mint, price);
+        mint
+    }
+
+    pub fn account<T: AccountDeserialize>(&self, address: &Pubkey) -> Option<T> {
+        let account = self.svm.get_account(address)?;
+        if account.data.is_empty() {
+            return None;
+        }
+        Some(T::try_deserialize(&mut &account.data[..]).expect("failed to decode account"))
+    }
+
+    pub fn listing(&self, mint: &Pubkey) -> Option<Listing> {
+        self.account(&pda::listing(mint).0)
+    }
+
+    /// Overwrites the data of an existing Anchor account.
+    pub fn write_account<T: AccountSerialize>(&mut self, address: &Pubkey, value: &T) {
+        let mut account = self.svm.get_account(address).expect("account does not exist");
+        let mut data = Vec::new();
+        value.try_serialize(&mut data).unwrap();
+        account.data[..data.len()].copy_from_slice(&data);
+        self.svm.set_account(*address, account).unwrap();
+    }
+
+    /// Rewrites the listing for `mint` in the pre-versioning layout, storing
+    /// `listing_mint` and `bump` in place of its own.
+    pub fn write_legacy_listing(&mut self, mint: &Pubkey, listing_mint: &Pubkey, bump: u8) {
+        let listing = self.listing(mint).unwrap();
+        let mut data = Listing::DISCRIMINATOR.to_vec();
+        data.extend_from_slice(listing.seller.as_ref());
+        data.extend_from_slice(listing_mint.as_ref());
+        data.extend_from_slice(&listing.price.to_le_bytes());
+        data.push(listing.is_active as u8);
+        data.push(bump);
+        assert_eq!(data.len(), LEGACY_LISTING_SPACE);
+
+        self.set_account_data(&pda::listing(mint).0, &nft_marketplace::ID, data);
+    }
+
+    pub fn set_account_data(&mut self, address: &Pubkey, owner: &Pubkey, data: Vec<u8>) {
+        let lamports = self.svm.minimum_balance_for_rent_exemption(data.len());
+        self.svm
+            .set_account(
+                *address,
+                Account {
+                    lamports,
+                    data,
+                    owner: *owner,
+                    executable: false,
+                    rent_epoch: 0,
+                },
+            )
+            .unwrap();
+    }
+
+    pub fn token_account(&self, address: &Pubkey) -> Option<TokenAccount> {
+        self.account(address)
+    }
+
+    /// Amount held by `owner`'s associated token account for `mint`, 0 when
+    /// the account does not exist.
+    pub fn token_amount(&self, owner: &Pubkey, mint: &Pubkey) -> u64 {
+        self.token_account(&get_associated_token_address(owner, mint))
+            .map_or(0, |account| account.amount)
+    }
+
+    pub fn balance(&self, address: &Pubkey) -> u64 {
+        self.svm.get_balance(address).unwrap_or(0)
+    }
+
+    pub fn now(&self) -> i64 {
+        self.svm.get_sysvar::<Clock>().unix_timestamp
+    }
+
+    pub fn warp_to(&mut self, unix_timestamp: i64) {
+        let mut clock = self.svm.get_sysvar::<Clock>();
+        clock.unix_timestamp = unix_timestamp;
+        self.svm.set_sysvar::<Clock>(&clock);
+    }
+
+    /// Marks `mint` as a verified member of `collection` by rewriting its
+    /// Metaplex metadata, since only a collection authority could verify it
+    /// for real.
+    pub fn set_verified_collection(&mut self, mint: &Pubkey, collection: &Pubkey) {
+        let address = pda::metadata(mint);
+        let mut account = self.svm.get_account(&address).unwrap();
+        let mut metadata = Metadata::safe_deserialize(&account.data).unwrap();
+        metadata.collection = Some(Collection {
+            verified: true,
+            key: *collection,
+        });
+        let data = metadata.try_to_vec().unwrap();
+        account.data[..data.len()].copy_from_slice(&data);
+        self.svm.set_account(address, account).unwrap();
+    }
+
+    pub fn init_collection_stats(&mut self, collection: &Pubkey) -> Pubkey {
+        let payer = self.authority.insecure_clone();
+        let stats = pda::collection_stats(collection).0;
+        self.send(
+            &[ix(
+                accounts::InitCollectionStats {
+                    payer: payer.pubkey(),
+                    collection_mint: *collection,
+                    collection_stats: stats,
+                    system_program: anchor_lang::system_program::ID,
+                },
+                instruction::InitCollectionStats {},
+            )],
+            &[&payer],
+        )
+        .expect("init_collection_stats failed");
+        stats
+    }
+
+    /// Creates a plain SPL mint with `owner` as mint authority and mints one
+    /// token of it to `owner`.
+    pub fn spl_mint(&mut self, owner: &Keypair, freeze_authority: Option<&Pubkey>) -> Pubkey {
+        let mint = Keypair::new();
+        let rent = self
+            .svm
+            .minimum_balance_for_rent_exemption(spl_token::state::Mint::LEN);
+        let ata = get_associated_token_address(&owner.pubkey(), &mint.pubkey());
+        self.send(
+            &[
+                system_instruction::create_account(
+                    &owner.pubkey(),
+                    &mint.pubkey(),
+                    rent,
+                    spl_token::state::Mint::LEN as u64,
+                    &spl_token::ID,
+                ),
+                spl_token::instruction::initialize_mint2(
+                    &spl_token::ID,
+                    &mint.pubkey(),
+                    &owner.pubkey(),
+                    freeze_authority,
+                    0,
+                )
+                .unwrap(),
+                ata_instruction::create_associated_token_account(
+                    &owner.pubkey(),
+                    &owner.pubkey(),
+                    &mint.pubkey(),
+                    &spl_token::ID,
+                ),
+                spl_token::instruction::mint_to(
+                    &spl_token::ID,
+                    &mint.pubkey(),
+                    &ata,
+                    &owner.pubkey(),
+                    &[],
+                    1,
+                )
+                .unwrap(),
+            ],
+            &[owner, &mint],
+        )
+        .expect("creating SPL mint failed");
+        mint.pubkey()
+    }
+}
+
+pub fn ix(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: nft_marketplace::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+/// Asserts that the transaction failed with the given program or Anchor error.
+pub fn assert_error(result: TransactionResult, code: impl Into<u32>) {
+    let code = code.into();
+    match result {
+        Ok(_) => panic!("expected error {}, transaction succeeded", code),
+        Err(failed) => match failed.err {
+            TransactionError::InstructionError(_, InstructionError::Custom(actual)) => {
+                assert_eq!(actual, code, "logs: {:#?}", failed.meta.logs)
+            }
+            other => panic!("expected error {}, got {:?}", code, other),
+        },
+    }
+}
+
+/// Deploys the marketplace program through the upgradeable loader with
+/// `upgrade_authority`, which is the only key allowed to initialize it.
+fn deploy_upgradeable(svm: &mut LiteSVM, upgrade_authority: &Pubkey, elf: PathBuf) {
+    let elf = std::fs::read(elf).expect("missing program binary, run `anchor build` first");
+    let program_data = pda::program_data();
+
+    // UpgradeableLoaderState::ProgramData { slot: 0, upgrade_authority_address }
+    let mut data = 3u32.to_le_bytes().to_vec();
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.push(1);
+    data.extend_from_slice(upgrade_authority.as_ref());
+    data.extend_from_slice(&elf);
+    set_loader_account(svm, &program_data, data, false);
+
+    // UpgradeableLoaderState::Program { programdata_address }
+    let mut data = 2u32.to_le_bytes().to_vec();
+    data.extend_from_slice(program_data.as_ref());
+    set_loader_account(svm, &nft_marketplace::ID, data, true);
+}
+
+fn set_loader_account(svm: &mut LiteSVM, address: &Pubkey, data: Vec<u8>, executable: bool) {
+    let lamports = svm.minimum_balance_for_rent_exemption(data.len());
+    svm.set_account(
+        *address,
+        Account {
+            lamports,
+            data,
+            owner: bpf_loader_upgradeable::ID,
+            executable,
+            rent_epoch: 0,
+        },
+    )
+    .unwrap();
+}
+
+/// Path of a program dump in `tests/fixtures`.
+fn fixture_path(name: &str) -> PathBuf {
+    let path = program_path("tests/fixtures").join(name);
+    assert!(
+        path.exists(),
+        "missing fixture {}, run scripts/fetch-fixtures.sh and commit the dump",
+        path.display()
+    );
+    path
+}
+
+fn program_path(relative_to_workspace: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("../..")
+        .join(relative_to_workspace)
+}
@@ -0,0 +1,601 @@
+//! Compute-unit benchmarks for every nft_marketplace instruction.
+//!
+//! Each instruction is run once on its common path and the units it consumed
+//! are compared with `tests/compute_units.baseline`. The run fails when an
+//! instruction has no baseline or uses more than `REGRESSION_THRESHOLD_PCT`
+//! percent over it, and writes a report to `bench_output.txt` at the workspace root
+//! either way. After an intended change in cost, record new numbers with
+//!
+//! `UPDATE_CU_BASELINE=1 cargo test -p nft_marketplace --test compute_units`
+
+mod common;
+
+use std::{collections::BTreeMap, fmt::Write as _, fs, path::PathBuf};
+
+use anchor_lang::{
+    prelude::Pubkey,
+    solana_program::{instruction::Instruction, sysvar},
+};
+use anchor_spl::{associated_token::get_associated_token_address, token::spl_token};
+use common::{ix, TestContext, FEE_BPS, REFERRAL_BPS, SOL};
+use nft_marketplace::{accounts, instruction, SignedOrder, SECONDS_PER_DAY};
+use nft_marketplace_client::{
+    instructions::{
+        BatchDelistNft, BatchListNft, BatchListing, BuyMany, BuyNft, DelistNft, ListNft, MintNft,
+        Payout, SweepListing, UpdatePrice,
+    },
+    pda,
+};
+use solana_ed25519_program::new_ed25519_instruction_with_signature;
+use solana_sdk::signature::{Keypair, Signer};
+
+/// Allowed growth over the baseline before the benchmark fails
+const REGRESSION_THRESHOLD_PCT: u64 = 5;
+
+fn baseline_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/compute_units.baseline")
+}
+
+fn report_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../bench_output.txt")
+}
+
+/// Compute units consumed per instruction, in the order they were measured.
+#[derive(Default)]
+struct Measurements(Vec<(&'static str, u64)>);
+
+impl Measurements {
+    fn measure(
+        &mut self,
+        ctx: &mut TestContext,
+        name: &'static str,
+        ixs: &[Instruction],
+        signers: &[&Keypair],
+    ) {
+        let meta = ctx.send(ixs, signers).unwrap_or_else(|failed| {
+            panic!("{} failed: {:?}\n{:#?}", name, failed.err, failed.meta.logs)
+        });
+        self.0.push((name, meta.compute_units_consumed));
+    }
+}
+
+fn read_baseline() -> BTreeMap<String, u64> {
+    let contents = fs::read_to_string(baseline_path()).unwrap_or_default();
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (name, units) = line
+                .split_once(char::is_whitespace)
+                .unwrap_or_else(|| panic!("malformed baseline line: {}", line));
+            let units = units
+                .trim()
+                .parse()
+                .unwrap_or_else(|_| panic!("malformed baseline line: {}", line));
+            (name.to_string(), units)
+        })
+        .collect()
+}
+
+fn write_baseline(measurements: &Measurements) {
+    let mut contents = String::from(
+        "# Compute units per nft_marketplace instruction, see tests/compute_units.rs\n",
+    );
+    for (name, units) in &measurements.0 {
+        writeln!(contents, "{} {}", name, units).unwrap();
+    }
+    fs::write(baseline_path(), contents).expect("failed to write compute unit baseline");
+}
+
+/// Writes the report and returns the instructions that regressed or have no
+/// baseline.
+fn report(measurements: &Measurements, baseline: &BTreeMap<String, u64>) -> Vec<String> {
+    let mut report = format!(
+        "{:<24} {:>10} {:>10} {:>9}\n",
+        "instruction", "units", "baseline", "change"
+    );
+    let mut regressions = Vec::new();
+
+    for (name, units) in &measurements.0 {
+        match baseline.get(*name) {
+            Some(&expected) => {
+                let change = (*units as f64 - expected as f64) / expected as f64 * 100.0;
+                let mut line = format!(
+                    "{:<24} {:>10} {:>10} {:>+8.2}%",
+                    name, units, expected, change
+                );
+                if *units * 100 > expected * (100 + REGRESSION_THRESHOLD_PCT) {
+                    line.push_str("  REGRESSION");
+                    regressions.push(format!("{} ({} -> {})", name, expected, units));
+                }
+                writeln!(report, "{}", line).unwrap();
+            }
+            None => {
+                writeln!(
+                    report,
+                    "{:<24} {:>10} {:>10} {:>9}  MISSING",
+                    name, units, "-", "new"
+                )
+                .unwrap();
+                regressions.push(format!("{} (no baseline, measured {})", name, units));
+            }
+        }
+    }
+
+    print!("{}", report);
+    fs::write(report_path(), report).expect("failed to write compute unit report");
+    regressions
+}
+
+fn measure_all() -> Measurements {
+    let mut measurements = Measurements::default();
+    let mut ctx = TestContext::without_marketplace();
+    let authority = ctx.authority.insecure_clone();
+    let treasury = ctx.treasury;
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+
+    // Marketplace configuration
+    let marketplace = pda::marketplace().0;
+    measurements.measure(
+        &mut ctx,
+        "initialize_marketplace",
+        &[ix(
+            accounts::InitializeMarketplace {
+                authority: authority.pubkey(),
+                program_data: pda::program_data(),
+                treasury,
+                marketplace,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::InitializeMarketplace {
+                fee_bps: FEE_BPS,
+                referral_bps: REFERRAL_BPS,
+            },
+        )],
+        &[&authority],
+    );
+    measurements.measure(
+        &mut ctx,
+        "update_marketplace",
+        &[ix(
+            accounts::UpdateMarketplace {
+                authority: authority.pubkey(),
+                treasury,
+                marketplace,
+            },
+            instruction::UpdateMarketplace {
+                fee_bps: FEE_BPS,
+                referral_bps: REFERRAL_BPS,
+            },
+        )],
+        &[&authority],
+    );
+    let collection = Pubkey::new_unique();
+    measurements.measure(
+        &mut ctx,
+        "init_collection_stats",
+        &[ix(
+            accounts::InitCollectionStats {
+                payer: authority.pubkey(),
+                collection_mint: collection,
+                collection_stats: pda::collection_stats(&collection).0,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::InitCollectionStats {},
+        )],
+        &[&authority],
+    );
+
+    // Non-strict, so the listings below stay open to any mint
+    let collection_allowlist = pda::collection_allowlist().0;
+    measurements.measure(
+        &mut ctx,
+        "init_collection_allowlist",
+        &[ix(
+            accounts::InitCollectionAllowlist {
+                authority: authority.pubkey(),
+                marketplace,
+                admins: None,
+                collection_allowlist,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::InitCollectionAllowlist { strict: false },
+        )],
+        &[&authority],
+    );
+    let update_allowlist = || accounts::UpdateCollectionAllowlist {
+        authority: authority.pubkey(),
+        marketplace,
+        admins: None,
+        collection_allowlist,
+    };
+    measurements.measure(
+        &mut ctx,
+        "add_allowed_collection",
+        &[ix(
+            update_allowlist(),
+            instruction::AddAllowedCollection { collection },
+        )],
+        &[&authority],
+    );
+    measurements.measure(
+        &mut ctx,
+        "remove_allowed_collection",
+        &[ix(
+            update_allowlist(),
+            instruction::RemoveAllowedCollection { collection },
+        )],
+        &[&authority],
+    );
+    measurements.measure(
+        &mut ctx,
+        "set_allowlist_strict",
+        &[ix(
+            update_allowlist(),
+            instruction::SetAllowlistStrict { strict: false },
+        )],
+        &[&authority],
+    );
+
+    // Fixed-price listings
+    let mint = Keypair::new();
+    measurements.measure(
+        &mut ctx,
+        "mint_nft",
+        &[MintNft {
+            signer: seller.pubkey(),
+            mint: mint.pubkey(),
+            name: "Test NFT".to_string(),
+            symbol: "TNFT".to_string(),
+            uri: "https://example.com/nft.json".to_string(),
+        }
+        .instruction()],
+        &[&seller, &mint],
+    );
+    let mint = mint.pubkey();
+    measurements.measure(
+        &mut ctx,
+        "list_nft",
+        &[ListNft {
+            seller: seller.pubkey(),
+            mint,
+            price: SOL,
+            collection: None,
+        }
+        .instruction()],
+        &[&seller],
+    );
+    measurements.measure(
+        &mut ctx,
+        "update_price",
+        &[UpdatePrice {
+            seller: seller.pubkey(),
+            mint,
+            new_price: 2 * SOL,
+            collection: None,
+        }
+        .instruction()],
+        &[&seller],
+    );
+    let proceeds_vault = pda::proceeds_vault(&seller.pubkey()).0;
+    measurements.measure(
+        &mut ctx,
+        "init_proceeds_vault",
+        &[ix(
+            accounts::InitProceedsVault {
+                seller: seller.pubkey(),
+                proceeds_vault,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::InitProceedsVault {},
+        )],
+        &[&seller],
+    );
+    measurements.measure(
+        &mut ctx,
+        "buy_nft",
+        &[BuyNft {
+            buyer: buyer.pubkey(),
+            seller: seller.pubkey(),
+            mint,
+            treasury,
+            payout: Payout::ProceedsVault,
+            referrer: None,
+            collection: None,
+        }
+        .instruction()],
+        &[&buyer],
+    );
+    measurements.measure(
+        &mut ctx,
+        "withdraw_proceeds",
+        &[ix(
+            accounts::WithdrawProceeds {
+                seller: seller.pubkey(),
+                proceeds_vault,
+            },
+            instruction::WithdrawProceeds {},
+        )],
+        &[&seller],
+    );
+
+    let mint = ctx.listed_nft(&seller, SOL);
+    measurements.measure(
+        &mut ctx,
+        "delist_nft",
+        &[DelistNft {
+            seller: seller.pubkey(),
+            mint,
+            collection: None,
+        }
+        .instruction()],
+        &[&seller],
+    );
+
+    let mint = ctx.listed_nft(&seller, SOL);
+    ctx.write_legacy_listing(&mint, &mint, pda::authority().1);
+    measurements.measure(
+        &mut ctx,
+        "migrate_listing",
+        &[ix(
+            accounts::MigrateListing {
+                payer: seller.pubkey(),
+                mint,
+                listing: pda::listing(&mint).0,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::MigrateListing {},
+        )],
+        &[&seller],
+    );
+
+    // Batches of three
+    let mints: Vec<Pubkey> = (0..3).map(|_| ctx.mint_nft(&seller)).collect();
+    measurements.measure(
+        &mut ctx,
+        "batch_list",
+        &[BatchListNft {
+            seller: seller.pubkey(),
+            listings: mints
+                .iter()
+                .map(|&mint| BatchListing {
+                    mint,
+                    price: SOL,
+                    collection: None,
+                })
+                .collect(),
+        }
+        .instruction()],
+        &[&seller],
+    );
+    measurements.measure(
+        &mut ctx,
+        "batch_delist",
+        &[BatchDelistNft {
+            seller: seller.pubkey(),
+            listings: mints.iter().map(|&mint| (mint, None)).collect(),
+        }
+        .instruction()],
+        &[&seller],
+    );
+
+    let mints: Vec<Pubkey> = (0..3).map(|_| ctx.listed_nft(&seller, SOL)).collect();
+    measurements.measure(
+        &mut ctx,
+        "buy_many",
+        &[BuyMany {
+            buyer: buyer.pubkey(),
+            treasury,
+            referrer: None,
+            listings: mints
+                .iter()
+                .map(|&mint| SweepListing {
+                    mint,
+                    seller: seller.pubkey(),
+                    expected_price: SOL,
+                    payout: Payout::Wallet,
+                    collection: None,
+                })
+                .collect(),
+            max_total: 3 * SOL,
+            skip_sold: false,
+        }
+        .instruction()],
+        &[&buyer],
+    );
+
+    // Rentals
+    let authority_pda = pda::authority();
+    let list_for_rent = |mint: &Pubkey| {
+        ix(
+            accounts::ListForRent {
+                owner: seller.pubkey(),
+                mint: *mint,
+                pda: authority_pda.0,
+                owner_token_account: get_associated_token_address(&seller.pubkey(), mint),
+                escrow_token_account: pda::escrow_token_account(mint),
+                rental: pda::rental(mint).0,
+                admins: pda::admins().0,
+                token_program: anchor_spl::token::ID,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::ListForRent {
+                daily_rate: SOL / 10,
+                max_days: 7,
+            },
+        )
+    };
+    let mint = ctx.mint_nft(&seller);
+    measurements.measure(
+        &mut ctx,
+        "list_for_rent",
+        &[list_for_rent(&mint)],
+        &[&seller],
+    );
+    measurements.measure(
+        &mut ctx,
+        "rent_nft",
+        &[ix(
+            accounts::RentNFT {
+                renter: buyer.pubkey(),
+                owner: seller.pubkey(),
+                mint,
+                pda: authority_pda.0,
+                escrow_token_account: pda::escrow_token_account(&mint),
+                renter_token_account: get_associated_token_address(&buyer.pubkey(), &mint),
+                rental: pda::rental(&mint).0,
+                admins: pda::admins().0,
+                token_program: anchor_spl::token::ID,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::RentNft {
+                days: 1,
+                bump: authority_pda.1,
+            },
+        )],
+        &[&buyer],
+    );
+    ctx.warp_to(ctx.now() + SECONDS_PER_DAY);
+    measurements.measure(
+        &mut ctx,
+        "end_rental",
+        &[ix(
+            accounts::EndRental {
+                caller: seller.pubkey(),
+                owner: seller.pubkey(),
+                renter: buyer.pubkey(),
+                mint,
+                pda: authority_pda.0,
+                renter_token_account: get_associated_token_address(&buyer.pubkey(), &mint),
+                owner_token_account: get_associated_token_address(&seller.pubkey(), &mint),
+                rental: pda::rental(&mint).0,
+                token_program: anchor_spl::token::ID,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::EndRental {
+                bump: authority_pda.1,
+            },
+        )],
+        &[&seller],
+    );
+    ctx.send(&[list_for_rent(&mint)], &[&seller]).unwrap();
+    measurements.measure(
+        &mut ctx,
+        "delist_rental",
+        &[ix(
+            accounts::DelistRental {
+                owner: seller.pubkey(),
+                mint,
+                pda: authority_pda.0,
+                escrow_token_account: pda::escrow_token_account(&mint),
+                owner_token_account: get_associated_token_address(&seller.pubkey(), &mint),
+                rental: pda::rental(&mint).0,
+                token_program: anchor_spl::token::ID,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::DelistRental {
+                bump: authority_pda.1,
+            },
+        )],
+        &[&seller],
+    );
+
+    // Signed orders. The ed25519 precompile does not consume compute units,
+    // so the measurement covers fill_signed_order alone.
+    let mint = ctx.mint_nft(&seller);
+    let approve = spl_token::instruction::approve(
+        &spl_token::ID,
+        &get_associated_token_address(&seller.pubkey(), &mint),
+        &authority_pda.0,
+        &seller.pubkey(),
+        &[],
+        1,
+    )
+    .unwrap();
+    ctx.send(&[approve], &[&seller]).unwrap();
+    let order = SignedOrder {
+        seller: seller.pubkey(),
+        mint,
+        price: SOL,
+        expiry: ctx.now() + 3_600,
+        nonce: 1,
+    };
+    let message = order.message().unwrap();
+    let signature: [u8; 64] = seller.sign_message(&message).as_ref().try_into().unwrap();
+    measurements.measure(
+        &mut ctx,
+        "fill_signed_order",
+        &[
+            new_ed25519_instruction_with_signature(
+                &message,
+                &signature,
+                &seller.pubkey().to_bytes(),
+            ),
+            ix(
+                accounts::FillSignedOrder {
+                    buyer: buyer.pubkey(),
+                    seller: Some(seller.pubkey()),
+                    proceeds_vault: None,
+                    marketplace,
+                    treasury,
+                    mint,
+                    pda: authority_pda.0,
+                    seller_token_account: get_associated_token_address(&seller.pubkey(), &mint),
+                    buyer_token_account: get_associated_token_address(&buyer.pubkey(), &mint),
+                    order_nonce: pda::order_nonce(&seller.pubkey(), order.nonce).0,
+                    metadata_account: pda::metadata(&mint),
+                    collection_stats: None,
+                    instructions_sysvar: sysvar::instructions::ID,
+                    admins: pda::admins().0,
+                    token_program: anchor_spl::token::ID,
+                    associated_token_program: anchor_spl::associated_token::ID,
+                    system_program: anchor_lang::system_program::ID,
+                },
+                instruction::FillSignedOrder {
+                    order,
+                    bump: authority_pda.1,
+                },
+            ),
+        ],
+        &[&buyer],
+    );
+    measurements.measure(
+        &mut ctx,
+        "cancel_signed_order",
+        &[ix(
+            accounts::CancelSignedOrder {
+                seller: seller.pubkey(),
+                order_nonce: pda::order_nonce(&seller.pubkey(), 2).0,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::CancelSignedOrder { nonce: 2 },
+        )],
+        &[&seller],
+    );
+
+    measurements
+}
+
+#[test]
+fn compute_units_within_baseline() {
+    let measurements = measure_all();
+
+    if std::env::var_os("UPDATE_CU_BASELINE").is_some() {
+        write_baseline(&measurements);
+    }
+
+    let regressions = report(&measurements, &read_baseline());
+    assert!(
+        regressions.is_empty(),
+        "compute units regressed more than {}% over or are missing from \
+         tests/compute_units.baseline, run with UPDATE_CU_BASELINE=1 to record them: {}",
+        REGRESSION_THRESHOLD_PCT,
+        regressions.join(", ")
+    );
+}
@@ -0,0 +1,272 @@
+//! Property-based fuzzing of the listing lifecycle. Random sequences of
+//! `list_nft`, `update_price`, `buy_nft` and `delist_nft` from several actors
+//! run against LiteSVM and are checked against a simple model of who holds
+//! each NFT. Set `PROPTEST_CASES` to run more sequences.
+
+mod common;
+
+use anchor_lang::prelude::Pubkey;
+use anchor_spl::associated_token::get_associated_token_address;
+use common::{TestContext, SOL};
+use nft_marketplace_client::{
+    instructions::{BuyNft, DelistNft, ListNft, Payout, UpdatePrice},
+    pda,
+};
+use proptest::prelude::*;
+use solana_sdk::signature::{Keypair, Signer};
+
+const ACTORS: usize = 3;
+const NFTS: usize = 2;
+/// LiteSVM charges the default 5000 lamports per signature
+const TX_FEE: u64 = 5_000;
+
+#[derive(Clone, Debug)]
+enum Action {
+    List {
+        actor: usize,
+        nft: usize,
+        price: u64,
+    },
+    UpdatePrice {
+        actor: usize,
+        nft: usize,
+        price: u64,
+    },
+    Buy {
+        actor: usize,
+        nft: usize,
+    },
+    Delist {
+        actor: usize,
+        nft: usize,
+    },
+}
+
+fn action() -> impl Strategy<Value = Action> {
+    let price = prop_oneof![Just(0), 1..=SOL];
+    prop_oneof![
+        (0..ACTORS, 0..NFTS, price.clone()).prop_map(|(actor, nft, price)| Action::List {
+            actor,
+            nft,
+            price
+        }),
+        (0..ACTORS, 0..NFTS, price).prop_map(|(actor, nft, price)| Action::UpdatePrice {
+            actor,
+            nft,
+            price
+        }),
+        (0..ACTORS, 0..NFTS).prop_map(|(actor, nft)| Action::Buy { actor, nft }),
+        (0..ACTORS, 0..NFTS).prop_map(|(actor, nft)| Action::Delist { actor, nft }),
+    ]
+}
+
+/// Expected state of one NFT.
+#[derive(Clone, Copy, Debug)]
+enum Holder {
+    Wallet(usize),
+    Listed { seller: usize, price: u64 },
+}
+
+struct Harness {
+    ctx: TestContext,
+    actors: Vec<Keypair>,
+    mints: Vec<Pubkey>,
+    holders: Vec<Holder>,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let mut ctx = TestContext::new();
+        let actors: Vec<Keypair> = (0..ACTORS).map(|_| ctx.funded_keypair()).collect();
+        let mints = (0..NFTS)
+            .map(|nft| ctx.mint_nft(&actors[nft % ACTORS]))
+            .collect();
+        let holders = (0..NFTS).map(|nft| Holder::Wallet(nft % ACTORS)).collect();
+
+        Self {
+            ctx,
+            actors,
+            mints,
+            holders,
+        }
+    }
+
+    /// Runs `action` and checks the outcome against the model. `None` means
+    /// the model has no opinion on whether it should succeed.
+    fn apply(&mut self, action: &Action) {
+        let before = self.tracked_lamports();
+
+        let (succeeded, expected) = match *action {
+            Action::List { actor, nft, price } => {
+                let ix = ListNft {
+                    seller: self.actors[actor].pubkey(),
+                    mint: self.mints[nft],
+                    price,
+                    collection: None,
+                }
+                .instruction();
+                let expected = matches!(self.holders[nft], Holder::Wallet(owner) if owner == actor);
+                (self.send(actor, ix), Some(expected))
+            }
+            Action::UpdatePrice { actor, nft, price } => {
+                let ix = UpdatePrice {
+                    seller: self.actors[actor].pubkey(),
+                    mint: self.mints[nft],
+                    new_price: price,
+                    collection: None,
+                }
+                .instruction();
+                let expected = price > 0
+                    && matches!(self.holders[nft], Holder::Listed { seller, .. } if seller == actor);
+                (self.send(actor, ix), Some(expected))
+            }
+            Action::Buy { actor, nft } => {
+                let (seller, expected) = match self.holders[nft] {
+                    // Buying your own listing is not forbidden, but not specified either
+                    Holder::Listed { seller, .. } => (seller, (seller != actor).then_some(true)),
+                    Holder::Wallet(owner) => (owner, Some(false)),
+                };
+                let ix = BuyNft {
+                    buyer: self.actors[actor].pubkey(),
+                    seller: self.actors[seller].pubkey(),
+                    mint: self.mints[nft],
+                    treasury: self.ctx.treasury,
+                    payout: Payout::Wallet,
+                    referrer: None,
+                    collection: None,
+                }
+                .instruction();
+                (self.send(actor, ix), expected)
+            }
+            Action::Delist { actor, nft } => {
+                let ix = DelistNft {
+                    seller: self.actors[actor].pubkey(),
+                    mint: self.mints[nft],
+                    collection: None,
+                }
+                .instruction();
+                let expected =
+                    matches!(self.holders[nft], Holder::Listed { seller, .. } if seller == actor);
+                (self.send(actor, ix), Some(expected))
+            }
+        };
+
+        if let Some(expected) = expected {
+            assert_eq!(succeeded, expected, "unexpected outcome for {:?}", action);
+        }
+
+        let after = self.tracked_lamports();
+        let spent = before
+            .checked_sub(after)
+            .unwrap_or_else(|| panic!("{:?} created {} lamports", action, after - before));
+        assert!(
+            spent == 0 || spent == TX_FEE,
+            "{:?} moved {} lamports out of the tracked accounts",
+            action,
+            spent
+        );
+
+        if succeeded {
+            self.holders[action_nft(action)] = match *action {
+                Action::List { actor, price, .. } => Holder::Listed {
+                    seller: actor,
+                    price,
+                },
+                Action::UpdatePrice { actor, price, .. } => Holder::Listed {
+                    seller: actor,
+                    price,
+                },
+                Action::Buy { actor, .. } => Holder::Wallet(actor),
+                Action::Delist { actor, .. } => Holder::Wallet(actor),
+            };
+        }
+    }
+
+    fn send(
+        &mut self,
+        actor: usize,
+        ix: anchor_lang::solana_program::instruction::Instruction,
+    ) -> bool {
+        let signer = self.actors[actor].insecure_clone();
+        self.ctx.send(&[ix], &[&signer]).is_ok()
+    }
+
+    /// Every account that lamports can move between during these actions.
+    fn tracked_lamports(&self) -> u64 {
+        let mut accounts: Vec<Pubkey> = self.actors.iter().map(|actor| actor.pubkey()).collect();
+        accounts.push(self.ctx.treasury);
+        for mint in &self.mints {
+            accounts.push(pda::listing(mint).0);
+            accounts.push(pda::escrow_token_account(mint));
+            for actor in &self.actors {
+                accounts.push(get_associated_token_address(&actor.pubkey(), mint));
+            }
+        }
+        accounts
+            .iter()
+            .map(|account| self.ctx.balance(account))
+            .sum()
+    }
+
+    fn check_invariants(&self) {
+        let authority = pda::authority().0;
+
+        for (nft, mint) in self.mints.iter().enumerate() {
+            // The NFT is in exactly one place
+            let escrowed = self.ctx.token_amount(&authority, mint);
+            let held: Vec<u64> = self
+                .actors
+                .iter()
+                .map(|actor| self.ctx.token_amount(&actor.pubkey(), mint))
+                .collect();
+            assert_eq!(
+                escrowed + held.iter().sum::<u64>(),
+                1,
+                "NFT {} is not in exactly one account",
+                nft
+            );
+
+            match self.holders[nft] {
+                Holder::Listed { seller, price } => {
+                    assert_eq!(escrowed, 1, "listed NFT {} is not in escrow", nft);
+                    let listing = self.ctx.listing(mint).expect("active listing is missing");
+                    assert!(listing.is_active);
+                    assert_eq!(listing.seller, self.actors[seller].pubkey());
+                    assert_eq!(listing.price, price);
+                }
+                Holder::Wallet(owner) => {
+                    assert_eq!(held[owner], 1, "NFT {} is not with its owner", nft);
+                    // Sold and delisted listings are closed, not left behind
+                    assert!(
+                        self.ctx.listing(mint).is_none(),
+                        "stale listing for NFT {}",
+                        nft
+                    );
+                    assert_eq!(self.ctx.balance(&pda::listing(mint).0), 0);
+                }
+            }
+        }
+    }
+}
+
+fn action_nft(action: &Action) -> usize {
+    match *action {
+        Action::List { nft, .. }
+        | Action::UpdatePrice { nft, .. }
+        | Action::Buy { nft, .. }
+        | Action::Delist { nft, .. } => nft,
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn listing_lifecycle_preserves_invariants(actions in prop::collection::vec(action(), 1..40)) {
+        let mut harness = Harness::new();
+        for action in &actions {
+            harness.apply(action);
+            harness.check_invariants();
+        }
+    }
+}
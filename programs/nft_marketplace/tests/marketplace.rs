@@ -0,0 +1,1321 @@
+mod common;
+
+use anchor_lang::{
+    prelude::Pubkey,
+    solana_program::{instruction::Instruction, program_option::COption},
+    InstructionData, Space,
+};
+use anchor_spl::token::Mint;
+use common::{assert_error, ix, TestContext, FEE_BPS, REFERRAL_BPS, SOL};
+use nft_marketplace::{
+    accounts, instruction, CollectionAllowlist, CollectionStats, ErrorCode, Listing, Marketplace,
+    ProceedsVault, LISTING_VERSION, MAX_ALLOWED_COLLECTIONS,
+};
+use nft_marketplace_client::{
+    instructions::{
+        BatchDelistNft, BatchListNft, BatchListing, BuyMany, BuyNft, DelistNft, ListNft, Payout,
+        SweepListing, UpdatePrice,
+    },
+    pda,
+};
+use solana_sdk::signature::{Keypair, Signer};
+
+fn buy(ctx: &TestContext, buyer: &Keypair, seller: &Pubkey, mint: &Pubkey) -> BuyNft {
+    BuyNft {
+        buyer: buyer.pubkey(),
+        seller: *seller,
+        mint: *mint,
+        treasury: ctx.treasury,
+        payout: Payout::Wallet,
+        referrer: None,
+        collection: None,
+    }
+}
+
+fn update_price(seller: &Keypair, mint: &Pubkey, new_price: u64) -> UpdatePrice {
+    UpdatePrice {
+        seller: seller.pubkey(),
+        mint: *mint,
+        new_price,
+        collection: None,
+    }
+}
+
+fn listing_rent(ctx: &TestContext) -> u64 {
+    ctx.svm
+        .minimum_balance_for_rent_exemption(8 + Listing::INIT_SPACE)
+}
+
+#[test]
+fn mints_nft_to_signer() {
+    let mut ctx = TestContext::new();
+    let owner = ctx.funded_keypair();
+
+    let mint = ctx.mint_nft(&owner);
+
+    assert_eq!(ctx.token_amount(&owner.pubkey(), &mint), 1);
+    let mint_account: Mint = ctx.account(&mint).unwrap();
+    assert_eq!(mint_account.supply, 1);
+    assert_eq!(mint_account.decimals, 0);
+    assert_eq!(mint_account.freeze_authority, COption::Some(pda::authority().0));
+
+    let metadata = ctx.svm.get_account(&pda::metadata(&mint)).unwrap();
+    assert_eq!(metadata.owner, mpl_token_metadata::ID);
+    assert!(ctx.svm.get_account(&pda::master_edition(&mint)).is_some());
+}
+
+#[test]
+fn lists_nft_into_escrow() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+
+    let mint = ctx.listed_nft(&seller, 2 * SOL);
+
+    let listing = ctx.listing(&mint).unwrap();
+    assert_eq!(listing.seller, seller.pubkey());
+    assert_eq!(listing.mint, mint);
+    assert_eq!(listing.price, 2 * SOL);
+    assert!(listing.is_active);
+    assert_eq!(listing.bump, pda::listing(&mint).1);
+    assert_eq!(listing.version, LISTING_VERSION);
+
+    assert_eq!(ctx.token_amount(&seller.pubkey(), &mint), 0);
+    assert_eq!(ctx.token_amount(&pda::authority().0, &mint), 1);
+}
+
+#[test]
+fn updates_listing_price() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, 2 * SOL);
+
+    ctx.send(&[update_price(&seller, &mint, 3 * SOL).instruction()], &[&seller])
+        .unwrap();
+
+    assert_eq!(ctx.listing(&mint).unwrap().price, 3 * SOL);
+}
+
+#[test]
+fn rejects_zero_price_update() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, 2 * SOL);
+
+    let result = ctx.send(&[update_price(&seller, &mint, 0).instruction()], &[&seller]);
+
+    assert_error(result, ErrorCode::InvalidPrice);
+}
+
+#[test]
+fn rejects_price_update_from_other_wallet() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let intruder = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, 2 * SOL);
+
+    let result = ctx.send(&[update_price(&intruder, &mint, SOL).instruction()], &[&intruder]);
+
+    assert_error(result, ErrorCode::UnauthorizedSeller);
+}
+
+#[test]
+fn buys_nft_and_pays_marketplace_fee() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let price = 2 * SOL;
+    let mint = ctx.listed_nft(&seller, price);
+
+    let seller_before = ctx.balance(&seller.pubkey());
+    let treasury_before = ctx.balance(&ctx.treasury);
+
+    ctx.send(&[buy(&ctx, &buyer, &seller.pubkey(), &mint).instruction()], &[&buyer])
+        .unwrap();
+
+    let fee = price * FEE_BPS as u64 / 10_000;
+    assert_eq!(
+        ctx.balance(&seller.pubkey()) - seller_before,
+        price - fee + listing_rent(&ctx)
+    );
+    assert_eq!(ctx.balance(&ctx.treasury) - treasury_before, fee);
+    assert_eq!(ctx.token_amount(&buyer.pubkey(), &mint), 1);
+    assert_eq!(ctx.token_amount(&pda::authority().0, &mint), 0);
+    assert!(ctx.listing(&mint).is_none());
+}
+
+#[test]
+fn pays_referral_share_of_fee() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let referrer = ctx.funded_keypair();
+    let price = 2 * SOL;
+    let mint = ctx.listed_nft(&seller, price);
+
+    let referrer_before = ctx.balance(&referrer.pubkey());
+    let treasury_before = ctx.balance(&ctx.treasury);
+
+    let mut ix = buy(&ctx, &buyer, &seller.pubkey(), &mint);
+    ix.referrer = Some(referrer.pubkey());
+    ctx.send(&[ix.instruction()], &[&buyer]).unwrap();
+
+    let fee = price * FEE_BPS as u64 / 10_000;
+    let referral = fee * REFERRAL_BPS as u64 / 10_000;
+    assert_eq!(ctx.balance(&referrer.pubkey()) - referrer_before, referral);
+    assert_eq!(ctx.balance(&ctx.treasury) - treasury_before, fee - referral);
+}
+
+#[test]
+fn rejects_self_referral() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, SOL);
+
+    let mut ix = buy(&ctx, &buyer, &seller.pubkey(), &mint);
+    ix.referrer = Some(buyer.pubkey());
+    let result = ctx.send(&[ix.instruction()], &[&buyer]);
+
+    assert_error(result, ErrorCode::SelfReferral);
+}
+
+#[test]
+fn rejects_fee_paid_to_another_treasury() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, SOL);
+
+    let mut ix = buy(&ctx, &buyer, &seller.pubkey(), &mint);
+    ix.treasury = buyer.pubkey();
+    let result = ctx.send(&[ix.instruction()], &[&buyer]);
+
+    assert_error(result, anchor_lang::error::ErrorCode::ConstraintAddress);
+    assert_eq!(ctx.token_amount(&pda::authority().0, &mint), 1);
+}
+
+#[test]
+fn rejects_inactive_listing() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, SOL);
+
+    // Listings are closed when they stop being active, so force the state
+    let mut listing = ctx.listing(&mint).unwrap();
+    listing.is_active = false;
+    ctx.write_account(&pda::listing(&mint).0, &listing);
+
+    let result = ctx.send(&[buy(&ctx, &buyer, &seller.pubkey(), &mint).instruction()], &[&buyer]);
+
+    assert_error(result, ErrorCode::ListingNotActive);
+}
+
+#[test]
+fn delists_nft_back_to_seller() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, SOL);
+
+    let ix = DelistNft {
+        seller: seller.pubkey(),
+        mint,
+        collection: None,
+    }
+    .instruction();
+    ctx.send(&[ix], &[&seller]).unwrap();
+
+    assert_eq!(ctx.token_amount(&seller.pubkey(), &mint), 1);
+    assert_eq!(ctx.token_amount(&pda::authority().0, &mint), 0);
+    assert!(ctx.listing(&mint).is_none());
+}
+
+#[test]
+fn rejects_initialization_by_non_upgrade_authority() {
+    let mut ctx = TestContext::without_marketplace();
+    let intruder = ctx.funded_keypair();
+    let treasury = Pubkey::new_unique();
+
+    let result = ctx.send(
+        &[ix(
+            accounts::InitializeMarketplace {
+                authority: intruder.pubkey(),
+                program_data: pda::program_data(),
+                treasury,
+                marketplace: pda::marketplace().0,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::InitializeMarketplace {
+                fee_bps: 0,
+                referral_bps: 0,
+            },
+        )],
+        &[&intruder],
+    );
+
+    assert_error(result, ErrorCode::UnauthorizedAuthority);
+    assert!(ctx.svm.get_account(&pda::marketplace().0).is_none());
+}
+
+#[test]
+fn updates_marketplace_config() {
+    let mut ctx = TestContext::new();
+    let authority = ctx.authority.insecure_clone();
+    let new_treasury = Pubkey::new_unique();
+
+    ctx.send(
+        &[update_marketplace(&authority, &new_treasury, 500, 1_000)],
+        &[&authority],
+    )
+    .unwrap();
+
+    let marketplace: Marketplace = ctx.account(&pda::marketplace().0).unwrap();
+    assert_eq!(marketplace.treasury, new_treasury);
+    assert_eq!(marketplace.fee_bps, 500);
+    assert_eq!(marketplace.referral_bps, 1_000);
+}
+
+#[test]
+fn rejects_fee_above_100_percent() {
+    let mut ctx = TestContext::new();
+    let authority = ctx.authority.insecure_clone();
+    let treasury = ctx.treasury;
+
+    let result = ctx.send(
+        &[update_marketplace(&authority, &treasury, 10_001, 0)],
+        &[&authority],
+    );
+
+    assert_error(result, ErrorCode::InvalidBasisPoints);
+}
+
+#[test]
+fn rejects_referral_share_above_100_percent() {
+    let mut ctx = TestContext::new();
+    let authority = ctx.authority.insecure_clone();
+    let treasury = ctx.treasury;
+
+    let result = ctx.send(
+        &[update_marketplace(&authority, &treasury, FEE_BPS, 10_001)],
+        &[&authority],
+    );
+
+    assert_error(result, ErrorCode::InvalidBasisPoints);
+}
+
+#[test]
+fn rejects_config_update_from_other_wallet() {
+    let mut ctx = TestContext::new();
+    let intruder = ctx.funded_keypair();
+    let treasury = ctx.treasury;
+
+    let result = ctx.send(&[update_marketplace(&intruder, &treasury, 0, 0)], &[&intruder]);
+
+    assert_error(result, ErrorCode::UnauthorizedAuthority);
+}
+
+fn update_marketplace(
+    authority: &Keypair,
+    treasury: &Pubkey,
+    fee_bps: u16,
+    referral_bps: u16,
+) -> Instruction {
+    ix(
+        accounts::UpdateMarketplace {
+            authority: authority.pubkey(),
+            treasury: *treasury,
+            marketplace: pda::marketplace().0,
+        },
+        instruction::UpdateMarketplace {
+            fee_bps,
+            referral_bps,
+        },
+    )
+}
+
+fn init_proceeds_vault(ctx: &mut TestContext, seller: &Keypair) -> Pubkey {
+    let vault = pda::proceeds_vault(&seller.pubkey()).0;
+    ctx.send(
+        &[ix(
+            accounts::InitProceedsVault {
+                seller: seller.pubkey(),
+                proceeds_vault: vault,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::InitProceedsVault {},
+        )],
+        &[seller],
+    )
+    .unwrap();
+    vault
+}
+
+fn withdraw_proceeds(seller: &Keypair) -> Instruction {
+    ix(
+        accounts::WithdrawProceeds {
+            seller: seller.pubkey(),
+            proceeds_vault: pda::proceeds_vault(&seller.pubkey()).0,
+        },
+        instruction::WithdrawProceeds {},
+    )
+}
+
+#[test]
+fn pays_into_proceeds_vault_and_withdraws() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let price = 2 * SOL;
+    let mint = ctx.listed_nft(&seller, price);
+    let vault = init_proceeds_vault(&mut ctx, &seller);
+
+    let seller_before = ctx.balance(&seller.pubkey());
+    let vault_before = ctx.balance(&vault);
+
+    let mut ix = buy(&ctx, &buyer, &seller.pubkey(), &mint);
+    ix.payout = Payout::ProceedsVault;
+    ctx.send(&[ix.instruction()], &[&buyer]).unwrap();
+
+    let proceeds = price - price * FEE_BPS as u64 / 10_000 + listing_rent(&ctx);
+    assert_eq!(ctx.balance(&seller.pubkey()), seller_before);
+    assert_eq!(ctx.balance(&vault) - vault_before, proceeds);
+    let stored: ProceedsVault = ctx.account(&vault).unwrap();
+    assert_eq!(stored.seller, seller.pubkey());
+
+    ctx.send(&[withdraw_proceeds(&seller)], &[&seller]).unwrap();
+
+    assert_eq!(ctx.balance(&vault), vault_before);
+    // The seller also paid the withdrawal transaction fee
+    assert!(ctx.balance(&seller.pubkey()) > seller_before + proceeds - SOL / 1_000);
+}
+
+#[test]
+fn rejects_withdrawal_from_empty_vault() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    init_proceeds_vault(&mut ctx, &seller);
+
+    let result = ctx.send(&[withdraw_proceeds(&seller)], &[&seller]);
+
+    assert_error(result, ErrorCode::NoProceeds);
+}
+
+#[test]
+fn rejects_withdrawal_by_another_wallet() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let intruder = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, SOL);
+    let vault = init_proceeds_vault(&mut ctx, &seller);
+    let mut purchase = buy(&ctx, &buyer, &seller.pubkey(), &mint);
+    purchase.payout = Payout::ProceedsVault;
+    ctx.send(&[purchase.instruction()], &[&buyer]).unwrap();
+    let vault_before = ctx.balance(&vault);
+
+    let result = ctx.send(
+        &[ix(
+            accounts::WithdrawProceeds {
+                seller: intruder.pubkey(),
+                proceeds_vault: vault,
+            },
+            instruction::WithdrawProceeds {},
+        )],
+        &[&intruder],
+    );
+
+    assert_error(result, anchor_lang::error::ErrorCode::ConstraintSeeds);
+    assert_eq!(ctx.balance(&vault), vault_before);
+}
+
+/// `buy_nft` with an explicit choice of payout accounts, which the client
+/// builder never leaves empty or doubles up.
+fn buy_with_payout(
+    ctx: &TestContext,
+    buyer: &Keypair,
+    mint: &Pubkey,
+    seller: Option<Pubkey>,
+    proceeds_vault: Option<Pubkey>,
+) -> Instruction {
+    ix(
+        accounts::BuyNFT {
+            buyer: buyer.pubkey(),
+            seller,
+            proceeds_vault,
+            marketplace: pda::marketplace().0,
+            treasury: ctx.treasury,
+            referrer: None,
+            mint: *mint,
+            pda: pda::authority().0,
+            escrow_token_account: pda::escrow_token_account(mint),
+            buyer_token_account: anchor_spl::associated_token::get_associated_token_address(
+                &buyer.pubkey(),
+                mint,
+            ),
+            listing: pda::listing(mint).0,
+            metadata_account: pda::metadata(mint),
+            collection_stats: None,
+            admins: pda::admins().0,
+            token_program: anchor_spl::token::ID,
+            associated_token_program: anchor_spl::associated_token::ID,
+            system_program: anchor_lang::system_program::ID,
+            rent: anchor_lang::solana_program::sysvar::rent::ID,
+        },
+        instruction::BuyNft {
+            bump: pda::authority().1,
+        },
+    )
+}
+
+#[test]
+fn rejects_buy_without_payout_destination() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, SOL);
+
+    let ix = buy_with_payout(&ctx, &buyer, &mint, None, None);
+    let result = ctx.send(&[ix], &[&buyer]);
+
+    assert_error(result, ErrorCode::InvalidProceedsDestination);
+}
+
+#[test]
+fn rejects_buy_paying_both_wallet_and_vault() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, SOL);
+    let vault = init_proceeds_vault(&mut ctx, &seller);
+
+    let ix = buy_with_payout(&ctx, &buyer, &mint, Some(seller.pubkey()), Some(vault));
+    let result = ctx.send(&[ix], &[&buyer]);
+
+    assert_error(result, ErrorCode::InvalidProceedsDestination);
+}
+
+#[test]
+fn tracks_collection_stats() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let collection = ctx.mint_nft(&seller);
+    let stats_address = ctx.init_collection_stats(&collection);
+    let mint = ctx.mint_nft(&seller);
+    ctx.set_verified_collection(&mint, &collection);
+
+    let list = ListNft {
+        seller: seller.pubkey(),
+        mint,
+        price: 2 * SOL,
+        collection: Some(collection),
+    };
+    ctx.send(&[list.instruction()], &[&seller]).unwrap();
+
+    let stats: CollectionStats = ctx.account(&stats_address).unwrap();
+    assert_eq!(stats.collection, collection);
+    assert_eq!(stats.listed_count, 1);
+    assert_eq!(stats.listed_value, 2 * SOL);
+
+    let mut reprice = update_price(&seller, &mint, 3 * SOL);
+    reprice.collection = Some(collection);
+    ctx.send(&[reprice.instruction()], &[&seller]).unwrap();
+
+    let stats: CollectionStats = ctx.account(&stats_address).unwrap();
+    assert_eq!(stats.listed_value, 3 * SOL);
+
+    let mut purchase = buy(&ctx, &buyer, &seller.pubkey(), &mint);
+    purchase.collection = Some(collection);
+    ctx.send(&[purchase.instruction()], &[&buyer]).unwrap();
+
+    let stats: CollectionStats = ctx.account(&stats_address).unwrap();
+    assert_eq!(stats.listed_count, 0);
+    assert_eq!(stats.listed_value, 0);
+    assert_eq!(stats.sales_count, 1);
+    assert_eq!(stats.total_volume, 3 * SOL);
+    assert_eq!(stats.last_sale_price, 3 * SOL);
+}
+
+#[test]
+fn requires_collection_stats_for_verified_collection() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let collection = ctx.mint_nft(&seller);
+    ctx.init_collection_stats(&collection);
+    let mint = ctx.mint_nft(&seller);
+    ctx.set_verified_collection(&mint, &collection);
+
+    let list = ListNft {
+        seller: seller.pubkey(),
+        mint,
+        price: SOL,
+        collection: None,
+    };
+    let result = ctx.send(&[list.instruction()], &[&seller]);
+
+    assert_error(result, ErrorCode::CollectionStatsRequired);
+}
+
+#[test]
+fn rejects_stats_of_another_collection() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let collection = ctx.mint_nft(&seller);
+    let other_collection = ctx.mint_nft(&seller);
+    ctx.init_collection_stats(&other_collection);
+    let mint = ctx.mint_nft(&seller);
+    ctx.set_verified_collection(&mint, &collection);
+
+    let list = ListNft {
+        seller: seller.pubkey(),
+        mint,
+        price: SOL,
+        collection: Some(other_collection),
+    };
+    let result = ctx.send(&[list.instruction()], &[&seller]);
+
+    // `list_nft` derives the stats address from the mint's own collection
+    assert_error(result, anchor_lang::error::ErrorCode::ConstraintSeeds);
+}
+
+#[test]
+fn creates_collection_stats_on_first_listing() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let collection = ctx.mint_nft(&seller);
+    let mint = ctx.mint_nft(&seller);
+    ctx.set_verified_collection(&mint, &collection);
+    let stats_address = pda::collection_stats(&collection).0;
+    assert!(ctx.account::<CollectionStats>(&stats_address).is_none());
+
+    let seller_before = ctx.balance(&seller.pubkey());
+    let list = ListNft {
+        seller: seller.pubkey(),
+        mint,
+        price: 2 * SOL,
+        collection: Some(collection),
+    };
+    ctx.send(&[list.instruction()], &[&seller]).unwrap();
+
+    let stats: CollectionStats = ctx.account(&stats_address).unwrap();
+    assert_eq!(stats.collection, collection);
+    assert_eq!(stats.bump, pda::collection_stats(&collection).1);
+    assert_eq!(stats.listed_count, 1);
+    assert_eq!(stats.listed_value, 2 * SOL);
+    // The seller paid the rent of the listing and the stats
+    let stats_rent = ctx.balance(&stats_address);
+    assert!(seller_before - ctx.balance(&seller.pubkey()) >= listing_rent(&ctx) + stats_rent);
+
+    // Later listings of the collection update the same account
+    let second = ctx.mint_nft(&seller);
+    ctx.set_verified_collection(&second, &collection);
+    let list = ListNft {
+        seller: seller.pubkey(),
+        mint: second,
+        price: SOL,
+        collection: Some(collection),
+    };
+    ctx.send(&[list.instruction()], &[&seller]).unwrap();
+
+    let stats: CollectionStats = ctx.account(&stats_address).unwrap();
+    assert_eq!(stats.listed_count, 2);
+    assert_eq!(stats.listed_value, 3 * SOL);
+}
+
+#[test]
+fn rejects_collection_stats_for_unverified_mint() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let collection = ctx.mint_nft(&seller);
+    let mint = ctx.mint_nft(&seller);
+
+    let list = ListNft {
+        seller: seller.pubkey(),
+        mint,
+        price: SOL,
+        collection: Some(collection),
+    };
+    let result = ctx.send(&[list.instruction()], &[&seller]);
+
+    assert_error(result, ErrorCode::CollectionStatsMismatch);
+    assert!(ctx.account::<CollectionStats>(&pda::collection_stats(&collection).0).is_none());
+}
+
+#[test]
+fn rejects_unreadable_metadata() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let mint = ctx.mint_nft(&seller);
+    ctx.set_account_data(&pda::metadata(&mint), &mpl_token_metadata::ID, vec![0xff; 100]);
+
+    let list = ListNft {
+        seller: seller.pubkey(),
+        mint,
+        price: SOL,
+        collection: None,
+    };
+    let result = ctx.send(&[list.instruction()], &[&seller]);
+
+    assert_error(result, ErrorCode::InvalidMetadata);
+}
+
+fn migrate_listing(payer: &Keypair, mint: &Pubkey) -> Instruction {
+    ix(
+        accounts::MigrateListing {
+            payer: payer.pubkey(),
+            mint: *mint,
+            listing: pda::listing(mint).0,
+            system_program: anchor_lang::system_program::ID,
+        },
+        instruction::MigrateListing {},
+    )
+}
+
+#[test]
+fn migrates_legacy_listing() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let cranker = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, 2 * SOL);
+    // Legacy listings stored the authority bump instead of their own
+    ctx.write_legacy_listing(&mint, &mint, pda::authority().1);
+
+    ctx.send(&[migrate_listing(&cranker, &mint)], &[&cranker]).unwrap();
+
+    let account = ctx.svm.get_account(&pda::listing(&mint).0).unwrap();
+    assert_eq!(account.data.len(), 8 + Listing::INIT_SPACE);
+    assert!(account.lamports >= listing_rent(&ctx));
+    let listing = ctx.listing(&mint).unwrap();
+    assert_eq!(listing.seller, seller.pubkey());
+    assert_eq!(listing.price, 2 * SOL);
+    assert_eq!(listing.version, LISTING_VERSION);
+    assert_eq!(listing.bump, pda::listing(&mint).1);
+
+    // The repaired bump makes the listing usable again
+    ctx.send(&[update_price(&seller, &mint, SOL).instruction()], &[&seller])
+        .unwrap();
+}
+
+#[test]
+fn rejects_buying_unmigrated_listing() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, SOL);
+    ctx.write_legacy_listing(&mint, &mint, pda::authority().1);
+
+    let result = ctx.send(&[buy(&ctx, &buyer, &seller.pubkey(), &mint).instruction()], &[&buyer]);
+
+    assert_error(result, anchor_lang::error::ErrorCode::AccountDidNotDeserialize);
+    assert_eq!(ctx.token_amount(&pda::authority().0, &mint), 1);
+}
+
+#[test]
+fn buys_migrated_listing() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let price = 2 * SOL;
+    let mint = ctx.listed_nft(&seller, price);
+    ctx.write_legacy_listing(&mint, &mint, pda::authority().1);
+    ctx.send(&[migrate_listing(&buyer, &mint)], &[&buyer]).unwrap();
+
+    let seller_before = ctx.balance(&seller.pubkey());
+    let listing_lamports = ctx.balance(&pda::listing(&mint).0);
+    ctx.send(&[buy(&ctx, &buyer, &seller.pubkey(), &mint).instruction()], &[&buyer])
+        .unwrap();
+
+    // The seller also receives the rent the migration topped up
+    let fee = price * FEE_BPS as u64 / 10_000;
+    assert_eq!(
+        ctx.balance(&seller.pubkey()) - seller_before,
+        price - fee + listing_lamports
+    );
+    assert_eq!(ctx.token_amount(&buyer.pubkey(), &mint), 1);
+    assert!(ctx.listing(&mint).is_none());
+}
+
+#[test]
+fn rejects_migrating_twice() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, SOL);
+
+    let result = ctx.send(&[migrate_listing(&seller, &mint)], &[&seller]);
+
+    assert_error(result, ErrorCode::ListingAlreadyMigrated);
+}
+
+#[test]
+fn rejects_migrating_listing_of_another_mint() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, SOL);
+    ctx.write_legacy_listing(&mint, &Pubkey::new_unique(), pda::listing(&mint).1);
+
+    let result = ctx.send(&[migrate_listing(&seller, &mint)], &[&seller]);
+
+    assert_error(result, ErrorCode::InvalidListingAccount);
+}
+
+#[test]
+fn batch_lists_and_delists_nfts() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let collection = ctx.mint_nft(&seller);
+    let stats_address = ctx.init_collection_stats(&collection);
+    let plain = ctx.mint_nft(&seller);
+    let member = ctx.mint_nft(&seller);
+    ctx.set_verified_collection(&member, &collection);
+    let other_member = ctx.mint_nft(&seller);
+    ctx.set_verified_collection(&other_member, &collection);
+
+    let batch = BatchListNft {
+        seller: seller.pubkey(),
+        listings: vec![
+            BatchListing {
+                mint: plain,
+                price: SOL,
+                collection: None,
+            },
+            BatchListing {
+                mint: member,
+                price: 2 * SOL,
+                collection: Some(collection),
+            },
+            BatchListing {
+                mint: other_member,
+                price: 3 * SOL,
+                collection: Some(collection),
+            },
+        ],
+    };
+    ctx.send(&[batch.instruction()], &[&seller]).unwrap();
+
+    for (mint, price) in [(plain, SOL), (member, 2 * SOL), (other_member, 3 * SOL)] {
+        let listing = ctx.listing(&mint).unwrap();
+        assert_eq!(listing.seller, seller.pubkey());
+        assert_eq!(listing.price, price);
+        assert!(listing.is_active);
+        assert_eq!(listing.bump, pda::listing(&mint).1);
+        assert_eq!(listing.version, LISTING_VERSION);
+        assert_eq!(ctx.token_amount(&pda::authority().0, &mint), 1);
+    }
+    let stats: CollectionStats = ctx.account(&stats_address).unwrap();
+    assert_eq!(stats.listed_count, 2);
+    assert_eq!(stats.listed_value, 5 * SOL);
+
+    let batch = BatchDelistNft {
+        seller: seller.pubkey(),
+        listings: vec![(plain, None), (member, Some(collection))],
+    };
+    ctx.send(&[batch.instruction()], &[&seller]).unwrap();
+
+    for mint in [plain, member] {
+        assert!(ctx.listing(&mint).is_none());
+        assert_eq!(ctx.token_amount(&seller.pubkey(), &mint), 1);
+    }
+    assert!(ctx.listing(&other_member).is_some());
+    let stats: CollectionStats = ctx.account(&stats_address).unwrap();
+    assert_eq!(stats.listed_count, 1);
+    assert_eq!(stats.listed_value, 3 * SOL);
+}
+
+#[test]
+fn rejects_incomplete_batch() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let mint = ctx.mint_nft(&seller);
+
+    let mut ix = BatchListNft {
+        seller: seller.pubkey(),
+        listings: vec![BatchListing {
+            mint,
+            price: SOL,
+            collection: None,
+        }],
+    }
+    .instruction();
+    ix.accounts.pop();
+    let result = ctx.send(&[ix], &[&seller]);
+
+    assert_error(result, ErrorCode::InvalidBatchAccounts);
+}
+
+#[test]
+fn rejects_batch_listing_already_listed_nft() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let mint = ctx.mint_nft(&seller);
+
+    let batch = BatchListNft {
+        seller: seller.pubkey(),
+        listings: vec![
+            BatchListing {
+                mint,
+                price: SOL,
+                collection: None,
+            };
+            2
+        ],
+    };
+    let result = ctx.send(&[batch.instruction()], &[&seller]);
+
+    // The first group already moved the NFT out of the seller's account
+    assert_error(result, ErrorCode::InvalidSellerTokenAccount);
+    assert!(ctx.listing(&mint).is_none());
+    assert_eq!(ctx.token_amount(&seller.pubkey(), &mint), 1);
+}
+
+#[test]
+fn batch_lists_nft_whose_listing_address_holds_dust() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let griefer = ctx.funded_keypair();
+    let mint = ctx.mint_nft(&seller);
+    let listing_address = pda::listing(&mint).0;
+    let dust = anchor_lang::solana_program::system_instruction::transfer(
+        &griefer.pubkey(),
+        &listing_address,
+        1_000,
+    );
+    ctx.send(&[dust], &[&griefer]).unwrap();
+
+    let batch = BatchListNft {
+        seller: seller.pubkey(),
+        listings: vec![BatchListing {
+            mint,
+            price: SOL,
+            collection: None,
+        }],
+    };
+    ctx.send(&[batch.instruction()], &[&seller]).unwrap();
+
+    let account = ctx.svm.get_account(&listing_address).unwrap();
+    assert_eq!(account.owner, nft_marketplace::ID);
+    assert_eq!(account.lamports, listing_rent(&ctx));
+    assert_eq!(ctx.listing(&mint).unwrap().price, SOL);
+    assert_eq!(ctx.token_amount(&pda::authority().0, &mint), 1);
+}
+
+#[test]
+fn rejects_batch_delisting_another_sellers_nft() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let attacker = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, SOL);
+
+    let batch = BatchDelistNft {
+        seller: attacker.pubkey(),
+        listings: vec![(mint, None)],
+    };
+    let result = ctx.send(&[batch.instruction()], &[&attacker]);
+
+    assert_error(result, ErrorCode::UnauthorizedSeller);
+    assert!(ctx.listing(&mint).unwrap().is_active);
+}
+
+fn sweep(ctx: &TestContext, buyer: &Keypair, listings: Vec<SweepListing>) -> BuyMany {
+    BuyMany {
+        buyer: buyer.pubkey(),
+        treasury: ctx.treasury,
+        referrer: None,
+        max_total: listings.iter().map(|listing| listing.expected_price).sum(),
+        listings,
+        skip_sold: false,
+    }
+}
+
+fn sweep_listing(seller: &Keypair, mint: &Pubkey, expected_price: u64) -> SweepListing {
+    SweepListing {
+        mint: *mint,
+        seller: seller.pubkey(),
+        expected_price,
+        payout: Payout::Wallet,
+        collection: None,
+    }
+}
+
+#[test]
+fn sweeps_several_listings() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let other_seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let first = ctx.listed_nft(&seller, SOL);
+    let second = ctx.listed_nft(&other_seller, 2 * SOL);
+    let vault = init_proceeds_vault(&mut ctx, &other_seller);
+
+    let seller_before = ctx.balance(&seller.pubkey());
+    let vault_before = ctx.balance(&vault);
+    let treasury_before = ctx.balance(&ctx.treasury);
+
+    let mut into_vault = sweep_listing(&other_seller, &second, 2 * SOL);
+    into_vault.payout = Payout::ProceedsVault;
+    let ix = sweep(
+        &ctx,
+        &buyer,
+        vec![sweep_listing(&seller, &first, SOL), into_vault],
+    );
+    ctx.send(&[ix.instruction()], &[&buyer]).unwrap();
+
+    for mint in [first, second] {
+        assert_eq!(ctx.token_amount(&buyer.pubkey(), &mint), 1);
+        assert!(ctx.listing(&mint).is_none());
+    }
+    let fee = |price: u64| price * FEE_BPS as u64 / 10_000;
+    assert_eq!(
+        ctx.balance(&seller.pubkey()) - seller_before,
+        SOL - fee(SOL) + listing_rent(&ctx)
+    );
+    assert_eq!(
+        ctx.balance(&vault) - vault_before,
+        2 * SOL - fee(2 * SOL) + listing_rent(&ctx)
+    );
+    assert_eq!(
+        ctx.balance(&ctx.treasury) - treasury_before,
+        fee(SOL) + fee(2 * SOL)
+    );
+}
+
+#[test]
+fn rejects_sweep_of_repriced_listing() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, SOL);
+    ctx.send(&[update_price(&seller, &mint, 2 * SOL).instruction()], &[&seller])
+        .unwrap();
+
+    let ix = sweep(&ctx, &buyer, vec![sweep_listing(&seller, &mint, SOL)]);
+    let result = ctx.send(&[ix.instruction()], &[&buyer]);
+
+    assert_error(result, ErrorCode::PriceMismatch);
+}
+
+#[test]
+fn rejects_sweep_above_max_spend() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let first = ctx.listed_nft(&seller, SOL);
+    let second = ctx.listed_nft(&seller, SOL);
+
+    let mut ix = sweep(
+        &ctx,
+        &buyer,
+        vec![
+            sweep_listing(&seller, &first, SOL),
+            sweep_listing(&seller, &second, SOL),
+        ],
+    );
+    ix.max_total = 2 * SOL - 1;
+    let result = ctx.send(&[ix.instruction()], &[&buyer]);
+
+    assert_error(result, ErrorCode::MaxSpendExceeded);
+    assert!(ctx.listing(&first).is_some());
+}
+
+#[test]
+fn skips_sold_listings_only_when_asked() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let sniper = ctx.funded_keypair();
+    let sold = ctx.listed_nft(&seller, SOL);
+    let available = ctx.listed_nft(&seller, SOL);
+    ctx.send(
+        &[buy(&ctx, &sniper, &seller.pubkey(), &sold).instruction()],
+        &[&sniper],
+    )
+    .unwrap();
+
+    let mut ix = sweep(
+        &ctx,
+        &buyer,
+        vec![
+            sweep_listing(&seller, &sold, SOL),
+            sweep_listing(&seller, &available, SOL),
+        ],
+    );
+    let result = ctx.send(&[ix.instruction()], &[&buyer]);
+    assert_error(result, anchor_lang::error::ErrorCode::AccountNotInitialized);
+
+    ix.skip_sold = true;
+    ctx.send(&[ix.instruction()], &[&buyer]).unwrap();
+
+    assert_eq!(ctx.token_amount(&buyer.pubkey(), &available), 1);
+    assert_eq!(ctx.token_amount(&sniper.pubkey(), &sold), 1);
+}
+
+#[test]
+fn skips_relisted_and_repriced_listings_only_when_asked() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let sniper = ctx.funded_keypair();
+    let relisted = ctx.listed_nft(&seller, SOL);
+    let repriced = ctx.listed_nft(&seller, SOL);
+    let available = ctx.listed_nft(&seller, SOL);
+    ctx.send(
+        &[buy(&ctx, &sniper, &seller.pubkey(), &relisted).instruction()],
+        &[&sniper],
+    )
+    .unwrap();
+    ctx.list_nft(&sniper, &relisted, SOL);
+    ctx.send(
+        &[update_price(&seller, &repriced, 2 * SOL).instruction()],
+        &[&seller],
+    )
+    .unwrap();
+
+    let mut ix = sweep(
+        &ctx,
+        &buyer,
+        vec![
+            sweep_listing(&seller, &relisted, SOL),
+            sweep_listing(&seller, &repriced, SOL),
+            sweep_listing(&seller, &available, SOL),
+        ],
+    );
+    let result = ctx.send(&[ix.instruction()], &[&buyer]);
+    assert_error(result, ErrorCode::InvalidProceedsDestination);
+
+    ix.skip_sold = true;
+    ctx.send(&[ix.instruction()], &[&buyer]).unwrap();
+
+    assert_eq!(ctx.token_amount(&buyer.pubkey(), &available), 1);
+    assert_eq!(ctx.listing(&relisted).unwrap().seller, sniper.pubkey());
+    assert_eq!(ctx.listing(&repriced).unwrap().price, 2 * SOL);
+    assert_eq!(ctx.token_amount(&buyer.pubkey(), &relisted), 0);
+    assert_eq!(ctx.token_amount(&buyer.pubkey(), &repriced), 0);
+}
+
+#[test]
+fn rejects_sweep_with_invalid_payout_even_when_skipping() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let mint = ctx.listed_nft(&seller, SOL);
+
+    let mut ix = sweep(&ctx, &buyer, vec![sweep_listing(&seller, &mint, SOL)]);
+    ix.skip_sold = true;
+    let mut ix = ix.instruction();
+    // Pay out to a program account that is no proceeds vault
+    let payout = ix
+        .accounts
+        .iter_mut()
+        .find(|meta| meta.pubkey == seller.pubkey())
+        .unwrap();
+    payout.pubkey = pda::marketplace().0;
+    let result = ctx.send(&[ix], &[&buyer]);
+
+    assert_error(result, ErrorCode::InvalidProceedsDestination);
+    assert!(ctx.listing(&mint).unwrap().is_active);
+}
+
+fn init_allowlist(ctx: &mut TestContext, strict: bool) {
+    let authority = ctx.authority.insecure_clone();
+    ctx.send(
+        &[ix(
+            accounts::InitCollectionAllowlist {
+                authority: authority.pubkey(),
+                marketplace: pda::marketplace().0,
+                admins: None,
+                collection_allowlist: pda::collection_allowlist().0,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::InitCollectionAllowlist { strict },
+        )],
+        &[&authority],
+    )
+    .unwrap();
+}
+
+fn update_allowlist(authority: &Keypair, data: impl InstructionData) -> Instruction {
+    ix(
+        accounts::UpdateCollectionAllowlist {
+            authority: authority.pubkey(),
+            marketplace: pda::marketplace().0,
+            admins: None,
+            collection_allowlist: pda::collection_allowlist().0,
+        },
+        data,
+    )
+}
+
+fn list(seller: &Keypair, mint: &Pubkey, collection: Option<Pubkey>) -> Instruction {
+    ListNft {
+        seller: seller.pubkey(),
+        mint: *mint,
+        price: SOL,
+        collection,
+    }
+    .instruction()
+}
+
+#[test]
+fn strict_allowlist_only_lists_allowed_collections() {
+    let mut ctx = TestContext::new();
+    let authority = ctx.authority.insecure_clone();
+    let seller = ctx.funded_keypair();
+    let collection = ctx.mint_nft(&seller);
+    ctx.init_collection_stats(&collection);
+    let member = ctx.mint_nft(&seller);
+    ctx.set_verified_collection(&member, &collection);
+    let unverified = ctx.mint_nft(&seller);
+    init_allowlist(&mut ctx, true);
+
+    let result = ctx.send(&[list(&seller, &member, Some(collection))], &[&seller]);
+    assert_error(result, ErrorCode::CollectionNotAllowed);
+
+    ctx.send(
+        &[update_allowlist(
+            &authority,
+            instruction::AddAllowedCollection { collection },
+        )],
+        &[&authority],
+    )
+    .unwrap();
+    let allowlist: CollectionAllowlist = ctx.account(&pda::collection_allowlist().0).unwrap();
+    assert!(allowlist.strict);
+    assert_eq!(allowlist.collections, vec![collection]);
+
+    ctx.send(&[list(&seller, &member, Some(collection))], &[&seller])
+        .unwrap();
+    let result = ctx.send(&[list(&seller, &unverified, None)], &[&seller]);
+    assert_error(result, ErrorCode::CollectionNotAllowed);
+
+    // Batch listing goes through the same check
+    let batch = BatchListNft {
+        seller: seller.pubkey(),
+        listings: vec![BatchListing {
+            mint: unverified,
+            price: SOL,
+            collection: None,
+        }],
+    };
+    let result = ctx.send(&[batch.instruction()], &[&seller]);
+    assert_error(result, ErrorCode::CollectionNotAllowed);
+}
+
+#[test]
+fn lists_anything_unless_allowlist_is_strict() {
+    let mut ctx = TestContext::new();
+    let authority = ctx.authority.insecure_clone();
+    let seller = ctx.funded_keypair();
+    let first = ctx.mint_nft(&seller);
+    let second = ctx.mint_nft(&seller);
+    init_allowlist(&mut ctx, false);
+
+    ctx.send(&[list(&seller, &first, None)], &[&seller])
+        .unwrap();
+
+    ctx.send(
+        &[update_allowlist(
+            &authority,
+            instruction::SetAllowlistStrict { strict: true },
+        )],
+        &[&authority],
+    )
+    .unwrap();
+    let result = ctx.send(&[list(&seller, &second, None)], &[&seller]);
+    assert_error(result, ErrorCode::CollectionNotAllowed);
+}
+
+#[test]
+fn removes_allowed_collection() {
+    let mut ctx = TestContext::new();
+    let authority = ctx.authority.insecure_clone();
+    let seller = ctx.funded_keypair();
+    let collection = ctx.mint_nft(&seller);
+    ctx.init_collection_stats(&collection);
+    let member = ctx.mint_nft(&seller);
+    ctx.set_verified_collection(&member, &collection);
+    init_allowlist(&mut ctx, true);
+    ctx.send(
+        &[update_allowlist(
+            &authority,
+            instruction::AddAllowedCollection { collection },
+        )],
+        &[&authority],
+    )
+    .unwrap();
+
+    let result = ctx.send(
+        &[update_allowlist(
+            &authority,
+            instruction::AddAllowedCollection { collection },
+        )],
+        &[&authority],
+    );
+    assert_error(result, ErrorCode::CollectionAlreadyAllowed);
+
+    ctx.send(
+        &[update_allowlist(
+            &authority,
+            instruction::RemoveAllowedCollection { collection },
+        )],
+        &[&authority],
+    )
+    .unwrap();
+    let result = ctx.send(
+        &[update_allowlist(
+            &authority,
+            instruction::RemoveAllowedCollection { collection },
+        )],
+        &[&authority],
+    );
+    assert_error(result, ErrorCode::CollectionNotOnAllowlist);
+
+    let result = ctx.send(&[list(&seller, &member, Some(collection))], &[&seller]);
+    assert_error(result, ErrorCode::CollectionNotAllowed);
+}
+
+#[test]
+fn rejects_allowlist_beyond_capacity() {
+    let mut ctx = TestContext::new();
+    let authority = ctx.authority.insecure_clone();
+    init_allowlist(&mut ctx, true);
+
+    for _ in 0..MAX_ALLOWED_COLLECTIONS {
+        ctx.send(
+            &[update_allowlist(
+                &authority,
+                instruction::AddAllowedCollection {
+                    collection: Pubkey::new_unique(),
+                },
+            )],
+            &[&authority],
+        )
+        .unwrap();
+    }
+    let result = ctx.send(
+        &[update_allowlist(
+            &authority,
+            instruction::AddAllowedCollection {
+                collection: Pubkey::new_unique(),
+            },
+        )],
+        &[&authority],
+    );
+
+    assert_error(result, ErrorCode::AllowlistFull);
+}
+
+#[test]
+fn rejects_allowlist_update_from_other_wallet() {
+    let mut ctx = TestContext::new();
+    let intruder = ctx.funded_keypair();
+    init_allowlist(&mut ctx, true);
+
+    let result = ctx.send(
+        &[update_allowlist(
+            &intruder,
+            instruction::SetAllowlistStrict { strict: false },
+        )],
+        &[&intruder],
+    );
+
+    assert_error(result, ErrorCode::UnauthorizedAuthority);
+}
@@ -0,0 +1,342 @@
+mod common;
+
+use anchor_lang::{
+    prelude::Pubkey,
+    solana_program::{instruction::Instruction, program_option::COption},
+};
+use anchor_spl::{
+    associated_token::{
+        get_associated_token_address,
+        spl_associated_token_account::instruction::create_associated_token_account,
+    },
+    token::spl_token::{self, error::TokenError},
+};
+use common::{assert_error, ix, TestContext, SOL};
+use nft_marketplace::{accounts, instruction, ErrorCode, Rental, SECONDS_PER_DAY};
+use nft_marketplace_client::pda;
+use solana_sdk::signature::{Keypair, Signer};
+
+const DAILY_RATE: u64 = SOL / 10;
+
+fn list_for_rent(owner: &Keypair, mint: &Pubkey, daily_rate: u64, max_days: u16) -> Instruction {
+    ix(
+        accounts::ListForRent {
+            owner: owner.pubkey(),
+            mint: *mint,
+            pda: pda::authority().0,
+            owner_token_account: get_associated_token_address(&owner.pubkey(), mint),
+            escrow_token_account: pda::escrow_token_account(mint),
+            rental: pda::rental(mint).0,
+            admins: pda::admins().0,
+            token_program: anchor_spl::token::ID,
+            associated_token_program: anchor_spl::associated_token::ID,
+            system_program: anchor_lang::system_program::ID,
+        },
+        instruction::ListForRent {
+            daily_rate,
+            max_days,
+        },
+    )
+}
+
+fn rent_nft(renter: &Keypair, owner: &Pubkey, mint: &Pubkey, days: u16) -> Instruction {
+    ix(
+        accounts::RentNFT {
+            renter: renter.pubkey(),
+            owner: *owner,
+            mint: *mint,
+            pda: pda::authority().0,
+            escrow_token_account: pda::escrow_token_account(mint),
+            renter_token_account: get_associated_token_address(&renter.pubkey(), mint),
+            rental: pda::rental(mint).0,
+            admins: pda::admins().0,
+            token_program: anchor_spl::token::ID,
+            associated_token_program: anchor_spl::associated_token::ID,
+            system_program: anchor_lang::system_program::ID,
+        },
+        instruction::RentNft {
+            days,
+            bump: pda::authority().1,
+        },
+    )
+}
+
+fn end_rental(caller: &Keypair, owner: &Pubkey, renter: &Pubkey, mint: &Pubkey) -> Instruction {
+    ix(
+        accounts::EndRental {
+            caller: caller.pubkey(),
+            owner: *owner,
+            renter: *renter,
+            mint: *mint,
+            pda: pda::authority().0,
+            renter_token_account: get_associated_token_address(renter, mint),
+            owner_token_account: get_associated_token_address(owner, mint),
+            rental: pda::rental(mint).0,
+            token_program: anchor_spl::token::ID,
+            associated_token_program: anchor_spl::associated_token::ID,
+            system_program: anchor_lang::system_program::ID,
+        },
+        instruction::EndRental {
+            bump: pda::authority().1,
+        },
+    )
+}
+
+fn delist_rental(owner: &Keypair, mint: &Pubkey) -> Instruction {
+    ix(
+        accounts::DelistRental {
+            owner: owner.pubkey(),
+            mint: *mint,
+            pda: pda::authority().0,
+            escrow_token_account: pda::escrow_token_account(mint),
+            owner_token_account: get_associated_token_address(&owner.pubkey(), mint),
+            rental: pda::rental(mint).0,
+            token_program: anchor_spl::token::ID,
+            associated_token_program: anchor_spl::associated_token::ID,
+            system_program: anchor_lang::system_program::ID,
+        },
+        instruction::DelistRental {
+            bump: pda::authority().1,
+        },
+    )
+}
+
+/// Mints an NFT to `owner` and offers it for rent for up to 7 days.
+fn rentable_nft(ctx: &mut TestContext, owner: &Keypair) -> Pubkey {
+    let mint = ctx.mint_nft(owner);
+    ctx.send(&[list_for_rent(owner, &mint, DAILY_RATE, 7)], &[owner])
+        .unwrap();
+    mint
+}
+
+#[test]
+fn rents_nft_into_frozen_renter_account() {
+    let mut ctx = TestContext::new();
+    let owner = ctx.funded_keypair();
+    let renter = ctx.funded_keypair();
+    let mint = rentable_nft(&mut ctx, &owner);
+    let owner_before = ctx.balance(&owner.pubkey());
+
+    ctx.send(&[rent_nft(&renter, &owner.pubkey(), &mint, 3)], &[&renter])
+        .unwrap();
+
+    assert_eq!(ctx.balance(&owner.pubkey()) - owner_before, 3 * DAILY_RATE);
+
+    let renter_account = ctx
+        .token_account(&get_associated_token_address(&renter.pubkey(), &mint))
+        .unwrap();
+    assert_eq!(renter_account.amount, 1);
+    assert!(renter_account.is_frozen());
+    assert_eq!(renter_account.delegate, COption::Some(pda::authority().0));
+
+    let rental: Rental = ctx.account(&pda::rental(&mint).0).unwrap();
+    assert_eq!(rental.renter, renter.pubkey());
+    assert_eq!(rental.end_time, ctx.now() + 3 * SECONDS_PER_DAY);
+}
+
+#[test]
+fn renter_cannot_move_rented_nft() {
+    let mut ctx = TestContext::new();
+    let owner = ctx.funded_keypair();
+    let renter = ctx.funded_keypair();
+    let mint = rentable_nft(&mut ctx, &owner);
+    ctx.send(&[rent_nft(&renter, &owner.pubkey(), &mint, 1)], &[&renter])
+        .unwrap();
+
+    let renter_account = get_associated_token_address(&renter.pubkey(), &mint);
+    let create = create_associated_token_account(
+        &renter.pubkey(),
+        &owner.pubkey(),
+        &mint,
+        &anchor_spl::token::ID,
+    );
+    ctx.send(&[create], &[&renter]).unwrap();
+    let transfer = spl_token::instruction::transfer_checked(
+        &spl_token::ID,
+        &renter_account,
+        &mint,
+        &get_associated_token_address(&owner.pubkey(), &mint),
+        &renter.pubkey(),
+        &[],
+        1,
+        0,
+    )
+    .unwrap();
+    let result = ctx.send(&[transfer], &[&renter]);
+
+    assert_error(result, TokenError::AccountFrozen as u32);
+    assert_eq!(ctx.token_amount(&renter.pubkey(), &mint), 1);
+}
+
+#[test]
+fn anyone_ends_expired_rental() {
+    let mut ctx = TestContext::new();
+    let owner = ctx.funded_keypair();
+    let renter = ctx.funded_keypair();
+    let cranker = ctx.funded_keypair();
+    let mint = rentable_nft(&mut ctx, &owner);
+    ctx.send(&[rent_nft(&renter, &owner.pubkey(), &mint, 1)], &[&renter])
+        .unwrap();
+
+    ctx.warp_to(ctx.now() + SECONDS_PER_DAY);
+    ctx.send(
+        &[end_rental(&cranker, &owner.pubkey(), &renter.pubkey(), &mint)],
+        &[&cranker],
+    )
+    .unwrap();
+
+    assert_eq!(ctx.token_amount(&owner.pubkey(), &mint), 1);
+    assert_eq!(ctx.token_amount(&renter.pubkey(), &mint), 0);
+    assert!(ctx.svm.get_account(&pda::rental(&mint).0).is_none());
+}
+
+#[test]
+fn rejects_ending_rental_early() {
+    let mut ctx = TestContext::new();
+    let owner = ctx.funded_keypair();
+    let renter = ctx.funded_keypair();
+    let mint = rentable_nft(&mut ctx, &owner);
+    ctx.send(&[rent_nft(&renter, &owner.pubkey(), &mint, 2)], &[&renter])
+        .unwrap();
+
+    ctx.warp_to(ctx.now() + SECONDS_PER_DAY);
+    let result = ctx.send(
+        &[end_rental(&renter, &owner.pubkey(), &renter.pubkey(), &mint)],
+        &[&renter],
+    );
+
+    assert_error(result, ErrorCode::RentalNotExpired);
+}
+
+#[test]
+fn rejects_ending_unrented_listing() {
+    let mut ctx = TestContext::new();
+    let owner = ctx.funded_keypair();
+    let mint = rentable_nft(&mut ctx, &owner);
+    // An unrented listing records the default pubkey as renter, give it a
+    // token account so account validation lets the call through
+    let create = create_associated_token_account(
+        &owner.pubkey(),
+        &Pubkey::default(),
+        &mint,
+        &anchor_spl::token::ID,
+    );
+    ctx.send(&[create], &[&owner]).unwrap();
+
+    let result = ctx.send(
+        &[end_rental(&owner, &owner.pubkey(), &Pubkey::default(), &mint)],
+        &[&owner],
+    );
+
+    assert_error(result, ErrorCode::RentalNotActive);
+}
+
+#[test]
+fn rejects_second_renter() {
+    let mut ctx = TestContext::new();
+    let owner = ctx.funded_keypair();
+    let renter = ctx.funded_keypair();
+    let other_renter = ctx.funded_keypair();
+    let mint = rentable_nft(&mut ctx, &owner);
+    ctx.send(&[rent_nft(&renter, &owner.pubkey(), &mint, 1)], &[&renter])
+        .unwrap();
+
+    let result = ctx.send(
+        &[rent_nft(&other_renter, &owner.pubkey(), &mint, 1)],
+        &[&other_renter],
+    );
+
+    assert_error(result, ErrorCode::RentalActive);
+}
+
+#[test]
+fn rejects_delisting_rented_nft() {
+    let mut ctx = TestContext::new();
+    let owner = ctx.funded_keypair();
+    let renter = ctx.funded_keypair();
+    let mint = rentable_nft(&mut ctx, &owner);
+    ctx.send(&[rent_nft(&renter, &owner.pubkey(), &mint, 1)], &[&renter])
+        .unwrap();
+
+    let result = ctx.send(&[delist_rental(&owner, &mint)], &[&owner]);
+
+    assert_error(result, ErrorCode::RentalActive);
+}
+
+#[test]
+fn rejects_delisting_another_owners_rental() {
+    let mut ctx = TestContext::new();
+    let owner = ctx.funded_keypair();
+    let intruder = ctx.funded_keypair();
+    let mint = rentable_nft(&mut ctx, &owner);
+
+    let result = ctx.send(&[delist_rental(&intruder, &mint)], &[&intruder]);
+
+    assert_error(result, ErrorCode::UnauthorizedSeller);
+    assert_eq!(ctx.token_amount(&pda::authority().0, &mint), 1);
+}
+
+#[test]
+fn delists_unrented_nft() {
+    let mut ctx = TestContext::new();
+    let owner = ctx.funded_keypair();
+    let mint = rentable_nft(&mut ctx, &owner);
+
+    ctx.send(&[delist_rental(&owner, &mint)], &[&owner]).unwrap();
+
+    assert_eq!(ctx.token_amount(&owner.pubkey(), &mint), 1);
+    assert!(ctx.svm.get_account(&pda::rental(&mint).0).is_none());
+}
+
+#[test]
+fn rejects_invalid_rental_durations() {
+    let mut ctx = TestContext::new();
+    let owner = ctx.funded_keypair();
+    let renter = ctx.funded_keypair();
+    let mint = rentable_nft(&mut ctx, &owner);
+
+    for days in [0, 8] {
+        let result = ctx.send(&[rent_nft(&renter, &owner.pubkey(), &mint, days)], &[&renter]);
+        assert_error(result, ErrorCode::InvalidRentalDuration);
+    }
+
+    let other_mint = ctx.mint_nft(&owner);
+    let result = ctx.send(&[list_for_rent(&owner, &other_mint, DAILY_RATE, 0)], &[&owner]);
+    assert_error(result, ErrorCode::InvalidRentalDuration);
+}
+
+#[test]
+fn rejects_zero_daily_rate() {
+    let mut ctx = TestContext::new();
+    let owner = ctx.funded_keypair();
+    let mint = ctx.mint_nft(&owner);
+
+    let result = ctx.send(&[list_for_rent(&owner, &mint, 0, 7)], &[&owner]);
+
+    assert_error(result, ErrorCode::InvalidPrice);
+}
+
+#[test]
+fn rejects_overflowing_rental_cost() {
+    let mut ctx = TestContext::new();
+    let owner = ctx.funded_keypair();
+    let renter = ctx.funded_keypair();
+    let mint = ctx.mint_nft(&owner);
+    ctx.send(&[list_for_rent(&owner, &mint, u64::MAX, 2)], &[&owner])
+        .unwrap();
+
+    let result = ctx.send(&[rent_nft(&renter, &owner.pubkey(), &mint, 2)], &[&renter]);
+
+    assert_error(result, ErrorCode::MathOverflow);
+}
+
+#[test]
+fn rejects_mint_without_marketplace_freeze_authority() {
+    let mut ctx = TestContext::new();
+    let owner = ctx.funded_keypair();
+    let mint = ctx.spl_mint(&owner, None);
+
+    let result = ctx.send(&[list_for_rent(&owner, &mint, DAILY_RATE, 7)], &[&owner]);
+
+    assert_error(result, ErrorCode::FreezeAuthorityMismatch);
+}
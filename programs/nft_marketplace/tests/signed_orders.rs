@@ -0,0 +1,318 @@
+mod common;
+
+use anchor_lang::{
+    prelude::Pubkey,
+    solana_program::{instruction::Instruction, sysvar},
+};
+use anchor_spl::{associated_token::get_associated_token_address, token::spl_token};
+use common::{assert_error, ix, TestContext, FEE_BPS, SOL};
+use nft_marketplace::{accounts, instruction, ErrorCode, OrderNonce, SignedOrder};
+use nft_marketplace_client::pda;
+use solana_ed25519_program::new_ed25519_instruction_with_signature;
+use solana_sdk::signature::{Keypair, Signer};
+
+fn fill_signed_order(ctx: &TestContext, buyer: &Keypair, order: &SignedOrder) -> Instruction {
+    fill_signed_order_paying(ctx, buyer, order, Some(order.seller), None)
+}
+
+/// `fill_signed_order` paying the seller's wallet or their proceeds vault.
+fn fill_signed_order_paying(
+    ctx: &TestContext,
+    buyer: &Keypair,
+    order: &SignedOrder,
+    seller: Option<Pubkey>,
+    proceeds_vault: Option<Pubkey>,
+) -> Instruction {
+    ix(
+        accounts::FillSignedOrder {
+            buyer: buyer.pubkey(),
+            seller,
+            proceeds_vault,
+            marketplace: pda::marketplace().0,
+            treasury: ctx.treasury,
+            mint: order.mint,
+            pda: pda::authority().0,
+            seller_token_account: get_associated_token_address(&order.seller, &order.mint),
+            buyer_token_account: get_associated_token_address(&buyer.pubkey(), &order.mint),
+            order_nonce: pda::order_nonce(&order.seller, order.nonce).0,
+            metadata_account: pda::metadata(&order.mint),
+            collection_stats: None,
+            instructions_sysvar: sysvar::instructions::ID,
+            admins: pda::admins().0,
+            token_program: anchor_spl::token::ID,
+            associated_token_program: anchor_spl::associated_token::ID,
+            system_program: anchor_lang::system_program::ID,
+        },
+        instruction::FillSignedOrder {
+            order: order.clone(),
+            bump: pda::authority().1,
+        },
+    )
+}
+
+fn cancel_signed_order(seller: &Keypair, nonce: u64) -> Instruction {
+    ix(
+        accounts::CancelSignedOrder {
+            seller: seller.pubkey(),
+            order_nonce: pda::order_nonce(&seller.pubkey(), nonce).0,
+            system_program: anchor_lang::system_program::ID,
+        },
+        instruction::CancelSignedOrder { nonce },
+    )
+}
+
+fn signature_verification(signer: &Keypair, order: &SignedOrder) -> Instruction {
+    let message = order.message().unwrap();
+    let signature: [u8; 64] = signer.sign_message(&message).as_ref().try_into().unwrap();
+    new_ed25519_instruction_with_signature(&message, &signature, &signer.pubkey().to_bytes())
+}
+
+fn approve_marketplace(ctx: &mut TestContext, seller: &Keypair, mint: &Pubkey) {
+    let approve = spl_token::instruction::approve(
+        &spl_token::ID,
+        &get_associated_token_address(&seller.pubkey(), mint),
+        &pda::authority().0,
+        &seller.pubkey(),
+        &[],
+        1,
+    )
+    .unwrap();
+    ctx.send(&[approve], &[seller]).unwrap();
+}
+
+/// Mints an NFT to `seller`, delegates it to the marketplace and returns an
+/// order for it that expires in an hour.
+fn signed_order(ctx: &mut TestContext, seller: &Keypair, price: u64) -> SignedOrder {
+    let mint = ctx.mint_nft(seller);
+    approve_marketplace(ctx, seller, &mint);
+    SignedOrder {
+        seller: seller.pubkey(),
+        mint,
+        price,
+        expiry: ctx.now() + 3_600,
+        nonce: 1,
+    }
+}
+
+#[test]
+fn fills_signed_order() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let price = 2 * SOL;
+    let order = signed_order(&mut ctx, &seller, price);
+    let seller_before = ctx.balance(&seller.pubkey());
+    let treasury_before = ctx.balance(&ctx.treasury);
+
+    ctx.send(
+        &[
+            signature_verification(&seller, &order),
+            fill_signed_order(&ctx, &buyer, &order),
+        ],
+        &[&buyer],
+    )
+    .unwrap();
+
+    assert_eq!(ctx.token_amount(&buyer.pubkey(), &order.mint), 1);
+    assert_eq!(ctx.token_amount(&seller.pubkey(), &order.mint), 0);
+    let fee = price * FEE_BPS as u64 / 10_000;
+    assert_eq!(ctx.balance(&seller.pubkey()) - seller_before, price - fee);
+    assert_eq!(ctx.balance(&ctx.treasury) - treasury_before, fee);
+    let nonce: OrderNonce = ctx
+        .account(&pda::order_nonce(&seller.pubkey(), order.nonce).0)
+        .unwrap();
+    assert_eq!(nonce.seller, seller.pubkey());
+    assert_eq!(nonce.nonce, order.nonce);
+}
+
+#[test]
+fn rejects_replayed_order() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let order = signed_order(&mut ctx, &seller, SOL);
+    let fill = [
+        signature_verification(&seller, &order),
+        fill_signed_order(&ctx, &buyer, &order),
+    ];
+    ctx.send(&fill, &[&buyer]).unwrap();
+
+    assert!(ctx.send(&fill, &[&buyer]).is_err());
+}
+
+#[test]
+fn rejects_cancelled_order() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let order = signed_order(&mut ctx, &seller, SOL);
+    ctx.send(&[cancel_signed_order(&seller, order.nonce)], &[&seller])
+        .unwrap();
+
+    let result = ctx.send(
+        &[
+            signature_verification(&seller, &order),
+            fill_signed_order(&ctx, &buyer, &order),
+        ],
+        &[&buyer],
+    );
+
+    // The nonce account already exists, so creating it fails
+    assert!(result.is_err());
+    assert_eq!(ctx.token_amount(&seller.pubkey(), &order.mint), 1);
+}
+
+#[test]
+fn rejects_expired_order() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let mut order = signed_order(&mut ctx, &seller, SOL);
+    order.expiry = ctx.now();
+
+    let result = ctx.send(
+        &[
+            signature_verification(&seller, &order),
+            fill_signed_order(&ctx, &buyer, &order),
+        ],
+        &[&buyer],
+    );
+
+    assert_error(result, ErrorCode::OrderExpired);
+}
+
+#[test]
+fn rejects_order_without_delegation() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let order = SignedOrder {
+        seller: seller.pubkey(),
+        mint: ctx.mint_nft(&seller),
+        price: SOL,
+        expiry: ctx.now() + 3_600,
+        nonce: 1,
+    };
+
+    let result = ctx.send(
+        &[
+            signature_verification(&seller, &order),
+            fill_signed_order(&ctx, &buyer, &order),
+        ],
+        &[&buyer],
+    );
+
+    assert_error(result, ErrorCode::MissingDelegation);
+}
+
+#[test]
+fn rejects_order_for_nft_seller_no_longer_holds() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let order = signed_order(&mut ctx, &seller, SOL);
+    ctx.list_nft(&seller, &order.mint, SOL);
+
+    let result = ctx.send(
+        &[
+            signature_verification(&seller, &order),
+            fill_signed_order(&ctx, &buyer, &order),
+        ],
+        &[&buyer],
+    );
+
+    assert_error(result, ErrorCode::InvalidSellerTokenAccount);
+}
+
+#[test]
+fn rejects_order_without_signature_verification() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let order = signed_order(&mut ctx, &seller, SOL);
+
+    let result = ctx.send(&[fill_signed_order(&ctx, &buyer, &order)], &[&buyer]);
+
+    assert_error(result, ErrorCode::MissingSignatureVerification);
+}
+
+#[test]
+fn rejects_order_with_tampered_price() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let order = signed_order(&mut ctx, &seller, 5 * SOL);
+    let tampered = SignedOrder {
+        price: SOL,
+        ..order.clone()
+    };
+
+    let result = ctx.send(
+        &[
+            signature_verification(&seller, &order),
+            fill_signed_order(&ctx, &buyer, &tampered),
+        ],
+        &[&buyer],
+    );
+
+    assert_error(result, ErrorCode::InvalidSignatureVerification);
+}
+
+#[test]
+fn rejects_order_signed_by_another_wallet() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let order = signed_order(&mut ctx, &seller, SOL);
+
+    let result = ctx.send(
+        &[
+            signature_verification(&buyer, &order),
+            fill_signed_order(&ctx, &buyer, &order),
+        ],
+        &[&buyer],
+    );
+
+    assert_error(result, ErrorCode::InvalidSignatureVerification);
+    assert_eq!(ctx.token_amount(&seller.pubkey(), &order.mint), 1);
+}
+
+#[test]
+fn pays_signed_order_into_proceeds_vault() {
+    let mut ctx = TestContext::new();
+    let seller = ctx.funded_keypair();
+    let buyer = ctx.funded_keypair();
+    let price = 2 * SOL;
+    let order = signed_order(&mut ctx, &seller, price);
+    let vault = pda::proceeds_vault(&seller.pubkey()).0;
+    ctx.send(
+        &[ix(
+            accounts::InitProceedsVault {
+                seller: seller.pubkey(),
+                proceeds_vault: vault,
+                system_program: anchor_lang::system_program::ID,
+            },
+            instruction::InitProceedsVault {},
+        )],
+        &[&seller],
+    )
+    .unwrap();
+    let seller_before = ctx.balance(&seller.pubkey());
+    let vault_before = ctx.balance(&vault);
+
+    ctx.send(
+        &[
+            signature_verification(&seller, &order),
+            fill_signed_order_paying(&ctx, &buyer, &order, None, Some(vault)),
+        ],
+        &[&buyer],
+    )
+    .unwrap();
+
+    assert_eq!(ctx.balance(&seller.pubkey()), seller_before);
+    assert_eq!(
+        ctx.balance(&vault) - vault_before,
+        price - price * FEE_BPS as u64 / 10_000
+    );
+    assert_eq!(ctx.token_amount(&buyer.pubkey(), &order.mint), 1);
+}
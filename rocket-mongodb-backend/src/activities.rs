@@ -0,0 +1,97 @@
+//! Read side of the `Activities` collection the chain indexer fills: the
+//! history of one NFT, everything a wallet bought, sold or listed, and the
+//! marketplace-wide sales feed. All of them page newest first.
+
+use mongodb::bson::{ doc, Document };
+use rocket::futures::TryStreamExt;
+use rocket::get;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket_db_pools::mongodb::options::FindOptions;
+use rocket_db_pools::Connection;
+
+use crate::db::MainDatabase;
+use crate::models::{ ActivityEntry, NftActivity };
+use crate::query::ActivityQuery;
+use crate::routes::error_response;
+
+#[get("/nfts/<mint_address>/activities?<query..>")]
+pub async fn nft_activities(
+    mint_address: String,
+    query: ActivityQuery,
+    db: Connection<MainDatabase>
+) -> Result<Json<serde_json::Value>, status::Custom<Json<serde_json::Value>>> {
+    find_activities(&db, doc! { "mint_address": mint_address }, &query).await
+}
+
+#[get("/wallets/<address>/activities?<query..>")]
+pub async fn wallet_activities(
+    address: String,
+    query: ActivityQuery,
+    db: Connection<MainDatabase>
+) -> Result<Json<serde_json::Value>, status::Custom<Json<serde_json::Value>>> {
+    let filter = doc! { "$or": [{ "from": &address }, { "to": &address }] };
+    find_activities(&db, filter, &query).await
+}
+
+#[get("/activities/sales?<query..>")]
+pub async fn recent_sales(
+    query: ActivityQuery,
+    db: Connection<MainDatabase>
+) -> Result<Json<serde_json::Value>, status::Custom<Json<serde_json::Value>>> {
+    find_activities(&db, doc! { "kind": "sale" }, &query).await
+}
+
+async fn find_activities(
+    db: &Connection<MainDatabase>,
+    filter: Document,
+    query: &ActivityQuery
+) -> Result<Json<serde_json::Value>, status::Custom<Json<serde_json::Value>>> {
+    let collection = db.database("nft_marketplace").collection::<Document>("Activities");
+
+    let filter = query.filter(filter).map_err(|message| error_response(Status::BadRequest, message))?;
+    let limit = query.limit();
+    // One extra document tells whether there is a next page
+    let options = FindOptions::builder()
+        .sort(query.sort())
+        .limit(limit + 1)
+        .build();
+
+    let mut documents = match collection.find(filter, options).await {
+        Ok(cursor) => {
+            cursor.try_collect::<Vec<Document>>().await.map_err(|e| {
+                println!("Error collecting activities: {}", e);
+                error_response(Status::InternalServerError, format!("Failed to collect activities: {}", e))
+            })?
+        }
+        Err(e) => {
+            println!("Error finding activities: {}", e);
+            return Err(error_response(Status::InternalServerError, format!("Failed to find activities: {}", e)));
+        }
+    };
+
+    let next_cursor = if (documents.len() as i64) > limit {
+        documents.truncate(limit as usize);
+        documents
+            .last()
+            .and_then(|document| document.get_object_id("_id").ok())
+            .map(|id| id.to_hex())
+    } else {
+        None
+    };
+
+    let activities = documents
+        .into_iter()
+        .map(|document| mongodb::bson::from_document::<NftActivity>(document).map(ActivityEntry::from))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| {
+            println!("Error decoding activities: {}", e);
+            error_response(Status::InternalServerError, format!("Failed to decode activities: {}", e))
+        })?;
+
+    Ok(Json(serde_json::json!({
+        "activities": activities,
+        "next_cursor": next_cursor
+    })))
+}
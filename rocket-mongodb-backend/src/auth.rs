@@ -0,0 +1,235 @@
+//! Sign-In-With-Solana style sessions for the mutating routes.
+//!
+//! A client fetches a nonce for its wallet from `/auth/nonce/<wallet>`, signs
+//! the returned message with the wallet and posts the signature to
+//! `/auth/verify`. The session token it gets back is sent as
+//! `Authorization: Bearer <token>` and resolved by the `AuthenticatedWallet`
+//! request guard.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use mongodb::bson::{doc, DateTime};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::{catch, get, post};
+use rocket_db_pools::Connection;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use uuid::Uuid;
+
+use crate::db::MainDatabase;
+use crate::models::{AuthNonce, Session};
+use crate::routes::error_response;
+
+const NONCE_TTL_SECS: i64 = 5 * 60;
+const SESSION_TTL_SECS: i64 = 24 * 60 * 60;
+
+/// Message the wallet signs to prove it controls `wallet`.
+pub fn sign_in_message(wallet: &str, nonce: &str) -> String {
+    format!(
+        "NFT Marketplace wants you to sign in with your Solana account:\n{}\n\nNonce: {}",
+        wallet, nonce
+    )
+}
+
+/// Checks a base58 ed25519 `signature` by `wallet` over the sign-in message.
+pub fn verify_signature(wallet: &str, nonce: &str, signature: &str) -> bool {
+    let (Ok(pubkey), Ok(signature)) = (wallet.parse::<Pubkey>(), signature.parse::<Signature>())
+    else {
+        return false;
+    };
+
+    signature.verify(pubkey.as_ref(), sign_in_message(wallet, nonce).as_bytes())
+}
+
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// TTL indexes only delete documents by a date field.
+fn expiry(expires_at: i64) -> DateTime {
+    DateTime::from_millis(expires_at * 1000)
+}
+
+#[get("/auth/nonce/<wallet>")]
+pub async fn get_nonce(
+    wallet: String,
+    db: Connection<MainDatabase>,
+) -> Result<Json<serde_json::Value>, status::Custom<Json<serde_json::Value>>> {
+    if wallet.parse::<Pubkey>().is_err() {
+        return Err(error_response(Status::BadRequest, "Invalid wallet address"));
+    }
+
+    let nonces = db
+        .database("nft_marketplace")
+        .collection::<AuthNonce>("AuthNonces");
+    let expires_at = now() + NONCE_TTL_SECS;
+    let auth_nonce = AuthNonce {
+        wallet: wallet.clone(),
+        nonce: Uuid::new_v4().to_string(),
+        expires_at,
+        expiry: Some(expiry(expires_at)),
+    };
+
+    match nonces.insert_one(&auth_nonce, None).await {
+        Ok(_) => Ok(Json(serde_json::json!({
+            "status": "success",
+            "nonce": auth_nonce.nonce,
+            "message": sign_in_message(&wallet, &auth_nonce.nonce),
+            "expires_at": auth_nonce.expires_at
+        }))),
+        Err(e) => {
+            println!("Error storing nonce: {}", e);
+            Err(error_response(
+                Status::InternalServerError,
+                format!("Failed to create nonce: {}", e),
+            ))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VerifySignature {
+    pub wallet: String,
+    pub nonce: String,
+    /// Base58 signature of the sign-in message
+    pub signature: String,
+}
+
+#[post("/auth/verify", format = "json", data = "<payload>")]
+pub async fn verify(
+    payload: Json<VerifySignature>,
+    db: Connection<MainDatabase>,
+) -> Result<Json<serde_json::Value>, status::Custom<Json<serde_json::Value>>> {
+    let database = db.database("nft_marketplace");
+    let nonces = database.collection::<AuthNonce>("AuthNonces");
+
+    // Nonces are single use, consume it whether or not the signature holds
+    let filter = doc! {
+        "wallet": &payload.wallet,
+        "nonce": &payload.nonce,
+        "expires_at": { "$gt": now() }
+    };
+    match nonces.find_one_and_delete(filter, None).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return Err(error_response(
+                Status::Unauthorized,
+                "Unknown or expired nonce",
+            ))
+        }
+        Err(e) => {
+            println!("Error consuming nonce: {}", e);
+            return Err(error_response(
+                Status::InternalServerError,
+                format!("Failed to verify nonce: {}", e),
+            ));
+        }
+    }
+
+    if !verify_signature(&payload.wallet, &payload.nonce, &payload.signature) {
+        return Err(error_response(Status::Unauthorized, "Invalid signature"));
+    }
+
+    let expires_at = now() + SESSION_TTL_SECS;
+    let session = Session {
+        token: Uuid::new_v4().simple().to_string(),
+        wallet: payload.wallet.clone(),
+        expires_at,
+        expiry: Some(expiry(expires_at)),
+    };
+    match database
+        .collection::<Session>("Sessions")
+        .insert_one(&session, None)
+        .await
+    {
+        Ok(_) => {
+            println!("Session started for {}", session.wallet);
+            Ok(Json(serde_json::json!({
+                "status": "success",
+                "token": session.token,
+                "expires_at": session.expires_at
+            })))
+        }
+        Err(e) => {
+            println!("Error storing session: {}", e);
+            Err(error_response(
+                Status::InternalServerError,
+                format!("Failed to create session: {}", e),
+            ))
+        }
+    }
+}
+
+/// Wallet behind the request's session token. Routes that modify an NFT
+/// document compare it against the document's `owner`.
+pub struct AuthenticatedWallet(pub String);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthenticatedWallet {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(token) = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+        else {
+            return Outcome::Error((Status::Unauthorized, "Missing session token"));
+        };
+
+        let db = match req.guard::<Connection<MainDatabase>>().await {
+            Outcome::Success(db) => db,
+            _ => return Outcome::Error((Status::InternalServerError, "Database unavailable")),
+        };
+        let sessions = db
+            .database("nft_marketplace")
+            .collection::<Session>("Sessions");
+
+        let filter = doc! { "token": token, "expires_at": { "$gt": now() } };
+        match sessions.find_one(filter, None).await {
+            Ok(Some(session)) => Outcome::Success(AuthenticatedWallet(session.wallet)),
+            Ok(None) => Outcome::Error((Status::Unauthorized, "Invalid or expired session token")),
+            Err(e) => {
+                println!("Error looking up session: {}", e);
+                Outcome::Error((Status::InternalServerError, "Failed to look up session"))
+            }
+        }
+    }
+}
+
+#[catch(401)]
+pub fn unauthorized() -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "status": "error",
+        "message": "Sign in with your wallet to do this"
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::signature::{Keypair, Signer};
+
+    use super::*;
+
+    #[test]
+    fn verifies_wallet_signature() {
+        let wallet = Keypair::new();
+        let address = wallet.pubkey().to_string();
+        let signature = wallet.sign_message(sign_in_message(&address, "nonce").as_bytes());
+
+        assert!(verify_signature(&address, "nonce", &signature.to_string()));
+        assert!(!verify_signature(&address, "other", &signature.to_string()));
+        assert!(!verify_signature(
+            &Pubkey::new_unique().to_string(),
+            "nonce",
+            &signature.to_string()
+        ));
+        assert!(!verify_signature(&address, "nonce", "not a signature"));
+    }
+}
@@ -1,5 +1,105 @@
-use rocket_db_pools::{ mongodb::Client, Database };
+use std::time::Duration;
+
+use mongodb::bson::{ doc, Document };
+use rocket::fairing::{ self, AdHoc };
+use rocket::{ Build, Rocket };
+use rocket_db_pools::{
+    mongodb::{ options::IndexOptions, Client, Database as MongoDatabase, IndexModel },
+    Database,
+};
 
 #[derive(Database)]
 #[database("nftDb")]
 pub struct MainDatabase(Client);
+
+/// Creates the indexes the routes query by. Creating an existing index is a
+/// no-op, so this runs on every launch.
+pub fn indexes() -> AdHoc {
+    AdHoc::try_on_ignite("MongoDB indexes", create_indexes)
+}
+
+async fn create_indexes(rocket: Rocket<Build>) -> fairing::Result {
+    let Some(db) = MainDatabase::fetch(&rocket) else {
+        return Err(rocket);
+    };
+    let database = db.database("nft_marketplace");
+
+    let collections = [
+        (
+            "MintedNFTs",
+            vec![
+                // get_listed_nfts: recency and price sorts, then each filter
+                doc! { "is_listed": 1, "listed_at": -1, "_id": -1 },
+                doc! { "is_listed": 1, "price": 1, "_id": 1 },
+                doc! { "is_listed": 1, "symbol": 1, "listed_at": -1, "_id": -1 },
+                doc! { "is_listed": 1, "owner": 1, "listed_at": -1, "_id": -1 },
+                doc! { "is_listed": 1, "collection": 1, "listed_at": -1, "_id": -1 },
+                doc! { "is_listed": 1, "rarity.rank": 1, "_id": 1 },
+                doc! {
+                    "is_listed": 1,
+                    "metadata.attributes.trait_type": 1,
+                    "metadata.attributes.value": 1
+                },
+                // Rarity is recomputed per verified collection
+                doc! { "collection": 1 },
+            ],
+        ),
+        (
+            "Activities",
+            vec![
+                doc! { "mint_address": 1, "_id": -1 },
+                doc! { "from": 1, "_id": -1 },
+                doc! { "to": 1, "_id": -1 },
+                doc! { "kind": 1, "_id": -1 },
+                // The indexer upserts by these
+                doc! { "signature": 1, "mint_address": 1, "kind": 1 },
+            ],
+        ),
+        ("WalletPortfolios", vec![doc! { "wallet": 1 }]),
+        ("Sessions", vec![doc! { "token": 1 }]),
+        ("AuthNonces", vec![doc! { "wallet": 1, "nonce": 1 }]),
+    ];
+
+    for (name, keys) in collections {
+        let models = keys
+            .into_iter()
+            .map(|keys| IndexModel::builder().keys(keys).build());
+        if let Err(e) = create(&database, name, models).await {
+            println!("Error creating indexes on {}: {}", name, e);
+            return Err(rocket);
+        }
+    }
+
+    // /list_nft and the indexer upsert by mint. A database that still holds
+    // duplicates has to run `cargo run -- dedupe-mints` first.
+    let unique = IndexOptions::builder().unique(true).build();
+    // Lookups already reject expired documents, the TTL index deletes them
+    let expire = IndexOptions::builder().expire_after(Duration::ZERO).build();
+    let constrained = [
+        ("MintedNFTs", doc! { "mint_address": 1 }, unique),
+        ("Sessions", doc! { "expiry": 1 }, expire.clone()),
+        ("AuthNonces", doc! { "expiry": 1 }, expire),
+    ];
+
+    for (name, keys, options) in constrained {
+        let model = IndexModel::builder().keys(keys).options(options).build();
+        if let Err(e) = create(&database, name, [model]).await {
+            println!("Error creating indexes on {}: {}", name, e);
+            return Err(rocket);
+        }
+    }
+
+    Ok(rocket)
+}
+
+async fn create(
+    database: &MongoDatabase,
+    name: &str,
+    models: impl IntoIterator<Item = IndexModel>
+) -> Result<(), String> {
+    database
+        .collection::<Document>(name)
+        .create_indexes(models, None).await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,1186 @@
+//! Background task that keeps `MintedNFTs` in sync with the program's
+//! `Listing` accounts, so the collection no longer depends on the frontend
+//! reporting every list, sale and delist.
+//!
+//! Each poll fetches the program's transactions since the last processed
+//! signature, records their activity in `Activities`, finds the mints whose
+//! listing they touched and reconciles those documents with the current
+//! on-chain `Listing`. The poll interval is read from `INDEXER_POLL_SECS`.
+
+use std::{collections::BTreeMap, env, future::Future, time::Duration};
+
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use mongodb::bson::{doc, Document};
+use nft_marketplace::{instruction, NftSold, BATCH_GROUP_LEN};
+use nft_marketplace_client::{Listing, PROGRAM_ID};
+use rocket::fairing::AdHoc;
+use rocket_db_pools::{
+    mongodb::{options::UpdateOptions, Client, Collection, Database as MongoDatabase},
+    Database,
+};
+use solana_client::{
+    nonblocking::rpc_client::RpcClient, rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_config::RpcTransactionConfig,
+};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use solana_transaction_status_client_types::{UiLoadedAddresses, UiTransactionEncoding};
+
+use crate::auth::now;
+use crate::db::MainDatabase;
+use crate::metadata;
+use crate::models::{ActivityKind, MintedNFTs, NftActivity, NATIVE_MINT};
+use crate::rarity;
+use crate::solana::{self, TokenMetadata};
+
+const DEFAULT_POLL_SECS: u64 = 10;
+/// Most signatures `getSignaturesForAddress` returns per page
+const SIGNATURE_PAGE_LIMIT: usize = 1_000;
+const PROGRAM_DATA_LOG: &str = "Program data: ";
+const PROGRAM_LOG_PREFIX: &str = "Program ";
+/// Index of the mint among the accounts of `list_nft`, `update_price` and
+/// `delist_nft`
+const MINT_ACCOUNT: usize = 1;
+/// Accounts before the first group of `batch_list` and `batch_delist`
+const BATCH_LIST_FIXED_ACCOUNTS: usize = 7;
+const BATCH_DELIST_FIXED_ACCOUNTS: usize = 3;
+const CURSOR_ID: &str = "listings";
+
+#[derive(Clone, Copy, Debug)]
+pub struct SignatureStatus {
+    pub signature: Signature,
+    pub failed: bool,
+}
+
+/// Top-level instruction of a transaction that invoked the program.
+#[derive(Clone, Debug, Default)]
+pub struct ProgramInstruction {
+    pub accounts: Vec<Pubkey>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ProgramTransaction {
+    pub signature: Signature,
+    pub instructions: Vec<ProgramInstruction>,
+    pub logs: Vec<String>,
+    /// Unix timestamp in seconds
+    pub block_time: Option<i64>,
+}
+
+/// Read access to the cluster. `RpcChain` talks to a validator; the tests use
+/// an in-memory stand-in.
+pub trait ChainSource {
+    /// Signatures involving the program newer than `until`, oldest first.
+    fn signatures_after(
+        &self,
+        until: Option<Signature>,
+    ) -> impl Future<Output = Result<Vec<SignatureStatus>, String>> + Send;
+
+    fn transaction(
+        &self,
+        signature: &Signature,
+    ) -> impl Future<Output = Result<ProgramTransaction, String>> + Send;
+
+    fn listing(
+        &self,
+        mint: &Pubkey,
+    ) -> impl Future<Output = Result<Option<Listing>, String>> + Send;
+
+    fn token_metadata(
+        &self,
+        mint: &Pubkey,
+    ) -> impl Future<Output = Result<Option<TokenMetadata>, String>> + Send;
+}
+
+/// What a transaction did to a mint. Prices are in lamports.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Activity {
+    Minted {
+        minter: Pubkey,
+    },
+    Listed {
+        seller: Pubkey,
+        price: u64,
+    },
+    PriceUpdated {
+        seller: Pubkey,
+        price: u64,
+    },
+    Delisted {
+        seller: Pubkey,
+    },
+    Sold {
+        seller: Pubkey,
+        buyer: Pubkey,
+        price: u64,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedActivity {
+    pub signature: Signature,
+    pub block_time: Option<i64>,
+    pub mint: Pubkey,
+    pub activity: Activity,
+}
+
+impl IndexedActivity {
+    /// The `Activities` document for this activity.
+    pub fn record(&self) -> NftActivity {
+        let (kind, price, from, to) = match self.activity {
+            Activity::Minted { minter } => (ActivityKind::Mint, None, None, Some(minter)),
+            Activity::Listed { seller, price } => {
+                (ActivityKind::List, Some(price), Some(seller), None)
+            }
+            Activity::PriceUpdated { seller, price } => {
+                (ActivityKind::PriceChange, Some(price), Some(seller), None)
+            }
+            Activity::Delisted { seller } => (ActivityKind::Delist, None, Some(seller), None),
+            Activity::Sold {
+                seller,
+                buyer,
+                price,
+            } => (ActivityKind::Sale, Some(price), Some(seller), Some(buyer)),
+        };
+
+        NftActivity {
+            mint_address: self.mint.to_string(),
+            kind,
+            signature: self.signature.to_string(),
+            price,
+            from: from.map(|from| from.to_string()),
+            to: to.map(|to| to.to_string()),
+            timestamp: self.block_time.unwrap_or_default(),
+        }
+    }
+}
+
+/// State of a mint after a sync, as it should be stored in `MintedNFTs`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListingUpdate {
+    pub mint: Pubkey,
+    /// Seller while listed, buyer after a sale, unchanged otherwise
+    pub owner: Option<Pubkey>,
+    /// Price in lamports while listed
+    pub price: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct SyncBatch {
+    /// Oldest first
+    pub activities: Vec<IndexedActivity>,
+    pub updates: Vec<ListingUpdate>,
+    /// Newest signature seen, to pass as `after` on the next sync
+    pub last_signature: Option<Signature>,
+}
+
+/// Activity per mint in `tx`. Mints are read at their position in each
+/// instruction's accounts, which the program checked against their listing.
+/// Sales are read from the `NftSold` events so every way of buying is covered.
+pub fn decode_activity(tx: &ProgramTransaction) -> Vec<(Pubkey, Activity)> {
+    let mut activity = Vec::new();
+
+    for ix in &tx.instructions {
+        activity.extend(instruction_activity(ix));
+    }
+
+    for sale in sold_events(tx) {
+        activity.push((
+            sale.mint,
+            Activity::Sold {
+                seller: sale.seller,
+                buyer: sale.buyer,
+                price: sale.price,
+            },
+        ));
+    }
+
+    activity
+}
+
+/// The signer is the first account of every instruction decoded here.
+fn instruction_activity(ix: &ProgramInstruction) -> Vec<(Pubkey, Activity)> {
+    let (Some(discriminator), Some(&signer)) = (ix.data.get(..8), ix.accounts.first()) else {
+        return Vec::new();
+    };
+    let mut args = &ix.data[8..];
+    let mints = ix.accounts.get(MINT_ACCOUNT).copied().into_iter();
+
+    if discriminator == instruction::MintNft::DISCRIMINATOR {
+        mints
+            .map(|mint| (mint, Activity::Minted { minter: signer }))
+            .collect()
+    } else if discriminator == instruction::ListNft::DISCRIMINATOR {
+        let Ok(args) = instruction::ListNft::deserialize(&mut args) else {
+            return Vec::new();
+        };
+        mints
+            .map(|mint| {
+                let activity = Activity::Listed {
+                    seller: signer,
+                    price: args.price,
+                };
+                (mint, activity)
+            })
+            .collect()
+    } else if discriminator == instruction::BatchList::DISCRIMINATOR {
+        let Ok(args) = instruction::BatchList::deserialize(&mut args) else {
+            return Vec::new();
+        };
+        // Mints come in the same order as their prices
+        batch_mints(&ix.accounts, BATCH_LIST_FIXED_ACCOUNTS)
+            .zip(args.prices)
+            .map(|(mint, price)| {
+                let activity = Activity::Listed {
+                    seller: signer,
+                    price,
+                };
+                (mint, activity)
+            })
+            .collect()
+    } else if discriminator == instruction::UpdatePrice::DISCRIMINATOR {
+        let Ok(args) = instruction::UpdatePrice::deserialize(&mut args) else {
+            return Vec::new();
+        };
+        mints
+            .map(|mint| {
+                let activity = Activity::PriceUpdated {
+                    seller: signer,
+                    price: args.new_price,
+                };
+                (mint, activity)
+            })
+            .collect()
+    } else if discriminator == instruction::DelistNft::DISCRIMINATOR {
+        mints
+            .map(|mint| (mint, Activity::Delisted { seller: signer }))
+            .collect()
+    } else if discriminator == instruction::BatchDelist::DISCRIMINATOR {
+        batch_mints(&ix.accounts, BATCH_DELIST_FIXED_ACCOUNTS)
+            .map(|mint| (mint, Activity::Delisted { seller: signer }))
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+/// Mints of a batch instruction, each the first account of its group.
+fn batch_mints(accounts: &[Pubkey], fixed: usize) -> impl Iterator<Item = Pubkey> + '_ {
+    accounts
+        .get(fixed..)
+        .unwrap_or_default()
+        .chunks_exact(BATCH_GROUP_LEN)
+        .map(|group| group[0])
+}
+
+/// `NftSold` events emitted by top-level instructions of the program. Data
+/// logs only count while the program is the innermost invoked one, so a
+/// program it calls or one called beside it can't log a sale, and the buyer
+/// and mint must be accounts of the instruction that emitted the event.
+fn sold_events(tx: &ProgramTransaction) -> Vec<NftSold> {
+    let mut sales = Vec::new();
+    // Programs invoked at the current log, outermost first
+    let mut frames: Vec<Pubkey> = Vec::new();
+    // `tx.instructions` holds the top-level invocations of the program in order
+    let mut instructions = tx.instructions.iter();
+    let mut current: Option<&ProgramInstruction> = None;
+
+    for log in &tx.logs {
+        if let Some(data) = log.strip_prefix(PROGRAM_DATA_LOG) {
+            let Some(ix) = current.filter(|_| frames.last() == Some(&PROGRAM_ID)) else {
+                continue;
+            };
+            let Some(sale) = decode_sold_event(data) else {
+                continue;
+            };
+            if ix.accounts.contains(&sale.buyer) && ix.accounts.contains(&sale.mint) {
+                sales.push(sale);
+            }
+            continue;
+        }
+
+        // `Program <id> invoke [n]`, `Program <id> success` and
+        // `Program <id> failed: ...`. Program logs start with `Program log:`,
+        // which never parses as an ID.
+        let Some((program, status)) = log
+            .strip_prefix(PROGRAM_LOG_PREFIX)
+            .and_then(|log| log.split_once(' '))
+        else {
+            continue;
+        };
+        let Ok(program) = program.parse::<Pubkey>() else {
+            continue;
+        };
+        if status.starts_with("invoke [") {
+            if frames.is_empty() {
+                current = if program == PROGRAM_ID {
+                    instructions.next()
+                } else {
+                    None
+                };
+            }
+            frames.push(program);
+        } else if status == "success" || status.starts_with("failed") {
+            frames.pop();
+        }
+    }
+
+    sales
+}
+
+fn decode_sold_event(data: &str) -> Option<NftSold> {
+    let data = STANDARD.decode(data).ok()?;
+    let event = data.strip_prefix(NftSold::DISCRIMINATOR)?;
+    NftSold::try_from_slice(event).ok()
+}
+
+/// Processes every program transaction after `after` and returns the current
+/// state of each mint they touched. Failed transactions are skipped.
+pub async fn sync<C: ChainSource>(
+    chain: &C,
+    after: Option<Signature>,
+) -> Result<SyncBatch, String> {
+    let signatures = chain.signatures_after(after).await?;
+
+    let mut activities = Vec::new();
+    // Mint to its latest buyer in this batch, if it was sold
+    let mut touched: BTreeMap<Pubkey, Option<Pubkey>> = BTreeMap::new();
+    for status in signatures.iter().filter(|status| !status.failed) {
+        let tx = chain.transaction(&status.signature).await?;
+        for (mint, activity) in decode_activity(&tx) {
+            println!("Indexed {:?} of {} in {}", activity, mint, tx.signature);
+            match activity {
+                // Minting doesn't touch the listing
+                Activity::Minted { .. } => {}
+                Activity::Sold { buyer, .. } => {
+                    touched.insert(mint, Some(buyer));
+                }
+                _ => {
+                    touched.entry(mint).or_default();
+                }
+            }
+            activities.push(IndexedActivity {
+                signature: tx.signature,
+                block_time: tx.block_time,
+                mint,
+                activity,
+            });
+        }
+    }
+
+    let mut updates = Vec::with_capacity(touched.len());
+    for (mint, buyer) in touched {
+        let update = match chain
+            .listing(&mint)
+            .await?
+            .filter(|listing| listing.is_active)
+        {
+            Some(listing) => ListingUpdate {
+                mint,
+                owner: Some(listing.seller),
+                price: Some(listing.price),
+            },
+            None => ListingUpdate {
+                mint,
+                owner: buyer,
+                price: None,
+            },
+        };
+        updates.push(update);
+    }
+
+    Ok(SyncBatch {
+        activities,
+        updates,
+        last_signature: signatures.last().map(|status| status.signature).or(after),
+    })
+}
+
+pub struct RpcChain {
+    rpc: RpcClient,
+}
+
+impl RpcChain {
+    pub fn new(rpc: RpcClient) -> Self {
+        Self { rpc }
+    }
+}
+
+impl ChainSource for RpcChain {
+    async fn signatures_after(
+        &self,
+        until: Option<Signature>,
+    ) -> Result<Vec<SignatureStatus>, String> {
+        let mut statuses = Vec::new();
+        let mut before = None;
+
+        // Pages run newest to oldest. Without a cursor only the latest page is
+        // indexed rather than the program's whole history.
+        loop {
+            let config = GetConfirmedSignaturesForAddress2Config {
+                before,
+                until,
+                limit: None,
+                commitment: Some(CommitmentConfig::confirmed()),
+            };
+            let page = self
+                .rpc
+                .get_signatures_for_address_with_config(&PROGRAM_ID, config)
+                .await
+                .map_err(|e| format!("Failed to fetch signatures: {}", e))?;
+            let full_page = page.len() == SIGNATURE_PAGE_LIMIT;
+
+            for status in page {
+                statuses.push(SignatureStatus {
+                    signature: parse_signature(&status.signature)?,
+                    failed: status.err.is_some(),
+                });
+            }
+            before = statuses.last().map(|status| status.signature);
+
+            if until.is_none() || !full_page {
+                break;
+            }
+        }
+
+        statuses.reverse();
+        Ok(statuses)
+    }
+
+    async fn transaction(&self, signature: &Signature) -> Result<ProgramTransaction, String> {
+        let config = RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Base64),
+            commitment: Some(CommitmentConfig::confirmed()),
+            max_supported_transaction_version: Some(0),
+        };
+        let confirmed = self
+            .rpc
+            .get_transaction_with_config(signature, config)
+            .await
+            .map_err(|e| format!("Failed to fetch transaction {}: {}", signature, e))?;
+        let tx = confirmed
+            .transaction
+            .transaction
+            .decode()
+            .ok_or_else(|| format!("Failed to decode transaction {}", signature))?;
+
+        let (logs, loaded_addresses) = match confirmed.transaction.meta {
+            Some(meta) => (
+                Option::<Vec<String>>::from(meta.log_messages).unwrap_or_default(),
+                Option::<UiLoadedAddresses>::from(meta.loaded_addresses),
+            ),
+            None => (Vec::new(), None),
+        };
+
+        // Account indexes run over the static keys, then the addresses loaded
+        // from lookup tables
+        let mut keys = tx.message.static_account_keys().to_vec();
+        if let Some(loaded) = loaded_addresses {
+            for key in loaded.writable.iter().chain(&loaded.readonly) {
+                keys.push(
+                    key.parse()
+                        .map_err(|e| format!("Invalid loaded address {}: {}", key, e))?,
+                );
+            }
+        }
+
+        let instructions = tx
+            .message
+            .instructions()
+            .iter()
+            .filter(|ix| keys.get(ix.program_id_index as usize) == Some(&PROGRAM_ID))
+            .map(|ix| ProgramInstruction {
+                accounts: ix
+                    .accounts
+                    .iter()
+                    .filter_map(|index| keys.get(*index as usize).copied())
+                    .collect(),
+                data: ix.data.clone(),
+            })
+            .collect();
+
+        Ok(ProgramTransaction {
+            signature: *signature,
+            instructions,
+            logs,
+            block_time: confirmed.block_time,
+        })
+    }
+
+    async fn listing(&self, mint: &Pubkey) -> Result<Option<Listing>, String> {
+        solana::fetch_listing(&self.rpc, mint).await
+    }
+
+    async fn token_metadata(&self, mint: &Pubkey) -> Result<Option<TokenMetadata>, String> {
+        solana::fetch_token_metadata(&self.rpc, mint).await
+    }
+}
+
+fn parse_signature(signature: &str) -> Result<Signature, String> {
+    signature
+        .parse()
+        .map_err(|e| format!("Invalid signature {}: {}", signature, e))
+}
+
+/// Starts the indexer once Rocket has connected to MongoDB.
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Chain indexer", |rocket| {
+        Box::pin(async move {
+            let Some(db) = MainDatabase::fetch(rocket) else {
+                println!("Chain indexer not started: database is not attached");
+                return;
+            };
+            let client = Client::clone(db);
+
+            let poll_secs = env::var("INDEXER_POLL_SECS")
+                .ok()
+                .and_then(|secs| secs.parse().ok())
+                .unwrap_or(DEFAULT_POLL_SECS);
+
+            println!("Starting chain indexer against {}", solana::rpc_url());
+            rocket::tokio::spawn(run(
+                RpcChain::new(solana::rpc_client()),
+                client,
+                metadata::http_client(),
+                Duration::from_secs(poll_secs),
+            ));
+        })
+    })
+}
+
+async fn run<C: ChainSource>(
+    chain: C,
+    client: Client,
+    http: reqwest::Client,
+    poll_interval: Duration,
+) {
+    let database = client.database("nft_marketplace");
+    let state = database.collection::<Document>("IndexerState");
+
+    let mut cursor = load_cursor(&state).await;
+
+    loop {
+        match sync(&chain, cursor).await {
+            Ok(batch) => {
+                // Retry the whole batch next time if any write failed
+                let applied = match apply_batch(&chain, &http, &database, &batch).await {
+                    Ok(()) => true,
+                    Err(e) => {
+                        println!("{}", e);
+                        false
+                    }
+                };
+
+                if applied && batch.last_signature != cursor {
+                    cursor = batch.last_signature;
+                    if let Err(e) = save_cursor(&state, cursor).await {
+                        println!("Error saving indexer cursor: {}", e);
+                    }
+                }
+            }
+            Err(e) => println!("Indexer sync failed: {}", e),
+        }
+
+        rocket::tokio::time::sleep(poll_interval).await;
+    }
+}
+
+async fn apply_batch<C: ChainSource>(
+    chain: &C,
+    http: &reqwest::Client,
+    database: &MongoDatabase,
+    batch: &SyncBatch,
+) -> Result<(), String> {
+    let activities = database.collection::<NftActivity>("Activities");
+    for activity in &batch.activities {
+        record_activity(&activities, &activity.record())
+            .await
+            .map_err(|e| format!("Error recording activity of {}: {}", activity.mint, e))?;
+    }
+    for update in &batch.updates {
+        apply_update(chain, http, database, update)
+            .await
+            .map_err(|e| format!("Error applying update for {}: {}", update.mint, e))?;
+    }
+
+    Ok(())
+}
+
+async fn apply_update<C: ChainSource>(
+    chain: &C,
+    http: &reqwest::Client,
+    database: &MongoDatabase,
+    update: &ListingUpdate,
+) -> Result<(), String> {
+    let nfts = database.collection::<MintedNFTs>("MintedNFTs");
+    let mint_address = update.mint.to_string();
+
+    let mut set = doc! { "is_listed": update.price.is_some() };
+    if let Some(owner) = update.owner {
+        set.insert("owner", owner.to_string());
+    }
+    if let Some(price) = update.price {
+        set.insert("price", price as i64);
+        // Relisting moves the NFT up the recent sort, repricing doesn't
+        set.insert(
+            "listed_at",
+            doc! { "$cond": [{ "$eq": ["$is_listed", true] }, "$listed_at", now()] },
+        );
+    }
+
+    // A pipeline, so `listed_at` can depend on the stored `is_listed`
+    let result = nfts
+        .update_one(
+            doc! { "mint_address": &mint_address },
+            vec![doc! { "$set": set }],
+            None,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Listings made outside the frontend have no document yet, build one from
+    // the token metadata
+    let (Some(owner), Some(price)) = (update.owner, update.price) else {
+        return Ok(());
+    };
+    if result.matched_count > 0 {
+        return Ok(());
+    }
+
+    let token_metadata = chain
+        .token_metadata(&update.mint)
+        .await?
+        .unwrap_or_default();
+    let offchain = if token_metadata.uri.is_empty() {
+        None
+    } else {
+        let fetched =
+            metadata::fetch_metadata(http, &token_metadata.uri, "", Some(&token_metadata)).await;
+        match fetched {
+            Ok(offchain) => Some(offchain),
+            Err(e) => {
+                println!(
+                    "Error fetching off-chain metadata of {}: {}",
+                    mint_address, e
+                );
+                None
+            }
+        }
+    };
+    let nft = MintedNFTs {
+        mint_address: mint_address.clone(),
+        name: token_metadata.name,
+        symbol: token_metadata.symbol,
+        price,
+        payment_mint: NATIVE_MINT.to_string(),
+        image_uri: offchain
+            .as_ref()
+            .and_then(|offchain| offchain.image.clone())
+            .unwrap_or_default(),
+        metadata_uri: token_metadata.uri,
+        owner: owner.to_string(),
+        is_listed: true,
+        listed_at: now(),
+        collection: token_metadata
+            .collection
+            .map(|collection| collection.to_string()),
+        metadata: offchain,
+        rarity: None,
+    };
+    println!("Indexer inserting NFT: {:?}", nft);
+
+    // Upsert so a concurrent /list_nft for the same mint isn't duplicated
+    nfts.update_one(
+        doc! { "mint_address": &mint_address },
+        doc! { "$setOnInsert": mongodb::bson::to_document(&nft).map_err(|e| e.to_string())? },
+        UpdateOptions::builder().upsert(true).build(),
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    rarity::refresh_collection_of(database, &nft);
+
+    Ok(())
+}
+
+async fn record_activity(
+    activities: &Collection<NftActivity>,
+    activity: &NftActivity,
+) -> Result<(), String> {
+    let kind = mongodb::bson::to_bson(&activity.kind).map_err(|e| e.to_string())?;
+
+    // Upsert so retrying a batch doesn't record its activity twice
+    activities
+        .update_one(
+            doc! {
+                "signature": &activity.signature,
+                "mint_address": &activity.mint_address,
+                "kind": kind,
+            },
+            doc! { "$setOnInsert": mongodb::bson::to_document(activity).map_err(|e| e.to_string())? },
+            UpdateOptions::builder().upsert(true).build(),
+        )
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+async fn load_cursor(state: &Collection<Document>) -> Option<Signature> {
+    match state.find_one(doc! { "_id": CURSOR_ID }, None).await {
+        Ok(cursor) => cursor
+            .as_ref()
+            .and_then(|cursor| cursor.get_str("last_signature").ok())
+            .and_then(|signature| parse_signature(signature).ok()),
+        Err(e) => {
+            println!("Error loading indexer cursor: {}", e);
+            None
+        }
+    }
+}
+
+async fn save_cursor(
+    state: &Collection<Document>,
+    cursor: Option<Signature>,
+) -> Result<(), String> {
+    let Some(signature) = cursor else {
+        return Ok(());
+    };
+
+    state
+        .update_one(
+            doc! { "_id": CURSOR_ID },
+            doc! { "$set": { "last_signature": signature.to_string() } },
+            UpdateOptions::builder().upsert(true).build(),
+        )
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anchor_lang::{solana_program::instruction::Instruction, Event};
+    use nft_marketplace::{LISTING_RESERVED_BYTES, LISTING_VERSION};
+    use nft_marketplace_client::instructions::{
+        BatchDelistNft, BatchListNft, BatchListing, BuyNft, DelistNft, ListNft, MintNft, Payout,
+        UpdatePrice,
+    };
+
+    use super::*;
+
+    /// In-memory stand-in for a validator: a signature history plus the
+    /// listing accounts as they are after the last transaction.
+    #[derive(Default)]
+    struct LocalChain {
+        history: Vec<(SignatureStatus, ProgramTransaction)>,
+        listings: HashMap<Pubkey, Listing>,
+    }
+
+    impl LocalChain {
+        fn push(
+            &mut self,
+            instructions: &[Instruction],
+            logs: Vec<String>,
+            failed: bool,
+        ) -> Signature {
+            let signature = Signature::new_unique();
+            let tx = ProgramTransaction {
+                signature,
+                instructions: instructions
+                    .iter()
+                    .map(|ix| ProgramInstruction {
+                        accounts: ix.accounts.iter().map(|meta| meta.pubkey).collect(),
+                        data: ix.data.clone(),
+                    })
+                    .collect(),
+                logs,
+                block_time: Some(self.history.len() as i64),
+            };
+            self.history
+                .push((SignatureStatus { signature, failed }, tx));
+            signature
+        }
+
+        fn set_listing(&mut self, mint: Pubkey, seller: Pubkey, price: u64, is_active: bool) {
+            let listing = Listing {
+                seller,
+                mint,
+                price,
+                is_active,
+                bump: 255,
+                version: LISTING_VERSION,
+                reserved: [0; LISTING_RESERVED_BYTES],
+            };
+            self.listings.insert(mint, listing);
+        }
+    }
+
+    impl ChainSource for LocalChain {
+        async fn signatures_after(
+            &self,
+            until: Option<Signature>,
+        ) -> Result<Vec<SignatureStatus>, String> {
+            let start = match until {
+                Some(until) => {
+                    self.history
+                        .iter()
+                        .position(|(status, _)| status.signature == until)
+                        .ok_or("unknown signature")?
+                        + 1
+                }
+                None => 0,
+            };
+            Ok(self.history[start..]
+                .iter()
+                .map(|(status, _)| *status)
+                .collect())
+        }
+
+        async fn transaction(&self, signature: &Signature) -> Result<ProgramTransaction, String> {
+            self.history
+                .iter()
+                .find(|(status, _)| status.signature == *signature)
+                .map(|(_, tx)| tx.clone())
+                .ok_or_else(|| "unknown signature".to_string())
+        }
+
+        async fn listing(&self, mint: &Pubkey) -> Result<Option<Listing>, String> {
+            Ok(self.listings.get(mint).cloned())
+        }
+
+        async fn token_metadata(&self, _mint: &Pubkey) -> Result<Option<TokenMetadata>, String> {
+            Ok(None)
+        }
+    }
+
+    fn list(seller: Pubkey, mint: Pubkey, price: u64) -> Instruction {
+        ListNft {
+            seller,
+            mint,
+            price,
+            collection: None,
+        }
+        .instruction()
+    }
+
+    fn buy(seller: Pubkey, buyer: Pubkey, mint: Pubkey) -> Instruction {
+        BuyNft {
+            buyer,
+            seller,
+            mint,
+            treasury: Pubkey::new_unique(),
+            payout: Payout::Wallet,
+            referrer: None,
+            collection: None,
+        }
+        .instruction()
+    }
+
+    fn sold_log(mint: Pubkey, seller: Pubkey, buyer: Pubkey, price: u64) -> String {
+        let event = NftSold {
+            mint,
+            seller,
+            buyer,
+            price,
+            marketplace_fee: 0,
+            referrer: None,
+            referral_fee: 0,
+        };
+        format!("{}{}", PROGRAM_DATA_LOG, STANDARD.encode(event.data()))
+    }
+
+    fn invoke_log(program: Pubkey, depth: u8) -> String {
+        format!("Program {} invoke [{}]", program, depth)
+    }
+
+    fn success_log(program: Pubkey) -> String {
+        format!("Program {} success", program)
+    }
+
+    /// Logs of a top-level `buy_nft` that emitted the sale.
+    fn sale_logs(mint: Pubkey, seller: Pubkey, buyer: Pubkey, price: u64) -> Vec<String> {
+        vec![
+            invoke_log(PROGRAM_ID, 1),
+            invoke_log(anchor_spl::token::ID, 2),
+            success_log(anchor_spl::token::ID),
+            sold_log(mint, seller, buyer, price),
+            success_log(PROGRAM_ID),
+        ]
+    }
+
+    #[test]
+    fn decodes_single_and_batch_instructions() {
+        let seller = Pubkey::new_unique();
+        let mints = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut chain = LocalChain::default();
+        chain.push(&[list(seller, mints[0], 5)], vec![], false);
+        chain.push(
+            &[UpdatePrice {
+                seller,
+                mint: mints[0],
+                new_price: 7,
+                collection: None,
+            }
+            .instruction()],
+            vec![],
+            false,
+        );
+        chain.push(
+            &[BatchListNft {
+                seller,
+                listings: mints
+                    .iter()
+                    .zip([5, 6])
+                    .map(|(mint, price)| BatchListing {
+                        mint: *mint,
+                        price,
+                        collection: None,
+                    })
+                    .collect(),
+            }
+            .instruction()],
+            vec![],
+            false,
+        );
+        chain.push(
+            &[DelistNft {
+                seller,
+                mint: mints[0],
+                collection: None,
+            }
+            .instruction()],
+            vec![],
+            false,
+        );
+        chain.push(
+            &[BatchDelistNft {
+                seller,
+                listings: vec![(mints[1], None)],
+            }
+            .instruction()],
+            vec![],
+            false,
+        );
+
+        let decoded: Vec<_> = chain
+            .history
+            .iter()
+            .map(|(_, tx)| decode_activity(tx))
+            .collect();
+
+        assert_eq!(
+            decoded,
+            vec![
+                vec![(mints[0], Activity::Listed { seller, price: 5 })],
+                vec![(mints[0], Activity::PriceUpdated { seller, price: 7 })],
+                vec![
+                    (mints[0], Activity::Listed { seller, price: 5 }),
+                    (mints[1], Activity::Listed { seller, price: 6 })
+                ],
+                vec![(mints[0], Activity::Delisted { seller })],
+                vec![(mints[1], Activity::Delisted { seller })],
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_sales_from_events() {
+        let seller = Pubkey::new_unique();
+        let buyer = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let mut chain = LocalChain::default();
+        chain.push(
+            &[buy(seller, buyer, mint)],
+            sale_logs(mint, seller, buyer, 9),
+            false,
+        );
+
+        assert_eq!(
+            decode_activity(&chain.history[0].1),
+            vec![(
+                mint,
+                Activity::Sold {
+                    seller,
+                    buyer,
+                    price: 9
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn ignores_spoofed_sale_events() {
+        let seller = Pubkey::new_unique();
+        let buyer = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let spoofer = Pubkey::new_unique();
+        let mut chain = LocalChain::default();
+
+        // Logged by a program the marketplace called
+        chain.push(
+            &[buy(seller, buyer, mint)],
+            vec![
+                invoke_log(PROGRAM_ID, 1),
+                invoke_log(spoofer, 2),
+                sold_log(mint, seller, buyer, 1),
+                success_log(spoofer),
+                success_log(PROGRAM_ID),
+            ],
+            false,
+        );
+        // Logged by another top-level program in the same transaction
+        chain.push(
+            &[buy(seller, buyer, mint)],
+            vec![
+                invoke_log(spoofer, 1),
+                sold_log(other, seller, buyer, 1),
+                success_log(spoofer),
+                invoke_log(PROGRAM_ID, 1),
+                success_log(PROGRAM_ID),
+            ],
+            false,
+        );
+        // A forged transition hidden in a program log
+        chain.push(
+            &[buy(seller, buyer, mint)],
+            vec![
+                invoke_log(PROGRAM_ID, 1),
+                invoke_log(spoofer, 2),
+                format!("Program log: Program {} success", spoofer),
+                sold_log(mint, seller, buyer, 1),
+                success_log(spoofer),
+                success_log(PROGRAM_ID),
+            ],
+            false,
+        );
+        // Emitted by the program for a mint the instruction didn't touch
+        chain.push(
+            &[buy(seller, buyer, mint)],
+            sale_logs(other, seller, buyer, 1),
+            false,
+        );
+
+        for (_, tx) in &chain.history {
+            assert_eq!(decode_activity(tx), vec![]);
+        }
+    }
+
+    #[test]
+    fn records_mints_and_sales() {
+        let minter = Pubkey::new_unique();
+        let buyer = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let mut chain = LocalChain::default();
+        chain.push(
+            &[MintNft {
+                signer: minter,
+                mint,
+                name: "Test".to_string(),
+                symbol: "TNFT".to_string(),
+                uri: "https://example.com/nft.json".to_string(),
+            }
+            .instruction()],
+            vec![],
+            false,
+        );
+        let sale = chain.push(
+            &[buy(minter, buyer, mint)],
+            sale_logs(mint, minter, buyer, 9),
+            false,
+        );
+
+        assert_eq!(
+            decode_activity(&chain.history[0].1),
+            vec![(mint, Activity::Minted { minter })]
+        );
+        assert_eq!(
+            decode_activity(&chain.history[1].1),
+            vec![(
+                mint,
+                Activity::Sold {
+                    seller: minter,
+                    buyer,
+                    price: 9
+                }
+            )]
+        );
+
+        let record = IndexedActivity {
+            signature: sale,
+            block_time: Some(1_700_000_000),
+            mint,
+            activity: Activity::Sold {
+                seller: minter,
+                buyer,
+                price: 9,
+            },
+        }
+        .record();
+        assert_eq!(record.kind, ActivityKind::Sale);
+        assert_eq!(record.signature, sale.to_string());
+        assert_eq!(record.price, Some(9));
+        assert_eq!(record.from, Some(minter.to_string()));
+        assert_eq!(record.to, Some(buyer.to_string()));
+        assert_eq!(record.timestamp, 1_700_000_000);
+    }
+
+    #[rocket::async_test]
+    async fn syncs_listing_state_since_cursor() {
+        let seller = Pubkey::new_unique();
+        let buyer = Pubkey::new_unique();
+        let sold = Pubkey::new_unique();
+        let listed = Pubkey::new_unique();
+        let mut chain = LocalChain::default();
+
+        chain.push(&[list(seller, sold, 5)], vec![], false);
+        let first = sync(&chain, None).await.unwrap();
+        assert_eq!(first.updates.len(), 1);
+
+        chain.push(
+            &[buy(seller, buyer, sold)],
+            sale_logs(sold, seller, buyer, 5),
+            false,
+        );
+        chain.set_listing(sold, seller, 5, false);
+        chain.push(&[list(seller, listed, 8)], vec![], false);
+        chain.set_listing(listed, seller, 8, true);
+        // A failed listing attempt leaves no trace
+        let last = chain.push(&[list(seller, Pubkey::new_unique(), 1)], vec![], true);
+
+        let batch = sync(&chain, first.last_signature).await.unwrap();
+
+        let kinds: Vec<_> = batch
+            .activities
+            .iter()
+            .map(|activity| (activity.mint, activity.record().kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![(sold, ActivityKind::Sale), (listed, ActivityKind::List)]
+        );
+
+        let mut expected = vec![
+            ListingUpdate {
+                mint: sold,
+                owner: Some(buyer),
+                price: None,
+            },
+            ListingUpdate {
+                mint: listed,
+                owner: Some(seller),
+                price: Some(8),
+            },
+        ];
+        expected.sort_by_key(|update| update.mint);
+        assert_eq!(batch.updates, expected);
+        assert_eq!(batch.last_signature, Some(last));
+
+        let idle = sync(&chain, batch.last_signature).await.unwrap();
+        assert!(idle.updates.is_empty());
+        assert!(idle.activities.is_empty());
+        assert_eq!(idle.last_signature, Some(last));
+    }
+}
@@ -1,20 +1,56 @@
+mod activities;
+mod auth;
 mod db;
+mod indexer;
+mod metadata;
+mod migrate;
 mod models;
+mod portfolio;
+mod query;
+mod rarity;
 mod routes;
+mod solana;
+mod storage;
+mod upload;
 use rocket_cors::{AllowedOrigins, CorsOptions};
 
-use rocket::{ launch, routes };
+use rocket::{ catchers, routes, Build, Rocket };
 use rocket_db_pools::Database;
 
-#[launch]
-fn rocket() -> _ {
+#[rocket::main]
+async fn main() {
+    dotenv::dotenv().ok();
+
+    // `cargo run -- <migration>` migrates the database and exits
+    let migration = std::env::args().nth(1);
+    if let Some(migration) = migration {
+        if let Err(e) = migrate::run(&migration).await {
+            println!("Migration failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Err(e) = rocket().launch().await {
+        println!("Server failed: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn rocket() -> Rocket<Build> {
+
     let cors = CorsOptions::default()
         .to_cors()
         .expect("CORS failed");
 
     rocket::build()
         .attach(db::MainDatabase::init())
+        .attach(db::indexes())
         .attach(cors) // ✅ Attach the CORS fairing here
+        .attach(indexer::fairing())
+        .attach(storage::fairing())
+        .manage(solana::rpc_client())
+        .manage(metadata::http_client())
         .mount(
             "/",
             routes![
@@ -22,6 +58,16 @@ fn rocket() -> _ {
                 routes::list_nft,
                 routes::update_nft_listing_status,
                 routes::delete_nft,
+                routes::refresh_metadata,
+                activities::nft_activities,
+                activities::wallet_activities,
+                activities::recent_sales,
+                portfolio::wallet_nfts,
+                rarity::collection_traits,
+                upload::upload,
+                auth::get_nonce,
+                auth::verify,
             ]
         )
+        .register("/", catchers![auth::unauthorized])
 }
\ No newline at end of file
@@ -0,0 +1,539 @@
+//! Off-chain JSON metadata that a mint's on-chain `uri` points at, and its
+//! validation against the Metaplex token metadata standard.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect, Url,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::auth::now;
+use crate::solana::TokenMetadata;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const IPFS_GATEWAY: &str = "https://ipfs.io/ipfs/";
+/// Largest off-chain JSON read, anything bigger is rejected
+const MAX_METADATA_BYTES: usize = 1024 * 1024;
+const MAX_REDIRECTS: usize = 5;
+
+/// Entry of `attributes`. Values are strings or numbers.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Attribute {
+    pub trait_type: String,
+    pub value: serde_json::Value,
+}
+
+impl Attribute {
+    /// `value` as text, numbers in their JSON form.
+    pub fn value_string(&self) -> String {
+        match &self.value {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MetadataFile {
+    pub uri: String,
+    #[serde(rename = "type")]
+    pub content_type: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Properties {
+    pub files: Vec<MetadataFile>,
+    pub category: String,
+}
+
+/// Metaplex token metadata standard JSON, as `/upload` pins it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MetadataJson {
+    pub name: String,
+    pub symbol: String,
+    pub description: String,
+    pub image: String,
+    pub attributes: Vec<Attribute>,
+    pub properties: Properties,
+    pub seller_fee_basis_points: u16,
+}
+
+/// The standard fields of an off-chain JSON, whichever of them are valid.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct OffchainMetadata {
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub external_url: Option<String>,
+    pub attributes: Vec<Attribute>,
+    pub properties: Option<Value>,
+}
+
+/// Off-chain JSON of a listed NFT as last fetched, stored on its
+/// `MintedNFTs` document.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FetchedMetadata {
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub external_url: Option<String>,
+    pub attributes: Vec<Attribute>,
+    pub properties: Option<Value>,
+    /// Ways the JSON deviates from the standard or the on-chain metadata
+    pub problems: Vec<String>,
+    /// The client-provided `image_uri` isn't the JSON's `image`
+    pub image_mismatch: bool,
+    /// Unix timestamp in seconds
+    pub fetched_at: i64,
+}
+
+/// Client for the URIs that mints point at. Those are chosen by whoever
+/// minted, so only public hosts are reached, redirects included.
+pub fn http_client() -> reqwest::Client {
+    let redirects = redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error("too many redirects");
+        }
+        match check_url(attempt.url()) {
+            Ok(()) => attempt.follow(),
+            Err(e) => attempt.error(e),
+        }
+    });
+
+    reqwest::Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .redirect(redirects)
+        .dns_resolver(Arc::new(PublicResolver))
+        .build()
+        .expect("HTTP client failed")
+}
+
+/// System resolver that refuses hosts with any non-public address, so a
+/// domain can't point the fetcher at the server's own network.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<SocketAddr> =
+                rocket::tokio::net::lookup_host((host, 0)).await?.collect();
+            if addrs.is_empty() || addrs.iter().any(|addr| !is_public(addr.ip())) {
+                return Err(format!("{} does not resolve to a public address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether `ip` is reachable on the public internet.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // Shared address space, 100.64.0.0/10
+        || (a == 100 && (b & 0xc0) == 64)
+        // IETF protocol assignments, 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking, 198.18.0.0/15
+        || (a == 198 && (b & 0xfe) == 18)
+        // Reserved, 240.0.0.0/4
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // Link-local, fe80::/10
+        || (first & 0xffc0) == 0xfe80
+        // Documentation, 2001:db8::/32
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+}
+
+/// Only `https` URLs, which `ipfs://` URIs become through the gateway, to a
+/// domain or a public address. Domains are checked when they are resolved.
+fn check_url(url: &Url) -> Result<(), String> {
+    if url.scheme() != "https" {
+        return Err(format!("{} is not an https or ipfs URI", url));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| format!("{} has no host", url))?;
+    match host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    {
+        Ok(ip) if !is_public(ip) => Err(format!("{} is not a public address", host)),
+        _ => Ok(()),
+    }
+}
+
+/// The URL `uri` is fetched from, if it is one the fetcher may reach.
+fn metadata_url(uri: &str) -> Result<Url, String> {
+    let url = Url::parse(&gateway_url(uri)).map_err(|e| format!("Invalid URI {}: {}", uri, e))?;
+    check_url(&url)?;
+    Ok(url)
+}
+
+/// `uri` as an HTTP URL, `ipfs://` URIs go through a public gateway.
+pub fn gateway_url(uri: &str) -> String {
+    match uri.strip_prefix("ipfs://") {
+        Some(path) => format!("{}{}", IPFS_GATEWAY, path.trim_start_matches("ipfs/")),
+        None => uri.to_string(),
+    }
+}
+
+/// IPFS path of `uri`, whichever gateway it goes through.
+fn ipfs_path(uri: &str) -> Option<&str> {
+    uri.strip_prefix("ipfs://")
+        .map(|path| path.trim_start_matches("ipfs/"))
+        .or_else(|| uri.split_once("/ipfs/").map(|(_, path)| path))
+}
+
+/// Whether `a` and `b` point at the same content.
+pub fn same_content(a: &str, b: &str) -> bool {
+    match (ipfs_path(a), ipfs_path(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => a.trim() == b.trim(),
+    }
+}
+
+/// JSON at `uri`, which must be an `https` or `ipfs` URI on a public host.
+/// Bodies over `MAX_METADATA_BYTES` are rejected.
+pub async fn fetch_metadata_json(http: &reqwest::Client, uri: &str) -> Result<Value, String> {
+    let mut response = http
+        .get(metadata_url(uri)?)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| format!("Failed to fetch metadata from {}: {}", uri, e))?;
+
+    let too_large = || format!("Metadata at {} exceeds {} bytes", uri, MAX_METADATA_BYTES);
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_METADATA_BYTES as u64)
+    {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| format!("Failed to fetch metadata from {}: {}", uri, e))?
+    {
+        if body.len() + chunk.len() > MAX_METADATA_BYTES {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    serde_json::from_slice(&body).map_err(|e| format!("Invalid metadata at {}: {}", uri, e))
+}
+
+pub async fn fetch_offchain_metadata(
+    http: &reqwest::Client,
+    uri: &str,
+) -> Result<OffchainMetadata, String> {
+    Ok(parse_metadata(&fetch_metadata_json(http, uri).await?).0)
+}
+
+/// Fetches the JSON at `uri` and checks it against the standard, the mint's
+/// on-chain metadata and the `image_uri` a client reported for it.
+pub async fn fetch_metadata(
+    http: &reqwest::Client,
+    uri: &str,
+    image_uri: &str,
+    onchain: Option<&TokenMetadata>,
+) -> Result<FetchedMetadata, String> {
+    let json = fetch_metadata_json(http, uri).await?;
+    Ok(check_metadata(&json, image_uri, onchain))
+}
+
+pub fn check_metadata(
+    json: &Value,
+    image_uri: &str,
+    onchain: Option<&TokenMetadata>,
+) -> FetchedMetadata {
+    let (metadata, mut problems) = parse_metadata(json);
+
+    if let Some(onchain) = onchain {
+        if metadata
+            .name
+            .as_deref()
+            .is_some_and(|name| name != onchain.name)
+        {
+            problems.push("name does not match the on-chain name".to_string());
+        }
+        if metadata
+            .symbol
+            .as_deref()
+            .is_some_and(|symbol| symbol != onchain.symbol)
+        {
+            problems.push("symbol does not match the on-chain symbol".to_string());
+        }
+    }
+    let image_mismatch = !image_uri.is_empty()
+        && metadata
+            .image
+            .as_deref()
+            .is_some_and(|image| !same_content(image, image_uri));
+
+    FetchedMetadata {
+        description: metadata.description,
+        image: metadata.image,
+        external_url: metadata.external_url,
+        attributes: metadata.attributes,
+        properties: metadata.properties,
+        problems,
+        image_mismatch,
+        fetched_at: now(),
+    }
+}
+
+/// Reads the standard fields of `json`, noting every deviation from the
+/// standard. Invalid fields and attributes are left out.
+pub fn parse_metadata(json: &Value) -> (OffchainMetadata, Vec<String>) {
+    let mut problems = Vec::new();
+    let Some(object) = json.as_object() else {
+        return (
+            OffchainMetadata::default(),
+            vec!["metadata is not a JSON object".to_string()],
+        );
+    };
+
+    let image = string_field(object, "image", true, &mut problems).filter(|image| {
+        let is_url = ["https://", "http://", "ipfs://", "ar://"]
+            .iter()
+            .any(|scheme| image.starts_with(scheme));
+        if !is_url {
+            problems.push("image is not a URL".to_string());
+        }
+        is_url
+    });
+    let metadata = OffchainMetadata {
+        name: string_field(object, "name", true, &mut problems),
+        symbol: string_field(object, "symbol", false, &mut problems),
+        description: string_field(object, "description", false, &mut problems),
+        image,
+        external_url: string_field(object, "external_url", false, &mut problems),
+        attributes: attributes(object, &mut problems),
+        properties: properties(object, &mut problems),
+    };
+
+    (metadata, problems)
+}
+
+fn string_field(
+    object: &Map<String, Value>,
+    field: &str,
+    required: bool,
+    problems: &mut Vec<String>,
+) -> Option<String> {
+    match object.get(field) {
+        Some(Value::String(value)) => Some(value.clone()),
+        None | Some(Value::Null) => {
+            if required {
+                problems.push(format!("{} is missing", field));
+            }
+            None
+        }
+        Some(_) => {
+            problems.push(format!("{} is not a string", field));
+            None
+        }
+    }
+}
+
+fn attributes(object: &Map<String, Value>, problems: &mut Vec<String>) -> Vec<Attribute> {
+    let items = match object.get("attributes") {
+        Some(Value::Array(items)) => items,
+        None | Some(Value::Null) => return Vec::new(),
+        Some(_) => {
+            problems.push("attributes is not an array".to_string());
+            return Vec::new();
+        }
+    };
+
+    items
+        .iter()
+        .enumerate()
+        .filter_map(
+            |(index, item)| match serde_json::from_value::<Attribute>(item.clone()) {
+                Ok(attribute) if attribute.value.is_string() || attribute.value.is_number() => {
+                    Some(attribute)
+                }
+                _ => {
+                    problems.push(format!(
+                        "attributes[{}] needs a trait_type and a string or number value",
+                        index
+                    ));
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
+fn properties(object: &Map<String, Value>, problems: &mut Vec<String>) -> Option<Value> {
+    let properties = match object.get("properties") {
+        Some(properties @ Value::Object(_)) => properties,
+        None | Some(Value::Null) => return None,
+        Some(_) => {
+            problems.push("properties is not an object".to_string());
+            return None;
+        }
+    };
+
+    match properties.get("files") {
+        Some(Value::Array(files)) => {
+            for (index, file) in files.iter().enumerate() {
+                if serde_json::from_value::<MetadataFile>(file.clone()).is_err() {
+                    problems.push(format!(
+                        "properties.files[{}] needs a uri and a type",
+                        index
+                    ));
+                }
+            }
+        }
+        None | Some(Value::Null) => {}
+        Some(_) => problems.push("properties.files is not an array".to_string()),
+    }
+
+    Some(properties.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE_CID: &str = "bafkreigh2akiscaildc";
+
+    fn standard_json() -> Value {
+        let image = format!("https://ipfs.io/ipfs/{}", IMAGE_CID);
+        serde_json::json!({
+            "name": "Test",
+            "symbol": "TNFT",
+            "description": "A test NFT",
+            "image": image,
+            "attributes": [
+                { "trait_type": "Background", "value": "Blue" },
+                { "trait_type": "Level", "value": 3 }
+            ],
+            "properties": {
+                "files": [{ "uri": image, "type": "image/png" }],
+                "category": "image"
+            }
+        })
+    }
+
+    #[test]
+    fn accepts_standard_metadata() {
+        let onchain = TokenMetadata {
+            name: "Test".to_string(),
+            symbol: "TNFT".to_string(),
+            ..Default::default()
+        };
+        // Same CID through another gateway
+        let image_uri = format!("https://example.mypinata.cloud/ipfs/{}", IMAGE_CID);
+
+        let metadata = check_metadata(&standard_json(), &image_uri, Some(&onchain));
+
+        assert_eq!(metadata.problems, Vec::<String>::new());
+        assert!(!metadata.image_mismatch);
+        assert_eq!(metadata.attributes.len(), 2);
+        assert_eq!(metadata.description.as_deref(), Some("A test NFT"));
+    }
+
+    #[test]
+    fn flags_deviations_and_mismatches() {
+        let mut json = standard_json();
+        json["image"] = "not a url".into();
+        json["attributes"][1] = serde_json::json!({ "value": 3 });
+        json["properties"]["files"] = serde_json::json!([{ "uri": 1 }]);
+        let onchain = TokenMetadata {
+            name: "Other".to_string(),
+            symbol: "TNFT".to_string(),
+            ..Default::default()
+        };
+
+        let metadata = check_metadata(&json, "https://example.com/other.png", Some(&onchain));
+
+        assert_eq!(
+            metadata.problems,
+            vec![
+                "image is not a URL",
+                "attributes[1] needs a trait_type and a string or number value",
+                "properties.files[0] needs a uri and a type",
+                "name does not match the on-chain name",
+            ]
+        );
+        assert_eq!(metadata.attributes.len(), 1);
+        // Without a valid image there is nothing to compare against
+        assert!(!metadata.image_mismatch);
+
+        let other_image = check_metadata(&standard_json(), "https://example.com/other.png", None);
+        assert!(other_image.image_mismatch);
+        assert_eq!(
+            parse_metadata(&Value::Null).1,
+            vec!["metadata is not a JSON object"]
+        );
+    }
+
+    #[test]
+    fn only_fetches_public_https_and_ipfs_uris() {
+        assert_eq!(
+            metadata_url(&format!("ipfs://{}", IMAGE_CID))
+                .unwrap()
+                .as_str(),
+            format!("https://ipfs.io/ipfs/{}", IMAGE_CID)
+        );
+        assert!(metadata_url("https://example.com/nft.json").is_ok());
+        assert!(metadata_url("https://93.184.215.14/nft.json").is_ok());
+
+        for uri in [
+            "http://example.com/nft.json",
+            "file:///etc/passwd",
+            "ar://nft",
+            "https://127.0.0.1/nft.json",
+            "https://2130706433/nft.json",
+            "https://10.0.0.8/nft.json",
+            "https://169.254.169.254/latest/meta-data",
+            "https://100.64.0.1/nft.json",
+            "https://[::1]/nft.json",
+            "https://[::ffff:192.168.1.1]/nft.json",
+            "https://[fd00::1]/nft.json",
+            "https://[fe80::1]/nft.json",
+            "not a uri",
+        ] {
+            assert!(metadata_url(uri).is_err(), "{} was allowed", uri);
+        }
+    }
+}
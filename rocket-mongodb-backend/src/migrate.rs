@@ -0,0 +1,153 @@
+//! One-off data migrations, run with `cargo run -- <migration>` instead of
+//! launching the server. Each one only touches documents it hasn't converted
+//! yet, so running it twice is harmless.
+
+use std::collections::BTreeSet;
+
+use mongodb::bson::{ doc, Document };
+use rocket::futures::TryStreamExt;
+use rocket_db_pools::mongodb::Client;
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+
+use crate::metadata;
+use crate::models::{ MintedNFTs, NATIVE_MINT };
+use crate::rarity;
+
+pub const MIGRATIONS: &[&str] = &["migrate-prices", "backfill-traits", "dedupe-mints", "backfill-listed-at"];
+
+pub async fn run(migration: &str) -> Result<(), String> {
+    // Same database the server is configured with in Rocket.toml
+    let url: String = rocket::Config::figment()
+        .extract_inner("databases.nftDb.url")
+        .map_err(|e| format!("Failed to read the database url: {}", e))?;
+    let client = Client::with_uri_str(&url).await.map_err(|e| e.to_string())?;
+    let nfts = client.database("nft_marketplace").collection::<Document>("MintedNFTs");
+
+    match migration {
+        "migrate-prices" => {
+            // Prices used to be f64 SOL, integers are already lamports
+            let converted = nfts
+                .update_many(
+                    doc! { "price": { "$type": "double" } },
+                    vec![doc! {
+                        "$set": {
+                            "price": {
+                                "$toLong": {
+                                    "$round": [{ "$multiply": ["$price", LAMPORTS_PER_SOL as i64] }, 0]
+                                }
+                            }
+                        }
+                    }],
+                    None
+                ).await
+                .map_err(|e| format!("Failed to convert prices: {}", e))?;
+            let defaulted = nfts
+                .update_many(
+                    doc! { "payment_mint": { "$exists": false } },
+                    doc! { "$set": { "payment_mint": NATIVE_MINT } },
+                    None
+                ).await
+                .map_err(|e| format!("Failed to set payment mints: {}", e))?;
+
+            println!(
+                "Converted {} prices to lamports, set the payment mint of {} NFTs",
+                converted.modified_count,
+                defaulted.modified_count
+            );
+            Ok(())
+        }
+        "backfill-traits" => {
+            let database = client.database("nft_marketplace");
+            let nfts = database.collection::<MintedNFTs>("MintedNFTs");
+            let all: Vec<MintedNFTs> = nfts
+                .find(None, None).await
+                .map_err(|e| format!("Failed to load NFTs: {}", e))?
+                .try_collect().await
+                .map_err(|e| format!("Failed to load NFTs: {}", e))?;
+
+            // NFTs listed before their JSON was stored have no attributes
+            let http = metadata::http_client();
+            let mut fetched = 0;
+            for nft in all.iter().filter(|nft| nft.metadata.is_none() && !nft.metadata_uri.is_empty()) {
+                let offchain = match metadata::fetch_metadata(&http, &nft.metadata_uri, &nft.image_uri, None).await {
+                    Ok(offchain) => offchain,
+                    Err(e) => {
+                        println!("Skipping metadata of {}: {}", nft.mint_address, e);
+                        continue;
+                    }
+                };
+                let offchain = mongodb::bson::to_bson(&offchain).map_err(|e| e.to_string())?;
+                nfts
+                    .update_one(
+                        doc! { "mint_address": &nft.mint_address },
+                        doc! { "$set": { "metadata": offchain } },
+                        None
+                    ).await
+                    .map_err(|e| format!("Failed to store metadata of {}: {}", nft.mint_address, e))?;
+                fetched += 1;
+            }
+
+            let keys: BTreeSet<String> = all.iter().filter_map(rarity::collection_key).collect();
+            for key in &keys {
+                rarity::recompute(&database, key).await.map_err(|e| format!("Failed to rank {}: {}", key, e))?;
+            }
+
+            println!("Fetched metadata of {} NFTs, ranked {} collections", fetched, keys.len());
+            Ok(())
+        }
+        "dedupe-mints" => {
+            // /list_nft used to insert a document per listing, keep the newest
+            let duplicates: Vec<Document> = nfts
+                .aggregate(
+                    vec![
+                        doc! { "$sort": { "_id": -1 } },
+                        doc! { "$group": { "_id": "$mint_address", "ids": { "$push": "$_id" } } },
+                        doc! { "$match": { "ids.1": { "$exists": true } } }
+                    ],
+                    None
+                ).await
+                .map_err(|e| format!("Failed to find duplicates: {}", e))?
+                .try_collect().await
+                .map_err(|e| format!("Failed to find duplicates: {}", e))?;
+
+            let mut removed = 0;
+            for duplicate in &duplicates {
+                let ids = duplicate.get_array("ids").map_err(|e| e.to_string())?;
+                let deleted = nfts
+                    .delete_many(doc! { "_id": { "$in": &ids[1..] } }, None).await
+                    .map_err(|e| format!("Failed to remove duplicates: {}", e))?;
+                removed += deleted.deleted_count;
+            }
+
+            // The plain index on the same key blocks the unique one the
+            // server creates on launch
+            if let Err(e) = nfts.drop_index("mint_address_1", None).await {
+                println!("Not dropping the mint_address index: {}", e);
+            }
+
+            println!("Removed {} duplicates of {} mints", removed, duplicates.len());
+            Ok(())
+        }
+        "backfill-listed-at" => {
+            // The recent sort used to go by `_id`, which is when the document
+            // was created
+            let backfilled = nfts
+                .update_many(
+                    doc! { "listed_at": { "$exists": false } },
+                    vec![doc! {
+                        "$set": {
+                            "listed_at": {
+                                "$toLong": { "$divide": [{ "$toLong": { "$toDate": "$_id" } }, 1000] }
+                            }
+                        }
+                    }],
+                    None
+                ).await
+                .map_err(|e| format!("Failed to backfill listing times: {}", e))?;
+
+            println!("Set the listing time of {} NFTs", backfilled.modified_count);
+            Ok(())
+        }
+        _ => Err(format!("Unknown migration {}, expected one of {:?}", migration, MIGRATIONS)),
+    }
+}
@@ -1,13 +1,170 @@
+use mongodb::bson::DateTime;
 use serde::{ Deserialize, Serialize };
 
+use crate::metadata::FetchedMetadata;
+use crate::rarity::Rarity;
+use crate::solana::lamports_to_sol;
+
+/// Wrapped SOL mint, the payment mint of prices in lamports
+pub const NATIVE_MINT: &str = "So11111111111111111111111111111111111111112";
+
+fn native_mint() -> String {
+    NATIVE_MINT.to_string()
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MintedNFTs {
     pub mint_address: String,
     pub name: String,
     pub symbol: String,
-    pub price: f64,
+    /// In base units of `payment_mint`, lamports for SOL
+    pub price: u64,
+    #[serde(default = "native_mint")]
+    pub payment_mint: String,
     pub image_uri: String,
     pub metadata_uri: String,
     pub owner: String,
     pub is_listed: bool,
+    /// When the NFT was last listed, unix timestamp in seconds
+    #[serde(default)]
+    pub listed_at: i64,
+    /// Verified Metaplex collection of the mint
+    #[serde(default)]
+    pub collection: Option<String>,
+    /// Off-chain JSON at `metadata_uri`, once fetched
+    #[serde(default)]
+    pub metadata: Option<FetchedMetadata>,
+    /// Rank within the collection by trait rarity, once computed
+    #[serde(default)]
+    pub rarity: Option<Rarity>,
+}
+
+/// `MintedNFTs` as the API returns it, with the price also in whole tokens.
+#[derive(Serialize, Debug, Clone)]
+pub struct ListedNFT {
+    #[serde(flatten)]
+    pub nft: MintedNFTs,
+    pub display_price: f64,
+}
+
+impl From<MintedNFTs> for ListedNFT {
+    fn from(nft: MintedNFTs) -> Self {
+        // SOL is the only payment mint the program accepts
+        Self {
+            display_price: lamports_to_sol(nft.price),
+            nft,
+        }
+    }
+}
+
+/// An NFT in a wallet's portfolio, held or listed by the wallet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WalletNft {
+    pub mint_address: String,
+    pub name: String,
+    pub symbol: String,
+    pub metadata_uri: String,
+    pub image_uri: Option<String>,
+    pub description: Option<String>,
+    pub collection: Option<String>,
+    /// Listed on the marketplace, the NFT is then held in escrow
+    pub is_listed: bool,
+    /// Lamports, while listed
+    pub price: Option<u64>,
+    pub display_price: Option<f64>,
+}
+
+impl From<MintedNFTs> for WalletNft {
+    fn from(nft: MintedNFTs) -> Self {
+        Self {
+            display_price: nft.is_listed.then(|| lamports_to_sol(nft.price)),
+            price: nft.is_listed.then_some(nft.price),
+            mint_address: nft.mint_address,
+            name: nft.name,
+            symbol: nft.symbol,
+            metadata_uri: nft.metadata_uri,
+            image_uri: Some(nft.image_uri).filter(|uri| !uri.is_empty()),
+            description: None,
+            collection: nft.collection,
+            is_listed: nft.is_listed,
+        }
+    }
+}
+
+/// Cached on-chain holdings of a wallet.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WalletPortfolio {
+    pub wallet: String,
+    pub nfts: Vec<WalletNft>,
+    /// Unix timestamp in seconds
+    #[serde(default)]
+    pub cached_at: i64,
+    /// Unix timestamp in seconds
+    pub expires_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityKind {
+    Mint,
+    List,
+    PriceChange,
+    Sale,
+    Delist,
+}
+
+/// One entry of an NFT's on-chain history, recorded by the chain indexer.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NftActivity {
+    pub mint_address: String,
+    pub kind: ActivityKind,
+    pub signature: String,
+    /// Lamports, for listings, price changes and sales
+    pub price: Option<u64>,
+    /// Seller of a listing or sale
+    pub from: Option<String>,
+    /// Buyer of a sale or minter of a new NFT
+    pub to: Option<String>,
+    /// Block time, unix timestamp in seconds
+    pub timestamp: i64,
+}
+
+/// `NftActivity` as the API returns it, with the price also in whole tokens.
+#[derive(Serialize, Debug, Clone)]
+pub struct ActivityEntry {
+    #[serde(flatten)]
+    pub activity: NftActivity,
+    pub display_price: Option<f64>,
+}
+
+impl From<NftActivity> for ActivityEntry {
+    fn from(activity: NftActivity) -> Self {
+        Self {
+            display_price: activity.price.map(lamports_to_sol),
+            activity,
+        }
+    }
+}
+
+/// Single-use challenge a wallet signs to start a session.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuthNonce {
+    pub wallet: String,
+    pub nonce: String,
+    /// Unix timestamp in seconds
+    pub expires_at: i64,
+    /// `expires_at` as a date, which the TTL index deletes the nonce by
+    #[serde(default)]
+    pub expiry: Option<DateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Session {
+    pub token: String,
+    pub wallet: String,
+    /// Unix timestamp in seconds
+    pub expires_at: i64,
+    /// `expires_at` as a date, which the TTL index deletes the session by
+    #[serde(default)]
+    pub expiry: Option<DateTime>,
 }
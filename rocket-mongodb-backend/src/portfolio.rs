@@ -0,0 +1,235 @@
+//! `/wallets/<address>/nfts`: what a wallet owns. Held NFTs are read from the
+//! wallet's token accounts and resolved through their Metaplex and off-chain
+//! metadata, which is cached in `WalletPortfolios` for
+//! `PORTFOLIO_CACHE_SECS`. `refresh` skips a cache older than
+//! `MIN_REFRESH_SECS`. Listed NFTs sit in the program's escrow, so they come
+//! from `MintedNFTs` on every request.
+
+use std::env;
+
+use mongodb::bson::doc;
+use rocket::futures::{ stream, StreamExt, TryStreamExt };
+use rocket::get;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+use rocket_db_pools::mongodb::{ options::ReplaceOptions, Collection };
+use rocket_db_pools::Connection;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::auth::now;
+use crate::db::MainDatabase;
+use crate::metadata;
+use crate::models::{ MintedNFTs, WalletNft, WalletPortfolio };
+use crate::routes::error_response;
+use crate::solana::{ self, TokenMetadata };
+
+const DEFAULT_CACHE_SECS: i64 = 60;
+/// A refresh within this long of the last load is served from the cache
+const MIN_REFRESH_SECS: i64 = 10;
+/// Off-chain JSONs fetched at once
+const OFFCHAIN_CONCURRENCY: usize = 8;
+
+#[get("/wallets/<address>/nfts?<refresh>")]
+pub async fn wallet_nfts(
+    address: String,
+    refresh: Option<bool>,
+    rpc: &State<RpcClient>,
+    http: &State<reqwest::Client>,
+    db: Connection<MainDatabase>
+) -> Result<Json<serde_json::Value>, status::Custom<Json<serde_json::Value>>> {
+    let Ok(owner) = address.parse::<Pubkey>() else {
+        return Err(error_response(Status::BadRequest, "Invalid wallet address"));
+    };
+
+    let database = db.database("nft_marketplace");
+    let portfolios = database.collection::<WalletPortfolio>("WalletPortfolios");
+
+    let cached = cached_portfolio(&portfolios, &address).await.filter(|portfolio| {
+        !refresh.unwrap_or(false) || portfolio.cached_at + MIN_REFRESH_SECS > now()
+    });
+    let held = match cached {
+        Some(portfolio) => portfolio.nfts,
+        None => {
+            let held = load_held(rpc, http, &owner).await.map_err(|e| {
+                println!("Error loading portfolio of {}: {}", address, e);
+                error_response(Status::BadGateway, format!("Failed to load wallet NFTs: {}", e))
+            })?;
+            cache_portfolio(&portfolios, &address, &held).await;
+            held
+        }
+    };
+
+    let filter = doc! { "owner": &address, "is_listed": true };
+    let listed = match database.collection::<MintedNFTs>("MintedNFTs").find(filter, None).await {
+        Ok(cursor) => cursor.try_collect::<Vec<MintedNFTs>>().await,
+        Err(e) => Err(e),
+    };
+    let listed = listed.map_err(|e| {
+        println!("Error finding listed NFTs of {}: {}", address, e);
+        error_response(Status::InternalServerError, format!("Failed to find listed NFTs: {}", e))
+    })?;
+
+    Ok(Json(serde_json::json!({
+        "wallet": address,
+        "nfts": merge_listings(held, listed)
+    })))
+}
+
+/// Held NFTs followed by the listed ones. A document claiming a held mint is
+/// listed is stale, the wallet holding it settles that.
+fn merge_listings(held: Vec<WalletNft>, listed: Vec<MintedNFTs>) -> Vec<WalletNft> {
+    let mut nfts = held;
+    for nft in listed {
+        if !nfts.iter().any(|held| held.mint_address == nft.mint_address) {
+            nfts.push(WalletNft::from(nft));
+        }
+    }
+    nfts
+}
+
+/// NFTs in the wallet's token accounts. A mint whose metadata can't be read
+/// is left out rather than failing the whole portfolio.
+async fn load_held(
+    rpc: &RpcClient,
+    http: &reqwest::Client,
+    owner: &Pubkey
+) -> Result<Vec<WalletNft>, String> {
+    let mints = solana::fetch_wallet_mints(rpc, owner).await?;
+
+    let mut onchain = Vec::with_capacity(mints.len());
+    for chunk in mints.chunks(solana::MAX_MULTIPLE_ACCOUNTS) {
+        match solana::fetch_token_metadatas(rpc, chunk).await {
+            // Mints without Metaplex metadata aren't NFTs of interest
+            Ok(metadatas) =>
+                onchain.extend(
+                    chunk
+                        .iter()
+                        .zip(metadatas)
+                        .filter_map(|(mint, metadata)| Some((*mint, metadata?)))
+                ),
+            Err(e) => println!("Skipping {} mints of {}: {}", chunk.len(), owner, e),
+        }
+    }
+
+    let nfts = stream::iter(onchain)
+        .map(|(mint, token_metadata)| load_nft(http, mint, token_metadata))
+        .buffer_unordered(OFFCHAIN_CONCURRENCY)
+        .collect().await;
+    Ok(nfts)
+}
+
+async fn load_nft(http: &reqwest::Client, mint: Pubkey, token_metadata: TokenMetadata) -> WalletNft {
+    // Unreachable off-chain JSON only costs the image and description
+    let offchain = match metadata::fetch_offchain_metadata(http, &token_metadata.uri).await {
+        Ok(offchain) => offchain,
+        Err(e) => {
+            println!("Error fetching off-chain metadata of {}: {}", mint, e);
+            Default::default()
+        }
+    };
+
+    WalletNft {
+        mint_address: mint.to_string(),
+        name: token_metadata.name,
+        symbol: token_metadata.symbol,
+        metadata_uri: token_metadata.uri,
+        image_uri: offchain.image,
+        description: offchain.description,
+        collection: token_metadata.collection.map(|collection| collection.to_string()),
+        is_listed: false,
+        price: None,
+        display_price: None,
+    }
+}
+
+async fn cached_portfolio(
+    portfolios: &Collection<WalletPortfolio>,
+    wallet: &str
+) -> Option<WalletPortfolio> {
+    let filter = doc! { "wallet": wallet, "expires_at": { "$gt": now() } };
+    match portfolios.find_one(filter, None).await {
+        Ok(portfolio) => portfolio,
+        Err(e) => {
+            println!("Error reading cached portfolio of {}: {}", wallet, e);
+            None
+        }
+    }
+}
+
+async fn cache_portfolio(portfolios: &Collection<WalletPortfolio>, wallet: &str, nfts: &[WalletNft]) {
+    let cache_secs = env::var("PORTFOLIO_CACHE_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(DEFAULT_CACHE_SECS);
+    let portfolio = WalletPortfolio {
+        wallet: wallet.to_string(),
+        nfts: nfts.to_vec(),
+        cached_at: now(),
+        expires_at: now() + cache_secs,
+    };
+
+    // A failed write only costs the next request a reload
+    let options = ReplaceOptions::builder().upsert(true).build();
+    if let Err(e) = portfolios.replace_one(doc! { "wallet": wallet }, &portfolio, options).await {
+        println!("Error caching portfolio of {}: {}", wallet, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::NATIVE_MINT;
+
+    use super::*;
+
+    fn held(mint_address: &str) -> WalletNft {
+        WalletNft {
+            mint_address: mint_address.to_string(),
+            name: "Held".to_string(),
+            symbol: "TNFT".to_string(),
+            metadata_uri: String::new(),
+            image_uri: None,
+            description: None,
+            collection: None,
+            is_listed: false,
+            price: None,
+            display_price: None,
+        }
+    }
+
+    fn listed(mint_address: &str, price: u64) -> MintedNFTs {
+        MintedNFTs {
+            mint_address: mint_address.to_string(),
+            name: "Listed".to_string(),
+            symbol: "TNFT".to_string(),
+            price,
+            payment_mint: NATIVE_MINT.to_string(),
+            image_uri: "https://example.com/nft.png".to_string(),
+            metadata_uri: String::new(),
+            owner: String::new(),
+            is_listed: true,
+            listed_at: 0,
+            collection: None,
+            metadata: None,
+            rarity: None,
+        }
+    }
+
+    #[test]
+    fn merges_escrowed_listings_into_holdings() {
+        let nfts = merge_listings(
+            vec![held("held")],
+            vec![listed("listed", 1_500_000_000), listed("held", 1)]
+        );
+
+        assert_eq!(nfts.len(), 2);
+        assert_eq!(nfts[0], held("held"));
+        assert_eq!(nfts[1].mint_address, "listed");
+        assert!(nfts[1].is_listed);
+        assert_eq!(nfts[1].price, Some(1_500_000_000));
+        assert_eq!(nfts[1].display_price, Some(1.5));
+        assert_eq!(nfts[1].image_uri.as_deref(), Some("https://example.com/nft.png"));
+    }
+}
@@ -0,0 +1,407 @@
+//! Query parameters of `/get_listed_nfts` and the activity feeds: keyset
+//! pagination, sorting and filters, translated into MongoDB filter and sort
+//! documents.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use std::collections::BTreeMap;
+
+use mongodb::bson::{bson, doc, oid::ObjectId, Bson, Document};
+use rocket::form::{FromForm, FromFormField};
+
+pub const DEFAULT_PAGE_SIZE: u32 = 50;
+pub const MAX_PAGE_SIZE: u32 = 100;
+
+#[derive(FromFormField, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Most recently listed first
+    #[default]
+    #[field(value = "recent")]
+    Recent,
+    #[field(value = "price_asc")]
+    PriceAsc,
+    #[field(value = "price_desc")]
+    PriceDesc,
+    /// Rarest first, only NFTs with a computed rarity
+    #[field(value = "rarity")]
+    Rarity,
+}
+
+#[derive(FromForm, Debug, Default)]
+pub struct ListingQuery {
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+    pub sort: Option<SortOrder>,
+    /// Price bounds in lamports
+    pub min_price: Option<u64>,
+    pub max_price: Option<u64>,
+    pub symbol: Option<String>,
+    pub owner: Option<String>,
+    pub collection: Option<String>,
+    /// `trait=Type:Value`, repeatable. Values of the same trait type match
+    /// any of them, different trait types must all match.
+    #[field(name = "trait")]
+    pub traits: Vec<String>,
+}
+
+/// Sort key of the last document on a page. Pages continue strictly after it,
+/// with `_id` breaking ties between equal keys.
+#[derive(Debug, PartialEq)]
+pub struct Cursor {
+    /// Listing time, price or rarity rank, depending on the sort order
+    pub key: u64,
+    pub id: ObjectId,
+}
+
+impl Cursor {
+    /// Cursor after `document`, if it has `field` (a dotted path) and `_id`.
+    pub fn after(document: &Document, field: &str) -> Option<Self> {
+        let mut value = document;
+        let mut path = field.split('.').peekable();
+        let mut key = None;
+        while let Some(name) = path.next() {
+            if path.peek().is_some() {
+                value = value.get_document(name).ok()?;
+            } else {
+                key = match value.get(name)? {
+                    Bson::Int64(key) => Some(*key as u64),
+                    Bson::Int32(key) => Some(*key as u64),
+                    _ => None,
+                };
+            }
+        }
+
+        Some(Self {
+            key: key?,
+            id: document.get_object_id("_id").ok()?,
+        })
+    }
+
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", self.key, self.id.to_hex()))
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let (key, id) = decoded.split_once(':')?;
+
+        Some(Self {
+            key: key.parse().ok()?,
+            id: ObjectId::parse_str(id).ok()?,
+        })
+    }
+}
+
+/// `$elemMatch` conditions on `metadata.attributes` for `trait=Type:Value`
+/// parameters, one per trait type.
+fn trait_filters(traits: &[String]) -> Result<Vec<Document>, &'static str> {
+    let mut values: BTreeMap<&str, Vec<Bson>> = BTreeMap::new();
+    for parameter in traits {
+        let (trait_type, value) = parameter
+            .split_once(':')
+            .filter(|(trait_type, _)| !trait_type.is_empty())
+            .ok_or("Invalid trait filter, expected Type:Value")?;
+        let values = values.entry(trait_type).or_default();
+        values.push(value.into());
+        // Numeric attribute values are stored as JSON numbers
+        if let Ok(number) = value.parse::<i64>() {
+            values.push(number.into());
+        } else if let Ok(number) = value.parse::<f64>() {
+            values.push(number.into());
+        }
+    }
+
+    Ok(values
+        .into_iter()
+        .map(|(trait_type, values)| {
+            doc! {
+                "metadata.attributes": {
+                    "$elemMatch": { "trait_type": trait_type, "value": { "$in": values } }
+                }
+            }
+        })
+        .collect())
+}
+
+fn page_size(limit: Option<u32>) -> i64 {
+    limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as i64
+}
+
+impl ListingQuery {
+    pub fn limit(&self) -> i64 {
+        page_size(self.limit)
+    }
+
+    pub fn sort_order(&self) -> SortOrder {
+        self.sort.unwrap_or_default()
+    }
+
+    /// Field the sort order pages by besides `_id`, and its direction.
+    pub fn sort_field(&self) -> (&'static str, i32) {
+        match self.sort_order() {
+            SortOrder::Recent => ("listed_at", -1),
+            SortOrder::PriceAsc => ("price", 1),
+            SortOrder::PriceDesc => ("price", -1),
+            SortOrder::Rarity => ("rarity.rank", 1),
+        }
+    }
+
+    pub fn sort(&self) -> Document {
+        let (field, direction) = self.sort_field();
+        doc! { field: direction, "_id": direction }
+    }
+
+    pub fn filter(&self) -> Result<Document, &'static str> {
+        let mut filter = doc! { "is_listed": true };
+
+        // Prices are stored as i64, larger bounds saturate instead of wrapping
+        let mut price = Document::new();
+        if let Some(min_price) = self.min_price {
+            price.insert("$gte", i64::try_from(min_price).unwrap_or(i64::MAX));
+        }
+        if let Some(max_price) = self.max_price {
+            price.insert("$lte", i64::try_from(max_price).unwrap_or(i64::MAX));
+        }
+        if !price.is_empty() {
+            filter.insert("price", price);
+        }
+
+        for (field, value) in [
+            ("symbol", &self.symbol),
+            ("owner", &self.owner),
+            ("collection", &self.collection),
+        ] {
+            if let Some(value) = value {
+                filter.insert(field, value);
+            }
+        }
+
+        let traits = trait_filters(&self.traits)?;
+        if !traits.is_empty() {
+            filter.insert("$and", traits);
+        }
+
+        if self.sort_order() == SortOrder::Rarity {
+            filter.insert("rarity.rank", doc! { "$exists": true });
+        }
+
+        if let Some(cursor) = &self.cursor {
+            let Cursor { key, id } = Cursor::decode(cursor).ok_or("Invalid cursor")?;
+            let (field, direction) = self.sort_field();
+            let after = if direction > 0 { "$gt" } else { "$lt" };
+            let key = match field {
+                "rarity.rank" => Bson::Int32(key as i32),
+                _ => Bson::Int64(key as i64),
+            };
+            filter.insert(
+                "$or",
+                bson!([
+                    { field: { after: key.clone() } },
+                    { field: key, "_id": { after: id } }
+                ]),
+            );
+        }
+
+        Ok(filter)
+    }
+}
+
+/// Activity feeds run newest first. The cursor is the `_id` of the last
+/// activity on the previous page.
+#[derive(FromForm, Debug, Default)]
+pub struct ActivityQuery {
+    /// `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+impl ActivityQuery {
+    pub fn limit(&self) -> i64 {
+        page_size(self.limit)
+    }
+
+    pub fn sort(&self) -> Document {
+        doc! { "_id": -1 }
+    }
+
+    /// `filter` narrowed to the activities after the cursor.
+    pub fn filter(&self, mut filter: Document) -> Result<Document, &'static str> {
+        if let Some(cursor) = &self.cursor {
+            let id = ObjectId::parse_str(cursor).map_err(|_| "Invalid cursor")?;
+            filter.insert("_id", doc! { "$lt": id });
+        }
+
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_cursor() {
+        let cursor = Cursor {
+            key: 1_250_000_000,
+            id: ObjectId::new(),
+        };
+
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+        assert_eq!(Cursor::decode("not a cursor"), None);
+    }
+
+    #[test]
+    fn builds_filter_from_parameters() {
+        let query = ListingQuery {
+            min_price: Some(1_000_000_000),
+            max_price: Some(2_000_000_000),
+            symbol: Some("TNFT".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            query.filter(),
+            Ok(doc! {
+                "is_listed": true,
+                "price": { "$gte": 1_000_000_000_i64, "$lte": 2_000_000_000_i64 },
+                "symbol": "TNFT"
+            })
+        );
+    }
+
+    #[test]
+    fn saturates_price_bounds_beyond_i64() {
+        let query = ListingQuery {
+            min_price: Some(u64::MAX),
+            max_price: Some(u64::MAX),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            query.filter(),
+            Ok(doc! {
+                "is_listed": true,
+                "price": { "$gte": i64::MAX, "$lte": i64::MAX }
+            })
+        );
+    }
+
+    #[test]
+    fn continues_after_cursor_in_sort_order() {
+        let id = ObjectId::new();
+        let cursor = Cursor { key: 3, id }.encode();
+
+        let recent = ListingQuery {
+            cursor: Some(cursor.clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            recent.filter(),
+            Ok(doc! {
+                "is_listed": true,
+                "$or": [
+                    { "listed_at": { "$lt": 3_i64 } },
+                    { "listed_at": 3_i64, "_id": { "$lt": id } }
+                ]
+            })
+        );
+        assert_eq!(recent.sort(), doc! { "listed_at": -1, "_id": -1 });
+
+        let cheapest = ListingQuery {
+            cursor: Some(cursor),
+            sort: Some(SortOrder::PriceAsc),
+            ..Default::default()
+        };
+        assert_eq!(
+            cheapest.filter(),
+            Ok(doc! {
+                "is_listed": true,
+                "$or": [
+                    { "price": { "$gt": 3_i64 } },
+                    { "price": 3_i64, "_id": { "$gt": id } }
+                ]
+            })
+        );
+        assert_eq!(cheapest.sort(), doc! { "price": 1, "_id": 1 });
+    }
+
+    #[test]
+    fn filters_by_traits_and_pages_by_rarity() {
+        let id = ObjectId::new();
+        let query = ListingQuery {
+            sort: Some(SortOrder::Rarity),
+            traits: vec![
+                "Background:Blue".to_string(),
+                "Level:3".to_string(),
+                "Background:Red".to_string(),
+            ],
+            cursor: Some(Cursor { key: 7, id }.encode()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            query.filter(),
+            Ok(doc! {
+                "is_listed": true,
+                "$and": [
+                    { "metadata.attributes": { "$elemMatch": {
+                        "trait_type": "Background", "value": { "$in": ["Blue", "Red"] }
+                    } } },
+                    { "metadata.attributes": { "$elemMatch": {
+                        "trait_type": "Level", "value": { "$in": ["3", 3_i64] }
+                    } } }
+                ],
+                "rarity.rank": { "$exists": true },
+                "$or": [
+                    { "rarity.rank": { "$gt": 7 } },
+                    { "rarity.rank": 7, "_id": { "$gt": id } }
+                ]
+            })
+        );
+        assert_eq!(query.sort(), doc! { "rarity.rank": 1, "_id": 1 });
+        assert_eq!(
+            Cursor::after(&doc! { "_id": id, "rarity": { "rank": 7 } }, "rarity.rank"),
+            Some(Cursor { key: 7, id })
+        );
+
+        let invalid = ListingQuery {
+            traits: vec!["Background".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            invalid.filter(),
+            Err("Invalid trait filter, expected Type:Value")
+        );
+    }
+
+    #[test]
+    fn clamps_page_size() {
+        let query = |limit| ListingQuery {
+            limit,
+            ..Default::default()
+        };
+
+        assert_eq!(query(None).limit(), DEFAULT_PAGE_SIZE as i64);
+        assert_eq!(query(Some(0)).limit(), 1);
+        assert_eq!(query(Some(1_000)).limit(), MAX_PAGE_SIZE as i64);
+    }
+
+    #[test]
+    fn pages_activities_by_id() {
+        let id = ObjectId::new();
+        let query = ActivityQuery {
+            cursor: Some(id.to_hex()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            query.filter(doc! { "kind": "sale" }),
+            Ok(doc! { "kind": "sale", "_id": { "$lt": id } })
+        );
+        let invalid = ActivityQuery {
+            cursor: Some("not a cursor".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(invalid.filter(Document::new()), Err("Invalid cursor"));
+    }
+}
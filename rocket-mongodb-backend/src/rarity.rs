@@ -0,0 +1,386 @@
+//! Trait counts and rarity per collection. NFTs are grouped by their verified
+//! collection and scored by how uncommon each of their trait values is within
+//! it. Anyone can mint with any symbol, so NFTs without a verified collection
+//! aren't ranked. An NFT lacking a trait type the rest of its collection has
+//! counts as having the value `None` for it.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+
+use mongodb::bson::doc;
+use rocket::futures::TryStreamExt;
+use rocket::get;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket_db_pools::mongodb::{options::ReplaceOptions, Database};
+use rocket_db_pools::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::now;
+use crate::db::MainDatabase;
+use crate::metadata::Attribute;
+use crate::models::MintedNFTs;
+use crate::routes::error_response;
+
+const MISSING_VALUE: &str = "None";
+/// NFTs whose rarity is written by one update
+const RARITY_WRITE_CHUNK: usize = 1_000;
+
+/// Collections with a recompute in flight, and whether they changed since it
+/// started and need another run
+static IN_FLIGHT: Mutex<BTreeMap<String, bool>> = Mutex::new(BTreeMap::new());
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Rarity {
+    /// Sum over the collection's trait types of the collection size divided
+    /// by how many NFTs share this NFT's value
+    pub score: f64,
+    /// 1 for the rarest, NFTs with equal scores share a rank
+    pub rank: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TraitCount {
+    pub trait_type: String,
+    pub value: String,
+    pub count: u32,
+}
+
+/// Trait counts of a collection, stored in `CollectionTraits`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CollectionTraits {
+    #[serde(rename = "_id")]
+    pub key: String,
+    pub size: u32,
+    pub traits: Vec<TraitCount>,
+    /// Unix timestamp in seconds
+    pub updated_at: i64,
+}
+
+/// Verified collection address, `None` for NFTs without one.
+pub fn collection_key(nft: &MintedNFTs) -> Option<String> {
+    nft.collection.clone()
+}
+
+/// Trait type to value for one NFT, the first entry of a type wins.
+fn trait_values(attributes: &[Attribute]) -> BTreeMap<&str, String> {
+    let mut values = BTreeMap::new();
+    for attribute in attributes {
+        values
+            .entry(attribute.trait_type.as_str())
+            .or_insert_with(|| attribute.value_string());
+    }
+    values
+}
+
+/// How many NFTs have each trait value, sorted by trait type and value.
+pub fn trait_counts(nfts: &[Vec<Attribute>]) -> Vec<TraitCount> {
+    let mut counts: BTreeMap<(String, String), u32> = BTreeMap::new();
+    for attributes in nfts {
+        for (trait_type, value) in trait_values(attributes) {
+            *counts.entry((trait_type.to_string(), value)).or_default() += 1;
+        }
+    }
+
+    counts
+        .into_iter()
+        .map(|((trait_type, value), count)| TraitCount {
+            trait_type,
+            value,
+            count,
+        })
+        .collect()
+}
+
+/// Rarity of each NFT in `nfts`, in the same order. `None` for all of them
+/// when the collection has no traits to compare.
+pub fn rarity_scores(nfts: &[Vec<Attribute>]) -> Vec<Option<Rarity>> {
+    let values: Vec<_> = nfts
+        .iter()
+        .map(|attributes| trait_values(attributes))
+        .collect();
+    let trait_types: BTreeSet<&str> = values
+        .iter()
+        .flat_map(|values| values.keys().copied())
+        .collect();
+    if trait_types.is_empty() {
+        return vec![None; nfts.len()];
+    }
+
+    let mut counts: BTreeMap<(&str, &str), u32> = BTreeMap::new();
+    for values in &values {
+        for &trait_type in &trait_types {
+            let value = values.get(trait_type).map_or(MISSING_VALUE, String::as_str);
+            *counts.entry((trait_type, value)).or_default() += 1;
+        }
+    }
+
+    let size = nfts.len() as f64;
+    let scores: Vec<f64> = values
+        .iter()
+        .map(|values| {
+            trait_types
+                .iter()
+                .map(|&trait_type| {
+                    let value = values.get(trait_type).map_or(MISSING_VALUE, String::as_str);
+                    size / counts[&(trait_type, value)] as f64
+                })
+                .sum()
+        })
+        .collect();
+
+    // Rarest first. Each NFT's rank is one more than the number of NFTs
+    // scoring strictly higher.
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+
+    let mut rarity = vec![None; scores.len()];
+    let mut rank = 1;
+    for (position, &index) in order.iter().enumerate() {
+        if position > 0 && scores[index] != scores[order[position - 1]] {
+            rank = position as u32 + 1;
+        }
+        rarity[index] = Some(Rarity {
+            score: scores[index],
+            rank,
+        });
+    }
+    rarity
+}
+
+/// Recomputes the trait counts of verified collection `key` and the rarity
+/// of each of its NFTs.
+pub async fn recompute(database: &Database, key: &str) -> Result<(), String> {
+    let nfts = database.collection::<MintedNFTs>("MintedNFTs");
+    let members: Vec<MintedNFTs> = nfts
+        .find(doc! { "collection": key }, None)
+        .await
+        .map_err(|e| e.to_string())?
+        .try_collect()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let attributes: Vec<Vec<Attribute>> = members
+        .iter()
+        .map(|nft| {
+            nft.metadata
+                .as_ref()
+                .map(|metadata| metadata.attributes.clone())
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let rarity = rarity_scores(&attributes);
+    for (members, rarity) in members
+        .chunks(RARITY_WRITE_CHUNK)
+        .zip(rarity.chunks(RARITY_WRITE_CHUNK))
+    {
+        let mints: Vec<&str> = members
+            .iter()
+            .map(|nft| nft.mint_address.as_str())
+            .collect();
+        let rarity = mongodb::bson::to_bson(rarity).map_err(|e| e.to_string())?;
+        // One pipeline update per chunk, each NFT picks its entry by the
+        // position of its mint
+        nfts.update_many(
+            doc! { "mint_address": { "$in": &mints } },
+            vec![doc! {
+                "$set": {
+                    "rarity": {
+                        "$arrayElemAt": [
+                            { "$literal": rarity },
+                            { "$indexOfArray": [{ "$literal": &mints }, "$mint_address"] },
+                        ]
+                    }
+                }
+            }],
+            None,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+    }
+
+    let traits = CollectionTraits {
+        key: key.to_string(),
+        size: members.len() as u32,
+        traits: trait_counts(&attributes),
+        updated_at: now(),
+    };
+    database
+        .collection::<CollectionTraits>("CollectionTraits")
+        .replace_one(
+            doc! { "_id": key },
+            &traits,
+            ReplaceOptions::builder().upsert(true).build(),
+        )
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Recomputes the collection of `nft` in the background, logging failures.
+/// Rarity is derived data, the write that triggered it shouldn't wait on it
+/// or fail with it. Changes while the collection is being recomputed get one
+/// more run once it finishes.
+pub fn refresh_collection_of(database: &Database, nft: &MintedNFTs) {
+    let Some(key) = collection_key(nft) else {
+        return;
+    };
+    {
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        if let Some(dirty) = in_flight.get_mut(&key) {
+            *dirty = true;
+            return;
+        }
+        in_flight.insert(key.clone(), false);
+    }
+
+    // One run per collection at a time, so an older run can't overwrite the
+    // ranks of a newer one
+    let database = database.clone();
+    rocket::tokio::spawn(async move {
+        loop {
+            if let Err(e) = recompute(&database, &key).await {
+                println!("Error computing rarity of {}: {}", key, e);
+            }
+            if !finish_run(&key) {
+                break;
+            }
+        }
+    });
+}
+
+/// Ends the in-flight run of `key`, true when it changed during the run and
+/// needs another one.
+fn finish_run(key: &str) -> bool {
+    let mut in_flight = IN_FLIGHT.lock().unwrap();
+    match in_flight.get_mut(key) {
+        Some(dirty) if *dirty => {
+            *dirty = false;
+            true
+        }
+        _ => {
+            in_flight.remove(key);
+            false
+        }
+    }
+}
+
+#[get("/collections/<key>/traits")]
+pub async fn collection_traits(
+    key: String,
+    db: Connection<MainDatabase>,
+) -> Result<Json<CollectionTraits>, status::Custom<Json<serde_json::Value>>> {
+    let traits = db
+        .database("nft_marketplace")
+        .collection::<CollectionTraits>("CollectionTraits");
+
+    match traits.find_one(doc! { "_id": &key }, None).await {
+        Ok(Some(traits)) => Ok(Json(traits)),
+        Ok(None) => Err(error_response(Status::NotFound, "Collection not found")),
+        Err(e) => {
+            println!("Error finding traits of {}: {}", key, e);
+            Err(error_response(
+                Status::InternalServerError,
+                format!("Failed to find traits: {}", e),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attributes(traits: &[(&str, serde_json::Value)]) -> Vec<Attribute> {
+        traits
+            .iter()
+            .map(|(trait_type, value)| Attribute {
+                trait_type: trait_type.to_string(),
+                value: value.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counts_trait_values() {
+        let nfts = vec![
+            attributes(&[("Background", "Blue".into()), ("Level", 3.into())]),
+            attributes(&[("Background", "Blue".into()), ("Background", "Red".into())]),
+        ];
+
+        assert_eq!(
+            trait_counts(&nfts),
+            vec![
+                TraitCount {
+                    trait_type: "Background".to_string(),
+                    value: "Blue".to_string(),
+                    count: 2,
+                },
+                TraitCount {
+                    trait_type: "Level".to_string(),
+                    value: "3".to_string(),
+                    count: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn ranks_rarest_first() {
+        let nfts = vec![
+            attributes(&[("Background", "Blue".into())]),
+            attributes(&[("Background", "Blue".into())]),
+            attributes(&[("Background", "Gold".into()), ("Hat", "Crown".into())]),
+            attributes(&[("Background", "Blue".into())]),
+        ];
+
+        let rarity = rarity_scores(&nfts);
+
+        // Gold: 4/1 + 4/1 for the only hat. Blue: 4/3 + 4/3 for no hat.
+        assert_eq!(
+            rarity[2],
+            Some(Rarity {
+                score: 8.0,
+                rank: 1
+            })
+        );
+        for common in [0, 1, 3] {
+            let common = rarity[common].as_ref().unwrap();
+            assert_eq!(common.rank, 2);
+            assert!((common.score - 8.0 / 3.0).abs() < 1e-9);
+        }
+        assert_eq!(rarity_scores(&[Vec::new(), Vec::new()]), vec![None, None]);
+    }
+
+    #[test]
+    fn ranks_ties_after_rarer_nfts() {
+        let nfts = vec![
+            attributes(&[("Background", "Blue".into())]),
+            attributes(&[("Background", "Gold".into())]),
+            attributes(&[("Background", "Red".into())]),
+            attributes(&[("Background", "Blue".into())]),
+            attributes(&[("Background", "Blue".into())]),
+        ];
+
+        let ranks: Vec<u32> = rarity_scores(&nfts)
+            .into_iter()
+            .map(|rarity| rarity.unwrap().rank)
+            .collect();
+
+        // Gold and Red share first place, so Blue comes third
+        assert_eq!(ranks, vec![3, 1, 1, 3, 3]);
+    }
+
+    #[test]
+    fn reruns_collections_changed_during_a_run() {
+        let key = "reruns_collections_changed_during_a_run";
+        IN_FLIGHT.lock().unwrap().insert(key.to_string(), true);
+
+        assert!(finish_run(key));
+        assert_eq!(IN_FLIGHT.lock().unwrap().get(key), Some(&false));
+        assert!(!finish_run(key));
+        assert!(!IN_FLIGHT.lock().unwrap().contains_key(key));
+    }
+}
@@ -6,15 +6,32 @@ use rocket::serde::json::Json;
 use rocket::delete;
 use rocket::http::Status;
 use rocket::response::status;
+use rocket::State;
 
 // MongoDB imports
 use mongodb::bson::{ doc, Document };
 use rocket_db_pools::Connection;
+use rocket_db_pools::mongodb::Collection;
+use rocket_db_pools::mongodb::options::{ FindOptions, UpdateOptions };
+
+// Query imports
+use crate::query::{ Cursor, ListingQuery };
+
+// Auth imports
+use crate::auth::{ now, AuthenticatedWallet };
 
 // Model imports
-use crate::models::MintedNFTs;
+use crate::models::{ ListedNFT, MintedNFTs, NATIVE_MINT };
 use serde::{ Deserialize, Serialize };
 
+// Solana imports
+use crate::metadata;
+use crate::rarity;
+use crate::solana;
+use nft_marketplace_client::Listing;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
 // Import TryStreamExt to get try_collect()
 use rocket::futures::TryStreamExt;
 
@@ -23,60 +40,70 @@ struct NFTMetadata {
     mint_address: String,
     name: String,
     symbol: String,
-    price: f64,
+    /// Lamports, matching the on-chain listing
+    price: u64,
+    payment_mint: Option<String>,
     image_uri: String,
+    /// Ignored, the mint's on-chain `uri` is stored instead
     metadata_uri: String,
     owner: String,
 }
 
-#[get("/get_listed_nfts", format = "json")]
+#[get("/get_listed_nfts?<query..>", format = "json")]
 pub async fn get_listed_nfts(
+    query: ListingQuery,
     mut db: Connection<MainDatabase>
-) -> Result<Json<Vec<MintedNFTs>>, status::Custom<Json<serde_json::Value>>> {
+) -> Result<Json<serde_json::Value>, status::Custom<Json<serde_json::Value>>> {
     // Make sure to use the correct database name that matches your MongoDB instance
-    let collection = db.database("nft_marketplace").collection("MintedNFTs");
+    let collection = db.database("nft_marketplace").collection::<Document>("MintedNFTs");
 
-    let filter = doc! { "is_listed": true };
+    let filter = query.filter().map_err(|message| error_response(Status::BadRequest, message))?;
+    let limit = query.limit();
+    // One extra document tells whether there is a next page
+    let options = FindOptions::builder()
+        .sort(query.sort())
+        .limit(limit + 1)
+        .build();
 
     // Add error logging
-    println!("Attempting to find listed NFTs");
+    println!("Attempting to find listed NFTs: {:?}", query);
 
-    match collection.find(filter, None).await {
+    match collection.find(filter, options).await {
         Ok(cursor) => {
-            match cursor.try_collect().await {
-                Ok(nfts) => {
+            match cursor.try_collect::<Vec<Document>>().await {
+                Ok(mut documents) => {
+                    let next_cursor = if (documents.len() as i64) > limit {
+                        documents.truncate(limit as usize);
+                        let (field, _) = query.sort_field();
+                        documents.last().and_then(|document| Cursor::after(document, field)).map(|cursor| cursor.encode())
+                    } else {
+                        None
+                    };
+
+                    let nfts = documents
+                        .into_iter()
+                        .map(|document| mongodb::bson::from_document::<MintedNFTs>(document).map(ListedNFT::from))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|e| {
+                            println!("Error decoding NFTs: {}", e);
+                            error_response(Status::InternalServerError, format!("Failed to decode NFTs: {}", e))
+                        })?;
+
                     println!("Found NFTs successfully");
-                    Ok(Json(nfts))
+                    Ok(Json(serde_json::json!({
+                        "nfts": nfts,
+                        "next_cursor": next_cursor
+                    })))
                 }
                 Err(e) => {
                     println!("Error collecting NFTs: {}", e);
-                    Err(
-                        status::Custom(
-                            Status::InternalServerError,
-                            Json(
-                                serde_json::json!({
-                            "status": "error",
-                            "message": format!("Failed to collect NFTs: {}", e)
-                        })
-                            )
-                        )
-                    )
+                    Err(error_response(Status::InternalServerError, format!("Failed to collect NFTs: {}", e)))
                 }
             }
         }
         Err(e) => {
             println!("Error finding NFTs: {}", e);
-            Err(
-                status::Custom(
-                    Status::InternalServerError,
-                    Json(
-                        serde_json::json!({
-                    "status": "error",
-                    "message": format!("Failed to find NFTs: {}", e)
-                })
-                    )
-                )
-            )
+            Err(error_response(Status::InternalServerError, format!("Failed to find NFTs: {}", e)))
         }
     }
 }
@@ -84,8 +111,56 @@ pub async fn get_listed_nfts(
 #[post("/list_nft", format = "application/json", data = "<nft_data>")]
 pub async fn list_nft(
     nft_data: Json<NFTMetadata>,
+    wallet: AuthenticatedWallet,
+    rpc: &State<RpcClient>,
+    http: &State<reqwest::Client>,
     mut db: Connection<MainDatabase>
 ) -> status::Custom<Json<serde_json::Value>> {
+    if nft_data.owner != wallet.0 {
+        return error_response(Status::Forbidden, "Only the owner can list this NFT");
+    }
+
+    // Only advertise NFTs the program actually holds in escrow for this seller
+    let mint = match verify_listing(rpc, &nft_data).await {
+        Ok(mint) => mint,
+        Err(response) => return response,
+    };
+
+    // The collection filter relies on it, but a listing doesn't need one
+    let token_metadata = match solana::fetch_token_metadata(rpc, &mint).await {
+        Ok(token_metadata) => token_metadata,
+        Err(e) => {
+            println!("Error fetching metadata of {}: {}", mint, e);
+            None
+        }
+    };
+
+    // Only the uri stored on-chain is fetched, never the one the client
+    // reports. Deviations are flagged on the document rather than rejected, an
+    // unreachable JSON can be fetched again with /refresh_metadata.
+    let metadata_uri = token_metadata
+        .as_ref()
+        .map(|token_metadata| token_metadata.uri.clone())
+        .unwrap_or_default();
+    let offchain = match &token_metadata {
+        Some(token_metadata) if !metadata_uri.is_empty() => {
+            let fetched = metadata::fetch_metadata(
+                http,
+                &metadata_uri,
+                &nft_data.image_uri,
+                Some(token_metadata)
+            ).await;
+            match fetched {
+                Ok(offchain) => Some(offchain),
+                Err(e) => {
+                    println!("Error fetching off-chain metadata of {}: {}", mint, e);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
     // Make sure to use the correct database name that matches your MongoDB instance
     let collection = db.database("nft_marketplace").collection::<MintedNFTs>("MintedNFTs");
 
@@ -94,35 +169,55 @@ pub async fn list_nft(
         name: nft_data.name.clone(),
         symbol: nft_data.symbol.clone(),
         price: nft_data.price,
+        payment_mint: NATIVE_MINT.to_string(),
         image_uri: nft_data.image_uri.clone(),
-        metadata_uri: nft_data.metadata_uri.clone(),
+        metadata_uri,
         owner: nft_data.owner.clone(),
         is_listed: true,
+        listed_at: now(),
+        collection: token_metadata
+            .and_then(|token_metadata| token_metadata.collection)
+            .map(|collection| collection.to_string()),
+        metadata: offchain,
+        rarity: None,
     };
 
-    // Add error logging
-    println!("Attempting to insert NFT: {:?}", nft);
+    let mut set = match mongodb::bson::to_document(&nft) {
+        Ok(set) => set,
+        Err(e) => {
+            return error_response(Status::InternalServerError, format!("Failed to list NFT: {}", e));
+        }
+    };
+    // Relisting keeps the rank until the collection is recomputed below
+    set.remove("rarity");
 
-    match collection.insert_one(nft, None).await {
+    println!("Attempting to upsert NFT: {:?}", nft);
+
+    // Upsert so relisting, or the indexer getting there first, doesn't
+    // duplicate the document
+    let result = collection.update_one(
+        doc! { "mint_address": &nft.mint_address },
+        doc! { "$set": set },
+        UpdateOptions::builder().upsert(true).build()
+    ).await;
+
+    match result {
         Ok(result) => {
-            println!("Insert successful: {:?}", result);
-            if let Some(id) = result.inserted_id.as_object_id() {
-                return status::Custom(
-                    Status::Created,
-                    Json(
-                        serde_json::json!({
-                        "status": "success",
-                        "message": format!("NFT ({}) listed successfully", id.to_string())
-                    })
-                    )
-                );
-            } else {
-                println!("No object ID returned");
-            }
+            println!("Upsert successful: {:?}", result);
+            rarity::refresh_collection_of(&db.database("nft_marketplace"), &nft);
+            status::Custom(
+                Status::Created,
+                Json(
+                    serde_json::json!({
+                    "status": "success",
+                    "message": format!("NFT ({}) listed successfully", nft.mint_address)
+                })
+                )
+            )
         }
         Err(e) => {
-            println!("Insert error: {}", e);
-            return status::Custom(
+            println!("Upsert error: {}", e);
+            status::Custom(
                 Status::InternalServerError,
                 Json(
                     serde_json::json!({
@@ -130,39 +225,207 @@ pub async fn list_nft(
                     "message": format!("Failed to list NFT: {}", e)
                 })
                 )
-            );
+            )
+        }
+    }
+}
+
+/// Rejects `nft_data` unless the mint has an active on-chain `Listing` whose
+/// seller and price match the payload, returning the mint.
+async fn verify_listing(
+    rpc: &RpcClient,
+    nft_data: &NFTMetadata
+) -> Result<Pubkey, status::Custom<Json<serde_json::Value>>> {
+    let (Ok(mint), Ok(seller)) = (
+        nft_data.mint_address.parse::<Pubkey>(),
+        nft_data.owner.parse::<Pubkey>(),
+    ) else {
+        return Err(error_response(Status::BadRequest, "Invalid mint or owner address"));
+    };
+    if nft_data.payment_mint.as_deref().is_some_and(|payment_mint| payment_mint != NATIVE_MINT) {
+        return Err(error_response(Status::BadRequest, "Only SOL payments are supported"));
+    }
+
+    let listing = match solana::fetch_listing(rpc, &mint).await {
+        Ok(listing) => listing,
+        Err(e) => {
+            println!("Error verifying listing: {}", e);
+            return Err(error_response(Status::BadGateway, format!("Failed to verify listing: {}", e)));
         }
+    };
+
+    check_listing(listing.as_ref(), &seller, nft_data.price)
+        .map(|()| mint)
+        .map_err(|message| {
+            println!("Rejected listing of {}: {}", mint, message);
+            error_response(Status::UnprocessableEntity, message)
+        })
+}
+
+/// `price` is in lamports and must equal the listing's.
+fn check_listing(listing: Option<&Listing>, seller: &Pubkey, price: u64) -> Result<(), &'static str> {
+    let listing = listing.ok_or("NFT is not listed on-chain")?;
+
+    if !listing.is_active {
+        return Err("On-chain listing is not active");
+    }
+    if listing.seller != *seller {
+        return Err("Owner does not match the on-chain seller");
+    }
+    if listing.price != price {
+        return Err("Price does not match the on-chain listing");
     }
 
+    Ok(())
+}
+
+/// Fails unless `wallet` owns the `MintedNFTs` document for `mint_address`.
+async fn require_owner(
+    collection: &Collection<MintedNFTs>,
+    mint_address: &str,
+    wallet: &AuthenticatedWallet
+) -> Result<(), status::Custom<Json<serde_json::Value>>> {
+    match collection.find_one(doc! { "mint_address": mint_address }, None).await {
+        Ok(Some(nft)) if nft.owner == wallet.0 => Ok(()),
+        Ok(Some(_)) => Err(error_response(Status::Forbidden, "Only the owner can modify this NFT")),
+        Ok(None) => Err(error_response(Status::NotFound, "NFT not found")),
+        Err(e) => {
+            println!("Error looking up NFT owner: {}", e);
+            Err(error_response(Status::InternalServerError, format!("Failed to find NFT: {}", e)))
+        }
+    }
+}
+
+pub(crate) fn error_response(
+    status: Status,
+    message: impl Into<String>
+) -> status::Custom<Json<serde_json::Value>> {
     status::Custom(
-        Status::InternalServerError,
-        Json(
-            serde_json::json!({
+        status,
+        Json(serde_json::json!({
             "status": "error",
-            "message": "Unknown error occurred"
-        })
-        )
+            "message": message.into()
+        }))
     )
 }
 
+/// Fetches an NFT's off-chain JSON again from the `uri` in the mint's
+/// Metaplex metadata, which replaces the stored `metadata_uri`.
+#[post("/refresh_metadata/<mint_address>")]
+pub async fn refresh_metadata(
+    mint_address: String,
+    rpc: &State<RpcClient>,
+    http: &State<reqwest::Client>,
+    mut db: Connection<MainDatabase>
+) -> Result<Json<serde_json::Value>, status::Custom<Json<serde_json::Value>>> {
+    let Ok(mint) = mint_address.parse::<Pubkey>() else {
+        return Err(error_response(Status::BadRequest, "Invalid mint address"));
+    };
+
+    let collection = db.database("nft_marketplace").collection::<MintedNFTs>("MintedNFTs");
+    let filter = doc! { "mint_address": &mint_address };
+
+    let nft = match collection.find_one(filter.clone(), None).await {
+        Ok(Some(nft)) => nft,
+        Ok(None) => {
+            return Err(error_response(Status::NotFound, "NFT not found"));
+        }
+        Err(e) => {
+            println!("Error finding NFT: {}", e);
+            return Err(error_response(Status::InternalServerError, format!("Failed to find NFT: {}", e)));
+        }
+    };
+
+    let token_metadata = solana::fetch_token_metadata(rpc, &mint).await.map_err(|e| {
+        println!("Error fetching metadata of {}: {}", mint, e);
+        error_response(Status::BadGateway, e)
+    })?;
+    // Stored URIs may have come from a client, only fetch the on-chain one
+    let Some(metadata_uri) = token_metadata.as_ref().map(|token_metadata| token_metadata.uri.clone()) else {
+        return Err(error_response(Status::UnprocessableEntity, "Mint has no Metaplex metadata"));
+    };
+
+    let fetched = metadata::fetch_metadata(http, &metadata_uri, &nft.image_uri, token_metadata.as_ref())
+        .await
+        .map_err(|e| {
+            println!("Error refreshing metadata of {}: {}", mint, e);
+            error_response(Status::BadGateway, e)
+        })?;
+
+    let mut update_doc = doc! { "metadata_uri": &metadata_uri };
+    match mongodb::bson::to_bson(&fetched) {
+        Ok(metadata) => update_doc.insert("metadata", metadata),
+        Err(e) => {
+            return Err(error_response(Status::InternalServerError, format!("Failed to encode metadata: {}", e)));
+        }
+    };
+    // Documents the indexer created have no image until their JSON is read
+    if let (true, Some(image)) = (nft.image_uri.is_empty(), &fetched.image) {
+        update_doc.insert("image_uri", image);
+    }
+
+    match collection.update_one(filter, doc! { "$set": update_doc }, None).await {
+        Ok(_) => {
+            println!("Refreshed metadata of {}", mint);
+            // Attributes may have changed, and with them the collection's rarity
+            rarity::refresh_collection_of(&db.database("nft_marketplace"), &nft);
+            Ok(Json(serde_json::json!({
+                "status": "success",
+                "metadata": fetched
+            })))
+        }
+        Err(e) => {
+            println!("Error storing metadata: {}", e);
+            Err(error_response(Status::InternalServerError, format!("Failed to store metadata: {}", e)))
+        }
+    }
+}
+
+/// Mint to resync. Clients used to report `is_listed` and `new_owner` as
+/// well, those are ignored now.
 #[derive(Serialize, Deserialize)]
 pub struct UpdateListingStatus {
     pub mint_address: String,
-    pub is_listed: bool,
-    pub new_owner: Option<String>,
 }
 
+/// Reads the listing state and owner of a mint from chain into its document.
+/// Any signed-in wallet may call it, the buyer of a sale included, since
+/// nothing it writes comes from the caller.
 #[put("/update_nft_listing_status", format = "json", data = "<payload>")]
 pub async fn update_nft_listing_status(
     payload: Json<UpdateListingStatus>,
+    _wallet: AuthenticatedWallet,
+    rpc: &State<RpcClient>,
     mut db: Connection<MainDatabase>
 ) -> Result<Json<serde_json::Value>, status::Custom<Json<serde_json::Value>>> {
     let collection = db.database("nft_marketplace").collection::<MintedNFTs>("MintedNFTs");
 
-    let mut update_doc = doc! { "is_listed": payload.is_listed };
+    let Ok(mint) = payload.mint_address.parse::<Pubkey>() else {
+        return Err(error_response(Status::BadRequest, "Invalid mint address"));
+    };
 
-    if let Some(owner) = &payload.new_owner {
-        update_doc.insert("owner_address", owner); // <-- Update owner
+    let listing = solana::fetch_listing(rpc, &mint).await.map_err(|e| {
+        println!("Error fetching listing of {}: {}", mint, e);
+        error_response(Status::BadGateway, e)
+    })?;
+    // A sold or delisted NFT follows whoever holds the token now
+    let (owner, price) = match listing.filter(|listing| listing.is_active) {
+        Some(listing) => (Some(listing.seller), Some(listing.price)),
+        None => {
+            let holder = solana::fetch_nft_holder(rpc, &mint).await.map_err(|e| {
+                println!("Error fetching holder of {}: {}", mint, e);
+                error_response(Status::BadGateway, e)
+            })?;
+            (holder, None)
+        }
+    };
+
+    let mut update_doc = doc! { "is_listed": price.is_some() };
+    if let Some(owner) = owner {
+        update_doc.insert("owner", owner.to_string());
+    }
+    if let Some(price) = price {
+        update_doc.insert("price", price as i64);
     }
 
     let filter = doc! { "mint_address": &payload.mint_address };
@@ -170,15 +433,14 @@ pub async fn update_nft_listing_status(
 
     match collection.update_one(filter, update, None).await {
         Ok(result) => {
-            if result.modified_count == 0 {
-                return Err(status::Custom(Status::NotFound, Json(serde_json::json!({
-                    "status": "error",
-                    "message": "No NFT found or no change made"
-                }))));
+            if result.matched_count == 0 {
+                return Err(error_response(Status::NotFound, "NFT not found"));
             }
             Ok(Json(serde_json::json!({
                 "status": "success",
-                "message": "NFT listing status and owner updated"
+                "message": "NFT listing status and owner updated",
+                "is_listed": price.is_some(),
+                "owner": owner.map(|owner| owner.to_string())
             })))
         }
         Err(e) => {
@@ -194,10 +456,13 @@ pub async fn update_nft_listing_status(
 #[delete("/delete_nft/<mint_address>")]
 pub async fn delete_nft(
     mint_address: String,
+    wallet: AuthenticatedWallet,
     mut db: Connection<MainDatabase>,
 ) -> Result<status::NoContent, status::Custom<Json<serde_json::Value>>> {
     let collection = db.database("nft_marketplace").collection::<MintedNFTs>("MintedNFTs");
 
+    require_owner(&collection, &mint_address, &wallet).await?;
+
     let filter = doc! { "mint_address": &mint_address };
 
     match collection.delete_one(filter, None).await {
@@ -225,3 +490,42 @@ pub async fn delete_nft(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nft_marketplace::{ LISTING_RESERVED_BYTES, LISTING_VERSION };
+    use solana_sdk::native_token::LAMPORTS_PER_SOL;
+
+    use super::*;
+
+    fn listing(seller: Pubkey, price: u64, is_active: bool) -> Listing {
+        Listing {
+            seller,
+            mint: Pubkey::new_unique(),
+            price,
+            is_active,
+            bump: 255,
+            version: LISTING_VERSION,
+            reserved: [0; LISTING_RESERVED_BYTES],
+        }
+    }
+
+    #[test]
+    fn accepts_matching_listing() {
+        let seller = Pubkey::new_unique();
+        let active = listing(seller, 2_009_999_999, true);
+
+        assert_eq!(check_listing(Some(&active), &seller, 2_009_999_999), Ok(()));
+    }
+
+    #[test]
+    fn rejects_listing_that_does_not_match() {
+        let seller = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+
+        assert!(check_listing(None, &seller, LAMPORTS_PER_SOL).is_err());
+        assert!(check_listing(Some(&listing(seller, LAMPORTS_PER_SOL, false)), &seller, LAMPORTS_PER_SOL).is_err());
+        assert!(check_listing(Some(&listing(other, LAMPORTS_PER_SOL, true)), &seller, LAMPORTS_PER_SOL).is_err());
+        assert!(check_listing(Some(&listing(seller, LAMPORTS_PER_SOL + 1, true)), &seller, LAMPORTS_PER_SOL).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! Solana RPC access shared by the routes and the chain indexer. The endpoint
+//! is read from `SOLANA_RPC_URL`.
+
+use std::env;
+
+use anchor_lang::AccountDeserialize;
+use anchor_spl::token::TokenAccount;
+use mpl_token_metadata::accounts::Metadata;
+use nft_marketplace_client::{accounts::decode_listing, pda, Listing};
+use solana_account_decoder_client_types::UiAccountData;
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_request::TokenAccountsFilter};
+use solana_sdk::{
+    commitment_config::CommitmentConfig, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey,
+};
+
+// Same cluster as the [provider] section of Anchor.toml
+const DEFAULT_RPC_URL: &str = "https://api.devnet.solana.com";
+/// Most accounts `getMultipleAccounts` returns per request
+pub const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TokenMetadata {
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    /// Collection the mint is a verified member of
+    pub collection: Option<Pubkey>,
+}
+
+pub fn rpc_url() -> String {
+    env::var("SOLANA_RPC_URL").unwrap_or_else(|_| DEFAULT_RPC_URL.to_string())
+}
+
+pub fn rpc_client() -> RpcClient {
+    RpcClient::new_with_commitment(rpc_url(), CommitmentConfig::confirmed())
+}
+
+pub async fn fetch_listing(rpc: &RpcClient, mint: &Pubkey) -> Result<Option<Listing>, String> {
+    let account = rpc
+        .get_account_with_commitment(&pda::listing(mint).0, rpc.commitment())
+        .await
+        .map_err(|e| format!("Failed to fetch listing of {}: {}", mint, e))?
+        .value;
+
+    // Unmigrated legacy listings can't be decoded, treat them as absent
+    // rather than stalling the caller
+    Ok(
+        account.and_then(|account| match decode_listing(&account.data) {
+            Ok(listing) => Some(listing),
+            Err(e) => {
+                println!("Skipping undecodable listing of {}: {}", mint, e);
+                None
+            }
+        }),
+    )
+}
+
+pub async fn fetch_token_metadata(
+    rpc: &RpcClient,
+    mint: &Pubkey,
+) -> Result<Option<TokenMetadata>, String> {
+    let account = rpc
+        .get_account_with_commitment(&pda::metadata(mint), rpc.commitment())
+        .await
+        .map_err(|e| format!("Failed to fetch metadata of {}: {}", mint, e))?
+        .value;
+
+    Ok(account.and_then(|account| decode_token_metadata(&account.data)))
+}
+
+/// Token metadata of up to `MAX_MULTIPLE_ACCOUNTS` mints in one request, in
+/// the order of `mints`.
+pub async fn fetch_token_metadatas(
+    rpc: &RpcClient,
+    mints: &[Pubkey],
+) -> Result<Vec<Option<TokenMetadata>>, String> {
+    let addresses: Vec<Pubkey> = mints.iter().map(pda::metadata).collect();
+    let accounts = rpc
+        .get_multiple_accounts(&addresses)
+        .await
+        .map_err(|e| format!("Failed to fetch metadata of {} mints: {}", mints.len(), e))?;
+
+    Ok(accounts
+        .into_iter()
+        .map(|account| account.and_then(|account| decode_token_metadata(&account.data)))
+        .collect())
+}
+
+fn decode_token_metadata(data: &[u8]) -> Option<TokenMetadata> {
+    let metadata = Metadata::safe_deserialize(data).ok()?;
+    Some(TokenMetadata {
+        // On-chain strings are padded with NUL bytes
+        name: metadata.name.trim_end_matches('\0').to_string(),
+        symbol: metadata.symbol.trim_end_matches('\0').to_string(),
+        uri: metadata.uri.trim_end_matches('\0').to_string(),
+        collection: metadata
+            .collection
+            .filter(|collection| collection.verified)
+            .map(|collection| collection.key),
+    })
+}
+
+/// Mints of the NFTs `owner` holds in its own token accounts. Listed NFTs sit
+/// in the program's escrow instead.
+pub async fn fetch_wallet_mints(rpc: &RpcClient, owner: &Pubkey) -> Result<Vec<Pubkey>, String> {
+    let accounts = rpc
+        .get_token_accounts_by_owner(owner, TokenAccountsFilter::ProgramId(anchor_spl::token::ID))
+        .await
+        .map_err(|e| format!("Failed to fetch token accounts of {}: {}", owner, e))?;
+
+    Ok(accounts
+        .iter()
+        .filter_map(|keyed| nft_mint(&keyed.account.data))
+        .collect())
+}
+
+/// Wallet holding the single token of `mint`, `None` once it is burned.
+pub async fn fetch_nft_holder(rpc: &RpcClient, mint: &Pubkey) -> Result<Option<Pubkey>, String> {
+    let largest = rpc
+        .get_token_largest_accounts(mint)
+        .await
+        .map_err(|e| format!("Failed to fetch token accounts of {}: {}", mint, e))?;
+    let Some(holding) = largest.iter().find(|account| account.amount.amount == "1") else {
+        return Ok(None);
+    };
+    let address: Pubkey = holding
+        .address
+        .parse()
+        .map_err(|e| format!("Invalid token account {}: {}", holding.address, e))?;
+
+    let account = rpc
+        .get_account(&address)
+        .await
+        .map_err(|e| format!("Failed to fetch token account {}: {}", address, e))?;
+    TokenAccount::try_deserialize(&mut &account.data[..])
+        .map(|token_account| Some(token_account.owner))
+        .map_err(|e| format!("Invalid token account {}: {}", address, e))
+}
+
+/// Mint of a `jsonParsed` token account holding the single token of a
+/// zero-decimals mint.
+fn nft_mint(data: &UiAccountData) -> Option<Pubkey> {
+    let UiAccountData::Json(account) = data else {
+        return None;
+    };
+    let info = account.parsed.get("info")?;
+    let amount = info.get("tokenAmount")?;
+
+    if amount.get("amount")?.as_str()? != "1" || amount.get("decimals")?.as_u64()? != 0 {
+        return None;
+    }
+    info.get("mint")?.as_str()?.parse().ok()
+}
+
+pub fn lamports_to_sol(lamports: u64) -> f64 {
+    lamports as f64 / LAMPORTS_PER_SOL as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_account_decoder_client_types::ParsedAccount;
+
+    use super::*;
+
+    fn token_account(mint: &Pubkey, amount: &str, decimals: u8) -> UiAccountData {
+        UiAccountData::Json(ParsedAccount {
+            program: "spl-token".to_string(),
+            parsed: serde_json::json!({
+                "type": "account",
+                "info": {
+                    "mint": mint.to_string(),
+                    "owner": Pubkey::new_unique().to_string(),
+                    "tokenAmount": { "amount": amount, "decimals": decimals }
+                }
+            }),
+            space: 165,
+        })
+    }
+
+    #[test]
+    fn finds_nft_token_accounts() {
+        let mint = Pubkey::new_unique();
+
+        assert_eq!(nft_mint(&token_account(&mint, "1", 0)), Some(mint));
+        assert_eq!(nft_mint(&token_account(&mint, "0", 0)), None);
+        assert_eq!(nft_mint(&token_account(&mint, "1", 6)), None);
+    }
+}
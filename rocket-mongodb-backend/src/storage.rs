@@ -0,0 +1,140 @@
+//! Where `/upload` puts images and metadata JSON. With `PINATA_API_KEY` and
+//! `PINATA_SECRET_API_KEY` set files are pinned to IPFS through Pinata,
+//! otherwise they are written to `UPLOAD_DIR` and served from `/uploads`,
+//! which is enough for local development and the tests.
+
+use std::{env, path::PathBuf};
+
+use pinata_sdk::{PinByFile, PinataApi};
+use rocket::fairing::AdHoc;
+use rocket::fs::FileServer;
+use rocket::tokio::fs;
+
+const DEFAULT_PINATA_GATEWAY: &str = "https://gateway.pinata.cloud/ipfs/";
+const DEFAULT_UPLOAD_DIR: &str = "uploads";
+const DEFAULT_PUBLIC_URL: &str = "http://127.0.0.1:8000";
+
+#[rocket::async_trait]
+pub trait Storage: Send + Sync {
+    /// Stores `bytes` as `name` and returns the URI they can be fetched from.
+    async fn store(&self, name: &str, bytes: Vec<u8>) -> Result<String, String>;
+
+    /// Removes what `store` returned `uri` for.
+    async fn remove(&self, uri: &str) -> Result<(), String>;
+}
+
+pub struct PinataStorage {
+    api: PinataApi,
+    /// Prefix of the pinned content's CID
+    gateway: String,
+}
+
+impl PinataStorage {
+    pub fn new(api_key: String, secret_api_key: String, gateway: String) -> Result<Self, String> {
+        let api = PinataApi::new(api_key, secret_api_key)
+            .map_err(|e| format!("Invalid Pinata credentials: {}", e))?;
+        Ok(Self { api, gateway })
+    }
+}
+
+#[rocket::async_trait]
+impl Storage for PinataStorage {
+    async fn store(&self, name: &str, bytes: Vec<u8>) -> Result<String, String> {
+        // The SDK pins files from disk
+        let path = env::temp_dir().join(name);
+        fs::write(&path, bytes)
+            .await
+            .map_err(|e| format!("Failed to stage {}: {}", name, e))?;
+        let pinned = self
+            .api
+            .pin_file(PinByFile::new(path.to_string_lossy()))
+            .await;
+        if let Err(e) = fs::remove_file(&path).await {
+            println!("Error removing staged upload {}: {}", path.display(), e);
+        }
+
+        let pinned = pinned.map_err(|e| format!("Failed to pin {}: {}", name, e))?;
+        Ok(format!("{}{}", self.gateway, pinned.ipfs_hash))
+    }
+
+    async fn remove(&self, uri: &str) -> Result<(), String> {
+        let hash = uri
+            .strip_prefix(&self.gateway)
+            .ok_or_else(|| format!("{} was not pinned through {}", uri, self.gateway))?;
+        self.api
+            .unpin(hash)
+            .await
+            .map_err(|e| format!("Failed to unpin {}: {}", hash, e))
+    }
+}
+
+pub struct LocalStorage {
+    dir: PathBuf,
+    /// URL `dir` is served from
+    base_url: String,
+}
+
+impl LocalStorage {
+    pub fn new(dir: impl Into<PathBuf>, base_url: impl Into<String>) -> Self {
+        Self {
+            dir: dir.into(),
+            base_url: base_url.into(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Storage for LocalStorage {
+    async fn store(&self, name: &str, bytes: Vec<u8>) -> Result<String, String> {
+        fs::create_dir_all(&self.dir)
+            .await
+            .and(fs::write(self.dir.join(name), bytes).await)
+            .map_err(|e| format!("Failed to store {}: {}", name, e))?;
+
+        Ok(format!("{}/{}", self.base_url.trim_end_matches('/'), name))
+    }
+
+    async fn remove(&self, uri: &str) -> Result<(), String> {
+        let name = uri
+            .strip_prefix(self.base_url.trim_end_matches('/'))
+            .and_then(|path| path.strip_prefix('/'))
+            .filter(|name| !name.contains('/'))
+            .ok_or_else(|| format!("{} is not an upload", uri))?;
+        fs::remove_file(self.dir.join(name))
+            .await
+            .map_err(|e| format!("Failed to remove {}: {}", name, e))
+    }
+}
+
+/// Manages the `Box<dyn Storage>` the upload route uses.
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Upload storage", |rocket| async move {
+        if let (Ok(api_key), Ok(secret_api_key)) = (
+            env::var("PINATA_API_KEY"),
+            env::var("PINATA_SECRET_API_KEY"),
+        ) {
+            let gateway =
+                env::var("PINATA_GATEWAY").unwrap_or_else(|_| DEFAULT_PINATA_GATEWAY.to_string());
+            return match PinataStorage::new(api_key, secret_api_key, gateway) {
+                Ok(storage) => Ok(rocket.manage(Box::new(storage) as Box<dyn Storage>)),
+                Err(e) => {
+                    println!("Upload storage not configured: {}", e);
+                    Err(rocket)
+                }
+            };
+        }
+
+        let dir = env::var("UPLOAD_DIR").unwrap_or_else(|_| DEFAULT_UPLOAD_DIR.to_string());
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            println!("Failed to create upload directory {}: {}", dir, e);
+            return Err(rocket);
+        }
+        let public_url = env::var("PUBLIC_URL").unwrap_or_else(|_| DEFAULT_PUBLIC_URL.to_string());
+
+        println!("Storing uploads in {}", dir);
+        let storage = LocalStorage::new(&dir, format!("{}/uploads", public_url));
+        Ok(rocket
+            .manage(Box::new(storage) as Box<dyn Storage>)
+            .mount("/uploads", FileServer::from(&dir)))
+    })
+}
@@ -0,0 +1,309 @@
+//! `/upload`: pins an NFT image and its Metaplex metadata JSON, returning the
+//! `uri` to pass to `mint_nft`. The backend counterpart of the frontend's
+//! `api/mint-nft` route, with attributes and royalties.
+
+use mpl_token_metadata::{MAX_NAME_LENGTH, MAX_SYMBOL_LENGTH, MAX_URI_LENGTH};
+use rocket::form::{Form, FromForm};
+use rocket::fs::TempFile;
+use rocket::http::Status;
+use rocket::post;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::tokio::io::AsyncReadExt;
+use rocket::State;
+use uuid::Uuid;
+
+use crate::auth::AuthenticatedWallet;
+use crate::metadata::{Attribute, MetadataFile, MetadataJson, Properties};
+use crate::routes::error_response;
+use crate::storage::Storage;
+
+/// Royalties are capped at 100%
+const MAX_SELLER_FEE_BASIS_POINTS: u16 = 10_000;
+
+#[derive(FromForm)]
+pub struct UploadForm<'r> {
+    pub file: TempFile<'r>,
+    pub name: String,
+    pub symbol: String,
+    pub description: String,
+    /// JSON array of `{ "trait_type": ..., "value": ... }`
+    pub attributes: Option<String>,
+    pub seller_fee_basis_points: Option<u16>,
+}
+
+/// An image and the fields of its metadata JSON.
+#[derive(Clone, Debug)]
+pub struct NftUpload {
+    pub name: String,
+    pub symbol: String,
+    pub description: String,
+    pub attributes: Vec<Attribute>,
+    pub seller_fee_basis_points: u16,
+    /// MIME type of `image`
+    pub content_type: String,
+    pub extension: Option<String>,
+    pub image: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UploadedNft {
+    pub image_uri: String,
+    pub metadata_uri: String,
+}
+
+impl NftUpload {
+    /// Checks the fields against what `mint_nft` and the standard accept.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() || self.name.len() > MAX_NAME_LENGTH {
+            return Err(format!("Name must be 1 to {} bytes", MAX_NAME_LENGTH));
+        }
+        if self.symbol.trim().is_empty() || self.symbol.len() > MAX_SYMBOL_LENGTH {
+            return Err(format!("Symbol must be 1 to {} bytes", MAX_SYMBOL_LENGTH));
+        }
+        if self.seller_fee_basis_points > MAX_SELLER_FEE_BASIS_POINTS {
+            return Err(format!(
+                "Seller fee must be at most {} basis points",
+                MAX_SELLER_FEE_BASIS_POINTS
+            ));
+        }
+        if self
+            .attributes
+            .iter()
+            .any(|attribute| attribute.trait_type.trim().is_empty())
+        {
+            return Err("Attributes need a trait_type".to_string());
+        }
+        if self
+            .attributes
+            .iter()
+            .any(|attribute| !(attribute.value.is_string() || attribute.value.is_number()))
+        {
+            return Err("Attribute values must be strings or numbers".to_string());
+        }
+        if !self.content_type.starts_with("image/") || self.image.is_empty() {
+            return Err("The file must be an image".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn metadata(&self, image_uri: &str) -> MetadataJson {
+        MetadataJson {
+            name: self.name.clone(),
+            symbol: self.symbol.clone(),
+            description: self.description.clone(),
+            image: image_uri.to_string(),
+            attributes: self.attributes.clone(),
+            properties: Properties {
+                files: vec![MetadataFile {
+                    uri: image_uri.to_string(),
+                    content_type: self.content_type.clone(),
+                }],
+                category: "image".to_string(),
+            },
+            seller_fee_basis_points: self.seller_fee_basis_points,
+        }
+    }
+}
+
+/// Stores the image, then the metadata JSON pointing at it. Whatever was
+/// stored is removed again when a later step fails.
+pub async fn pin_nft(storage: &dyn Storage, upload: &NftUpload) -> Result<UploadedNft, String> {
+    let id = Uuid::new_v4().simple();
+    let image_name = match &upload.extension {
+        Some(extension) => format!("{}.{}", id, extension),
+        None => id.to_string(),
+    };
+    let metadata_name = format!("{}-metadata.json", id);
+    let image_uri = storage.store(&image_name, upload.image.clone()).await?;
+
+    match pin_metadata(storage, upload, &metadata_name, &image_uri).await {
+        Ok(metadata_uri) => Ok(UploadedNft {
+            image_uri,
+            metadata_uri,
+        }),
+        Err(e) => {
+            discard(storage, &image_uri).await;
+            Err(e)
+        }
+    }
+}
+
+async fn pin_metadata(
+    storage: &dyn Storage,
+    upload: &NftUpload,
+    name: &str,
+    image_uri: &str,
+) -> Result<String, String> {
+    let metadata = serde_json::to_vec(&upload.metadata(image_uri))
+        .map_err(|e| format!("Failed to encode metadata: {}", e))?;
+    let metadata_uri = storage.store(name, metadata).await?;
+
+    // mint_nft stores the uri on-chain. Its length is only known once the
+    // storage has named the content.
+    if metadata_uri.len() > MAX_URI_LENGTH {
+        discard(storage, &metadata_uri).await;
+        return Err(format!(
+            "Metadata URI is longer than {} bytes",
+            MAX_URI_LENGTH
+        ));
+    }
+
+    Ok(metadata_uri)
+}
+
+async fn discard(storage: &dyn Storage, uri: &str) {
+    if let Err(e) = storage.remove(uri).await {
+        println!("Error removing upload {}: {}", uri, e);
+    }
+}
+
+#[post("/upload", data = "<form>")]
+pub async fn upload(
+    form: Form<UploadForm<'_>>,
+    wallet: AuthenticatedWallet,
+    storage: &State<Box<dyn Storage>>,
+) -> Result<status::Custom<Json<serde_json::Value>>, status::Custom<Json<serde_json::Value>>> {
+    let attributes = match &form.attributes {
+        Some(attributes) => serde_json::from_str(attributes).map_err(|e| {
+            error_response(Status::BadRequest, format!("Invalid attributes: {}", e))
+        })?,
+        None => Vec::new(),
+    };
+
+    let mut image = Vec::new();
+    let read = match form.file.open().await {
+        Ok(mut file) => file.read_to_end(&mut image).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = read {
+        println!("Error reading upload: {}", e);
+        return Err(error_response(
+            Status::InternalServerError,
+            format!("Failed to read file: {}", e),
+        ));
+    }
+
+    let content_type = form.file.content_type();
+    let upload = NftUpload {
+        name: form.name.clone(),
+        symbol: form.symbol.clone(),
+        description: form.description.clone(),
+        attributes,
+        seller_fee_basis_points: form.seller_fee_basis_points.unwrap_or_default(),
+        content_type: content_type
+            .map(|content_type| content_type.to_string())
+            .unwrap_or_default(),
+        extension: content_type
+            .and_then(|content_type| content_type.extension())
+            .map(|extension| extension.to_string()),
+        image,
+    };
+    upload
+        .validate()
+        .map_err(|message| error_response(Status::BadRequest, message))?;
+
+    println!("Uploading NFT {} for {}", upload.name, wallet.0);
+    match pin_nft(storage.as_ref(), &upload).await {
+        Ok(uploaded) => Ok(status::Custom(
+            Status::Created,
+            Json(serde_json::json!({
+                "status": "success",
+                "image_uri": uploaded.image_uri,
+                "metadata_uri": uploaded.metadata_uri
+            })),
+        )),
+        Err(e) => {
+            println!("Upload error: {}", e);
+            Err(error_response(
+                Status::BadGateway,
+                format!("Failed to upload NFT: {}", e),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metadata::MetadataJson;
+    use crate::storage::LocalStorage;
+
+    use super::*;
+
+    fn upload() -> NftUpload {
+        NftUpload {
+            name: "Test".to_string(),
+            symbol: "TNFT".to_string(),
+            description: "A test NFT".to_string(),
+            attributes: vec![Attribute {
+                trait_type: "Background".to_string(),
+                value: "Blue".into(),
+            }],
+            seller_fee_basis_points: 500,
+            content_type: "image/png".to_string(),
+            extension: Some("png".to_string()),
+            image: vec![0x89, b'P', b'N', b'G'],
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_uploads() {
+        assert_eq!(upload().validate(), Ok(()));
+
+        let long_name = NftUpload {
+            name: "x".repeat(MAX_NAME_LENGTH + 1),
+            ..upload()
+        };
+        assert!(long_name.validate().is_err());
+        let not_image = NftUpload {
+            content_type: "text/plain".to_string(),
+            ..upload()
+        };
+        assert!(not_image.validate().is_err());
+        let nested = NftUpload {
+            attributes: vec![Attribute {
+                trait_type: "Nested".to_string(),
+                value: serde_json::json!({ "a": 1 }),
+            }],
+            ..upload()
+        };
+        assert!(nested.validate().is_err());
+    }
+
+    #[rocket::async_test]
+    async fn pins_image_and_metadata() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let storage = LocalStorage::new(&dir, "http://localhost/uploads");
+        let upload = upload();
+
+        let uploaded = pin_nft(&storage, &upload).await.unwrap();
+
+        let file_name = |uri: &str| uri.rsplit('/').next().unwrap().to_string();
+        assert!(uploaded.image_uri.ends_with(".png"));
+        assert_eq!(
+            std::fs::read(dir.join(file_name(&uploaded.image_uri))).unwrap(),
+            upload.image
+        );
+        let metadata: MetadataJson = serde_json::from_slice(
+            &std::fs::read(dir.join(file_name(&uploaded.metadata_uri))).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(metadata, upload.metadata(&uploaded.image_uri));
+        assert_eq!(metadata.properties.files[0].content_type, "image/png");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn removes_uploads_when_the_uri_is_too_long() {
+        let dir = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let base_url = format!("http://localhost/{}", "x".repeat(MAX_URI_LENGTH));
+        let storage = LocalStorage::new(&dir, base_url);
+
+        assert!(pin_nft(&storage, &upload()).await.is_err());
+
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
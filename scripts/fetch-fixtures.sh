@@ -0,0 +1,10 @@
+#!/usr/bin/env bash
+# Dumps the mainnet programs the tests load into tests/fixtures. The dumps are
+# tracked by git (see .gitignore), so commit them after refreshing.
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+mkdir -p tests/fixtures
+
+# Metaplex Token Metadata
+solana program dump -u m metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s tests/fixtures/mpl_token_metadata.so
@@ -22,6 +22,41 @@ describe("nft_marketplace", () => {
   const program = anchor.workspace
     .NftMarketplace as Program<NftMarketplace>;
 
+  const FEE_BPS = 250;
+  const treasury = Keypair.generate();
+  const [marketplacePda] = PublicKey.findProgramAddressSync(
+    [Buffer.from("marketplace")],
+    program.programId
+  );
+  const [adminsPda] = PublicKey.findProgramAddressSync(
+    [Buffer.from("admins")],
+    program.programId
+  );
+  const [allowlistPda] = PublicKey.findProgramAddressSync(
+    [Buffer.from("collection_allowlist")],
+    program.programId
+  );
+  // `anchor test` deploys the program with the provider wallet as upgrade authority
+  const [programDataPda] = PublicKey.findProgramAddressSync(
+    [program.programId.toBuffer()],
+    new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+  );
+
+  before(async () => {
+    // Fund the treasury so small fees never fall below rent exemption
+    const sig = await provider.connection.requestAirdrop(treasury.publicKey, 1e9);
+    await provider.connection.confirmTransaction(sig);
+
+    await program.methods
+      .initializeMarketplace(FEE_BPS, 0)
+      .accounts({
+        authority: provider.wallet.publicKey,
+        programData: programDataPda,
+        treasury: treasury.publicKey,
+      } as any)
+      .rpc();
+  });
+
   it("Mints and burns an NFT", async () => {
     // Generate a new mint keypair
     const mint = Keypair.generate();
@@ -160,40 +195,85 @@ describe("nft_marketplace", () => {
     .signers([mint])
     .rpc();
 
-  // Buyer balance before
+  // Escrow authority and its token account
+  const [pda, bump] = PublicKey.findProgramAddressSync(
+    [Buffer.from("authority")],
+    program.programId
+  );
+  const escrowAta = await getAssociatedTokenAddress(mint.publicKey, pda, true);
+  const [listingPda] = PublicKey.findProgramAddressSync(
+    [Buffer.from("listing"), mint.publicKey.toBuffer()],
+    program.programId
+  );
+
+  const price = 1_000_000_000; // 1 SOL
+  const fee = (price * FEE_BPS) / 10_000;
+
+  // List the NFT
+  await program.methods
+    .listNft(new anchor.BN(price), bump)
+    .accounts({
+      seller: seller.publicKey,
+      mint: mint.publicKey,
+      pda,
+      sellerTokenAccount: sellerAta,
+      escrowTokenAccount: escrowAta,
+      listing: listingPda,
+      metadataAccount: metadataPda,
+      collectionStats: null,
+      collectionAllowlist: allowlistPda,
+      admins: adminsPda,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+      rent: SYSVAR_RENT_PUBKEY,
+    } as any)
+    .rpc();
+
+  // Balances before
   const buyerBefore = await provider.connection.getBalance(buyer.publicKey);
   const sellerBefore = await provider.connection.getBalance(seller.publicKey);
+  const treasuryBefore = await provider.connection.getBalance(treasury.publicKey);
 
   // Execute buy_nft
-  const price = 1_000_000_000; // 1 SOL
-
   await program.methods
-    .buyNft(new anchor.BN(price))
+    .buyNft(bump)
     .accounts({
       buyer: buyer.publicKey,
       seller: seller.publicKey,
+      proceedsVault: null,
+      marketplace: marketplacePda,
+      treasury: treasury.publicKey,
+      referrer: null,
       mint: mint.publicKey,
-      sellerTokenAccount: sellerAta,
+      pda,
+      escrowTokenAccount: escrowAta,
       buyerTokenAccount: buyerAta,
+      listing: listingPda,
+      metadataAccount: metadataPda,
+      collectionStats: null,
+      admins: adminsPda,
       tokenProgram: TOKEN_PROGRAM_ID,
       associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
       systemProgram: SystemProgram.programId,
       rent: SYSVAR_RENT_PUBKEY,
-    }as any)
+    } as any)
     .signers([buyer])
     .rpc();
 
   // Check balances
   const buyerAfter = await provider.connection.getBalance(buyer.publicKey);
   const sellerAfter = await provider.connection.getBalance(seller.publicKey);
+  const treasuryAfter = await provider.connection.getBalance(treasury.publicKey);
 
   const sellerBalanceDiff = sellerAfter - sellerBefore;
   const buyerBalanceDiff = buyerBefore - buyerAfter;
 
   assert(
-    sellerBalanceDiff >= price,
-    "Seller should receive payment (may be higher due to rent exemption reclaim)"
+    sellerBalanceDiff >= price - fee,
+    "Seller should receive payment minus the fee (may be higher due to rent exemption reclaim)"
   );
+  assert.equal(treasuryAfter - treasuryBefore, fee, "Treasury should receive the marketplace fee");
   assert(
     buyerBalanceDiff >= price,
     "Buyer should pay the price (may be higher due to tx fee)"